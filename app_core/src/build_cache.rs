@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+use crate::llm::Dependency;
+
+/// Touched every time a target directory is used; its mtime drives eviction.
+const LAST_USED_FILE: &str = ".last_used";
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct BuildCacheSettings {
+    /// Root of the cache: shared target directories and the sandbox's cargo home.
+    #[serde(default = "default_dir")]
+    pub dir: String,
+    /// How many dependency sets keep a target directory at once.
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+    /// Upper bound on the combined size of all target directories.
+    #[serde(default = "default_max_total_mb")]
+    pub max_total_mb: u64,
+}
+
+fn default_dir() -> String {
+    ".sandbox_cache".to_string()
+}
+
+fn default_max_entries() -> usize {
+    8
+}
+
+fn default_max_total_mb() -> u64 {
+    20 * 1024
+}
+
+impl Default for BuildCacheSettings {
+    fn default() -> Self {
+        Self {
+            dir: default_dir(),
+            max_entries: default_max_entries(),
+            max_total_mb: default_max_total_mb(),
+        }
+    }
+}
+
+/// Target directories shared between sandbox runs, one per dependency set, so
+/// crates like tokio or serde are compiled once instead of on every query.
+///
+/// Builds with the same dependency set are serialised by a per-key lock held for
+/// the whole build-and-run, since they also share the `sandbox` binary path.
+/// Builds with different dependency sets run in parallel.
+pub struct BuildCache {
+    root: PathBuf,
+    settings: BuildCacheSettings,
+    locks: Mutex<HashMap<String, Arc<AsyncMutex<()>>>>,
+}
+
+/// Exclusive use of one target directory until dropped.
+pub struct CacheLease {
+    pub target_dir: PathBuf,
+    _guard: OwnedMutexGuard<()>,
+}
+
+/// Hashes the dependency set, ignoring order, so equivalent manifests share a target directory.
+pub fn cache_key(dependencies: &[Dependency]) -> String {
+    let mut entries: Vec<String> = dependencies
+        .iter()
+        .map(|d| {
+            let mut features = d.features.clone();
+            features.sort();
+            features.dedup();
+            format!("{}[{}]", d.name, features.join(","))
+        })
+        .collect();
+    entries.sort();
+
+    let digest = Sha256::digest(entries.join(";").as_bytes());
+    digest[..8].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl BuildCache {
+    pub fn new(settings: BuildCacheSettings) -> Result<Self> {
+        // Absolute, because the docker executor bind-mounts these paths.
+        let root = std::path::absolute(&settings.dir)
+            .with_context(|| format!("Invalid build cache directory {}", settings.dir))?;
+        std::fs::create_dir_all(root.join("targets"))
+            .with_context(|| format!("Failed to create build cache at {}", root.display()))?;
+        std::fs::create_dir_all(root.join("cargo-home"))?;
+        Ok(Self {
+            root,
+            settings,
+            locks: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Cargo home for sandboxes that don't use the host's (the docker executor).
+    pub fn cargo_home(&self) -> PathBuf {
+        self.root.join("cargo-home")
+    }
+
+    fn target_dir(&self, key: &str) -> PathBuf {
+        self.root.join("targets").join(key)
+    }
+
+    fn lock_for(&self, key: &str) -> Arc<AsyncMutex<()>> {
+        self.locks
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Waits for exclusive use of the target directory for `key`, creating it if needed.
+    pub async fn checkout(&self, key: &str) -> Result<CacheLease> {
+        let guard = self.lock_for(key).lock_owned().await;
+        let target_dir = self.target_dir(key);
+        tokio::fs::create_dir_all(&target_dir)
+            .await
+            .with_context(|| format!("Failed to create target dir {}", target_dir.display()))?;
+        tokio::fs::write(target_dir.join(LAST_USED_FILE), b"").await?;
+        Ok(CacheLease {
+            target_dir,
+            _guard: guard,
+        })
+    }
+
+    /// Deletes least recently used target directories until the cache is within
+    /// `max_entries` and `max_total_mb`. Directories in use are never touched.
+    pub async fn evict(&self) -> Result<()> {
+        let mut entries = Vec::new();
+        let mut dir = tokio::fs::read_dir(self.root.join("targets")).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let path = entry.path();
+            let key = entry.file_name().to_string_lossy().into_owned();
+            let last_used = tokio::fs::metadata(path.join(LAST_USED_FILE))
+                .await
+                .and_then(|m| m.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            let size = tokio::task::spawn_blocking({
+                let path = path.clone();
+                move || dir_size(&path)
+            })
+            .await?;
+            entries.push((key, last_used, size));
+        }
+
+        let max_bytes = self.settings.max_total_mb * 1024 * 1024;
+        let mut total: u64 = entries.iter().map(|(_, _, size)| size).sum();
+        let mut count = entries.len();
+        entries.sort_by_key(|(_, last_used, _)| *last_used);
+
+        for (key, _, size) in entries {
+            if count <= self.settings.max_entries && total <= max_bytes {
+                break;
+            }
+            // Skip directories a build is using right now.
+            let Ok(_guard) = self.lock_for(&key).try_lock_owned() else {
+                continue;
+            };
+            let path = self.target_dir(&key);
+            println!("INFO: Evicting sandbox build cache {}", path.display());
+            tokio::fs::remove_dir_all(&path)
+                .await
+                .with_context(|| format!("Failed to evict {}", path.display()))?;
+            count -= 1;
+            total = total.saturating_sub(size);
+        }
+        Ok(())
+    }
+}
+
+fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return 0;
+    };
+    entries
+        .filter_map(|e| e.ok())
+        .map(|e| match e.file_type() {
+            Ok(t) if t.is_dir() => dir_size(&e.path()),
+            Ok(_) => e.metadata().map(|m| m.len()).unwrap_or(0),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn dep(name: &str, features: &[&str]) -> Dependency {
+        Dependency {
+            name: name.to_string(),
+            version: None,
+            features: features.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn keys_ignore_the_order_of_dependencies_and_features() {
+        let a = cache_key(&[dep("tokio", &["rt", "macros"]), dep("serde", &["derive"])]);
+        let b = cache_key(&[
+            dep("serde", &["derive"]),
+            dep("tokio", &["macros", "rt", "macros"]),
+        ]);
+        assert_eq!(a, b);
+        assert_eq!(a.len(), 16);
+        assert_ne!(
+            a,
+            cache_key(&[dep("tokio", &["rt"]), dep("serde", &["derive"])])
+        );
+        assert_ne!(cache_key(&[]), cache_key(&[dep("serde", &[])]));
+    }
+
+    fn cache(dir: &tempfile::TempDir, max_entries: usize, max_total_mb: u64) -> BuildCache {
+        BuildCache::new(BuildCacheSettings {
+            dir: dir.path().to_string_lossy().into_owned(),
+            max_entries,
+            max_total_mb,
+        })
+        .unwrap()
+    }
+
+    /// Checks `key` out and back in, marking it used `age` ago.
+    async fn use_key(cache: &BuildCache, key: &str, age: Duration) {
+        let lease = cache.checkout(key).await.unwrap();
+        std::fs::write(lease.target_dir.join("artifact"), vec![0u8; 1024]).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(lease.target_dir.join(LAST_USED_FILE))
+            .unwrap()
+            .set_modified(SystemTime::now() - age)
+            .unwrap();
+    }
+
+    fn cached_keys(cache: &BuildCache) -> Vec<String> {
+        let mut keys: Vec<String> = std::fs::read_dir(cache.root.join("targets"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    #[tokio::test]
+    async fn evicts_the_least_recently_used_beyond_max_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(&dir, 2, 1024);
+        use_key(&cache, "old", Duration::from_secs(300)).await;
+        use_key(&cache, "mid", Duration::from_secs(200)).await;
+        use_key(&cache, "new", Duration::from_secs(100)).await;
+
+        cache.evict().await.unwrap();
+        assert_eq!(cached_keys(&cache), ["mid", "new"]);
+    }
+
+    #[tokio::test]
+    async fn skips_directories_in_use() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(&dir, 2, 1024);
+        use_key(&cache, "old", Duration::from_secs(300)).await;
+        use_key(&cache, "mid", Duration::from_secs(200)).await;
+        use_key(&cache, "new", Duration::from_secs(100)).await;
+
+        let _building = cache.lock_for("old").try_lock_owned().unwrap();
+        cache.evict().await.unwrap();
+        assert_eq!(cached_keys(&cache), ["new", "old"]);
+    }
+
+    #[tokio::test]
+    async fn evicts_until_within_the_size_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        // No room at all: everything not in use goes.
+        let cache = cache(&dir, 10, 0);
+        use_key(&cache, "old", Duration::from_secs(300)).await;
+        use_key(&cache, "new", Duration::from_secs(100)).await;
+
+        let _building = cache.lock_for("new").try_lock_owned().unwrap();
+        cache.evict().await.unwrap();
+        assert_eq!(cached_keys(&cache), ["new"]);
+    }
+}
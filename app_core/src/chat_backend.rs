@@ -0,0 +1,147 @@
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use genai::{
+    Client,
+    chat::{ChatMessage, ChatRequest, ChatStreamEvent},
+};
+
+use crate::events::{QueryEvent, QueryEvents};
+
+/// A single system + user prompt pair, which is all the pipeline ever sends.
+#[derive(Debug, Clone)]
+pub struct ChatPrompt {
+    pub system: String,
+    pub user: String,
+}
+
+impl ChatPrompt {
+    pub fn new(system: impl Into<String>, user: impl Into<String>) -> Self {
+        Self {
+            system: system.into(),
+            user: user.into(),
+        }
+    }
+}
+
+/// Anything that can answer a chat prompt with text.
+#[async_trait]
+pub trait ChatBackend: Send + Sync {
+    /// Returns the text of the answer, or `None` if the model produced no text.
+    /// Implementations should forward partial output to `events` when it is active.
+    async fn chat(&self, prompt: &ChatPrompt, events: &QueryEvents) -> Result<Option<String>>;
+}
+
+/// The production backend, talking to a hosted model through `genai`.
+pub struct GenaiBackend {
+    client: Client,
+    model: String,
+}
+
+impl GenaiBackend {
+    pub fn new(client: Client, model: impl Into<String>) -> Self {
+        Self {
+            client,
+            model: model.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl ChatBackend for GenaiBackend {
+    async fn chat(&self, prompt: &ChatPrompt, events: &QueryEvents) -> Result<Option<String>> {
+        let request = ChatRequest::new(vec![
+            ChatMessage::system(&prompt.system),
+            ChatMessage::user(&prompt.user),
+        ]);
+
+        if !events.is_active() {
+            let response = self.client.exec_chat(&self.model, request, None).await?;
+            return Ok(response.content_text_as_str().map(String::from));
+        }
+
+        let mut stream = self
+            .client
+            .exec_chat_stream(&self.model, request, None)
+            .await?
+            .stream;
+
+        let mut text = String::new();
+        while let Some(event) = stream.next().await {
+            // Dropping the stream stops the generation.
+            events.ensure_connected()?;
+            if let ChatStreamEvent::Chunk(chunk) = event? {
+                events.emit(QueryEvent::LlmToken {
+                    text: chunk.content.clone(),
+                });
+                text.push_str(&chunk.content);
+            }
+        }
+
+        Ok((!text.is_empty()).then_some(text))
+    }
+}
+
+/// A deterministic backend that replays canned responses in order, for offline runs and tests.
+/// Every prompt it receives is recorded so callers can assert on what was asked.
+#[derive(Default)]
+pub struct ScriptedBackend {
+    responses: Mutex<VecDeque<String>>,
+    prompts: Mutex<Vec<ChatPrompt>>,
+}
+
+impl ScriptedBackend {
+    pub fn new(responses: Vec<String>) -> Self {
+        Self {
+            responses: Mutex::new(responses.into()),
+            prompts: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Loads one response per file from `dir`, replayed in file name order
+    /// (e.g. `01_plan.json`, `02_generate.json`, `03_repair.json`).
+    pub fn from_dir(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let mut paths = std::fs::read_dir(dir)
+            .with_context(|| format!("Failed to read fixture directory {}", dir.display()))?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        paths.retain(|p| p.is_file());
+        paths.sort();
+
+        let responses = paths
+            .iter()
+            .map(|p| {
+                std::fs::read_to_string(p)
+                    .with_context(|| format!("Failed to read fixture {}", p.display()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self::new(responses))
+    }
+
+    /// The prompts received so far, in order.
+    pub fn recorded_prompts(&self) -> Vec<ChatPrompt> {
+        self.prompts.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl ChatBackend for ScriptedBackend {
+    async fn chat(&self, prompt: &ChatPrompt, events: &QueryEvents) -> Result<Option<String>> {
+        self.prompts.lock().unwrap().push(prompt.clone());
+        let response = self
+            .responses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or_else(|| anyhow!("Scripted chat backend has no responses left"))?;
+        events.emit(QueryEvent::LlmToken {
+            text: response.clone(),
+        });
+        Ok(Some(response))
+    }
+}
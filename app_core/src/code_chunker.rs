@@ -0,0 +1,360 @@
+//! Splits Rust source along item boundaries, so a retrieved chunk is a whole
+//! function, type or trait with its doc comments rather than an arbitrary
+//! 1000-character window.
+
+use anyhow::{Context, Result};
+use proc_macro2::{LineColumn, Span};
+use syn::{ImplItem, Item, TraitItem, Visibility, spanned::Spanned};
+
+use crate::ingestion::split_into_chunks;
+
+/// Items longer than this are broken up: impls and traits into their members,
+/// inline modules into their items, and anything else by plain text splitting.
+const MAX_ITEM_CHARS: usize = 2000;
+
+/// One item of a Rust source file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RustChunk {
+    pub text: String,
+    /// Path of the item within the crate, e.g. `net::TcpStream::connect`.
+    pub item_path: String,
+    /// `fn`, `struct`, `enum`, `trait`, `impl`, `method`, `mod`, ...
+    pub item_kind: String,
+}
+
+/// Parses `source` and returns one chunk per top-level item. `module_path` is
+/// the path of the file's module within its crate, empty for the crate root.
+pub fn chunk_rust_source(source: &str, module_path: &str) -> Result<Vec<RustChunk>> {
+    chunk_file(source, module_path, false)
+}
+
+/// Like `chunk_rust_source`, but keeps only the crate's public API: `pub`
+/// items, exported macros, impls, and the `pub` methods of inherent impls.
+pub fn chunk_public_items(source: &str, module_path: &str) -> Result<Vec<RustChunk>> {
+    chunk_file(source, module_path, true)
+}
+
+fn chunk_file(source: &str, module_path: &str, public_only: bool) -> Result<Vec<RustChunk>> {
+    let file = syn::parse_file(source).context("Failed to parse Rust source")?;
+    let text = SourceText::new(source);
+    let mut chunks = Vec::new();
+
+    let module_docs = doc_text(&file.attrs);
+    if !module_docs.is_empty() {
+        chunks.push(RustChunk {
+            text: module_docs,
+            item_path: module_path.to_string(),
+            item_kind: "mod".to_string(),
+        });
+    }
+    for item in &file.items {
+        chunk_item(&text, item, module_path, public_only, &mut chunks);
+    }
+    Ok(chunks)
+}
+
+/// Module path for a file inside a crate's `src` directory: `src/net/tcp.rs`
+/// becomes `net::tcp`, and `lib.rs`, `main.rs` and `mod.rs` name their directory.
+pub fn module_path_for_file(relative_path: &str) -> String {
+    let trimmed = relative_path
+        .trim_start_matches("./")
+        .trim_start_matches("src/")
+        .trim_end_matches(".rs");
+    let mut segments: Vec<&str> = trimmed.split('/').filter(|s| !s.is_empty()).collect();
+    if matches!(segments.last(), Some(&"lib" | &"main" | &"mod")) {
+        segments.pop();
+    }
+    segments.join("::")
+}
+
+pub(crate) fn join_path(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}::{}", prefix, name)
+    }
+}
+
+fn is_public(item: &Item) -> bool {
+    let vis = match item {
+        Item::Fn(f) => &f.vis,
+        Item::Struct(s) => &s.vis,
+        Item::Enum(e) => &e.vis,
+        Item::Union(u) => &u.vis,
+        Item::Trait(t) => &t.vis,
+        Item::Type(t) => &t.vis,
+        Item::Const(c) => &c.vis,
+        Item::Static(s) => &s.vis,
+        // Only inline modules; `mod foo;` is chunked when its file is.
+        Item::Mod(m) if m.content.is_some() => &m.vis,
+        Item::Macro(m) => {
+            return m.attrs.iter().any(|a| a.path().is_ident("macro_export"));
+        }
+        Item::Impl(_) => return true,
+        _ => return false,
+    };
+    matches!(vis, Visibility::Public(_))
+}
+
+fn chunk_item(
+    text: &SourceText,
+    item: &Item,
+    prefix: &str,
+    public_only: bool,
+    chunks: &mut Vec<RustChunk>,
+) {
+    if public_only && !is_public(item) {
+        return;
+    }
+    let (name, kind) = match item {
+        Item::Fn(f) => (f.sig.ident.to_string(), "fn"),
+        Item::Struct(s) => (s.ident.to_string(), "struct"),
+        Item::Enum(e) => (e.ident.to_string(), "enum"),
+        Item::Union(u) => (u.ident.to_string(), "union"),
+        Item::Trait(t) => (t.ident.to_string(), "trait"),
+        Item::Type(t) => (t.ident.to_string(), "type"),
+        Item::Const(c) => (c.ident.to_string(), "const"),
+        Item::Static(s) => (s.ident.to_string(), "static"),
+        Item::Mod(m) => (m.ident.to_string(), "mod"),
+        Item::Macro(m) => match &m.ident {
+            Some(ident) => (ident.to_string(), "macro"),
+            None => return,
+        },
+        Item::Impl(i) => (text.slice(i.self_ty.span()), "impl"),
+        // `use`, `extern crate` and the like carry nothing worth retrieving on their own.
+        _ => return,
+    };
+    let path = join_path(prefix, &name);
+    let item_text = text.slice(item.span());
+
+    if item_text.chars().count() <= MAX_ITEM_CHARS {
+        push_chunk(chunks, item_text, &path, kind);
+        return;
+    }
+
+    match item {
+        Item::Impl(i) => {
+            let header = text.slice_until_brace(i.span());
+            let inherent = i.trait_.is_none();
+            for member in &i.items {
+                let (member_name, member_kind) = match member {
+                    ImplItem::Fn(f)
+                        if public_only && inherent && !matches!(f.vis, Visibility::Public(_)) =>
+                    {
+                        continue;
+                    }
+                    ImplItem::Fn(f) => (f.sig.ident.to_string(), "method"),
+                    ImplItem::Const(c) => (c.ident.to_string(), "const"),
+                    ImplItem::Type(t) => (t.ident.to_string(), "type"),
+                    _ => continue,
+                };
+                let body = format!("// in {}\n{}", header, text.slice(member.span()));
+                push_chunk(chunks, body, &join_path(&path, &member_name), member_kind);
+            }
+        }
+        Item::Trait(t) => {
+            let header = text.slice_until_brace(t.span());
+            for member in &t.items {
+                let (member_name, member_kind) = match member {
+                    TraitItem::Fn(f) => (f.sig.ident.to_string(), "method"),
+                    TraitItem::Const(c) => (c.ident.to_string(), "const"),
+                    TraitItem::Type(t) => (t.ident.to_string(), "type"),
+                    _ => continue,
+                };
+                let body = format!("// in {}\n{}", header, text.slice(member.span()));
+                push_chunk(chunks, body, &join_path(&path, &member_name), member_kind);
+            }
+        }
+        Item::Mod(m) if m.content.is_some() => {
+            let docs = doc_text(&m.attrs);
+            if !docs.is_empty() {
+                push_chunk(chunks, docs, &path, kind);
+            }
+            for inner in m.content.iter().flat_map(|(_, items)| items) {
+                chunk_item(text, inner, &path, public_only, chunks);
+            }
+        }
+        _ => push_chunk(chunks, item_text, &path, kind),
+    }
+}
+
+/// Adds a chunk, falling back to plain text splitting when a single item is still too long.
+fn push_chunk(chunks: &mut Vec<RustChunk>, text: String, path: &str, kind: &str) {
+    let pieces = if text.chars().count() <= MAX_ITEM_CHARS {
+        vec![text]
+    } else {
+        split_into_chunks(&text).unwrap_or_else(|_| vec![text])
+    };
+    chunks.extend(pieces.into_iter().map(|text| RustChunk {
+        text,
+        item_path: path.to_string(),
+        item_kind: kind.to_string(),
+    }));
+}
+
+/// The text of `#[doc]` attributes (`///` and `//!` comments), one line each.
+pub(crate) fn doc_text(attrs: &[syn::Attribute]) -> String {
+    attrs
+        .iter()
+        .filter(|a| a.path().is_ident("doc"))
+        .filter_map(|a| match &a.meta {
+            syn::Meta::NameValue(nv) => match &nv.value {
+                syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Str(s),
+                    ..
+                }) => Some(s.value()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .map(|line| line.strip_prefix(' ').unwrap_or(&line).to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Source text indexed by line, for turning spans back into the original text.
+pub(crate) struct SourceText<'a> {
+    source: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> SourceText<'a> {
+    pub(crate) fn new(source: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self {
+            source,
+            line_starts,
+        }
+    }
+
+    /// Byte offset of a span position; lines are 1-based, columns count chars.
+    fn offset(&self, at: LineColumn) -> usize {
+        let Some(&start) = self.line_starts.get(at.line.saturating_sub(1)) else {
+            return self.source.len();
+        };
+        self.source[start..]
+            .char_indices()
+            .nth(at.column)
+            .map(|(i, _)| start + i)
+            .unwrap_or(self.source.len())
+    }
+
+    pub(crate) fn slice(&self, span: Span) -> String {
+        self.between(span.start(), span.end())
+    }
+
+    /// The text from `start` up to, not including, `end`.
+    pub(crate) fn between(&self, start: LineColumn, end: LineColumn) -> String {
+        let start = self.offset(start);
+        let end = self.offset(end).max(start);
+        self.source[start..end].to_string()
+    }
+
+    /// The text of a braced item up to its opening brace, e.g. `impl Display for Foo`.
+    /// Leading doc comments and attributes are dropped.
+    fn slice_until_brace(&self, span: Span) -> String {
+        let text = self.slice(span);
+        let header = text.split('{').next().unwrap_or_default();
+        header
+            .lines()
+            .filter(|l| {
+                let l = l.trim_start();
+                !l.starts_with("///") && !l.starts_with("#[")
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+            .trim()
+            .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn items_keep_their_doc_comments() {
+        let source = r#"
+//! Networking helpers.
+
+use std::io;
+
+/// Connects to `addr`.
+pub fn connect(addr: &str) -> io::Result<()> {
+    Ok(())
+}
+
+pub struct Client;
+
+impl Client {
+    pub fn new() -> Self { Client }
+}
+"#;
+        let chunks = chunk_rust_source(source, "net").unwrap();
+        let kinds: Vec<(&str, &str)> = chunks
+            .iter()
+            .map(|c| (c.item_path.as_str(), c.item_kind.as_str()))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                ("net", "mod"),
+                ("net::connect", "fn"),
+                ("net::Client", "struct"),
+                ("net::Client", "impl"),
+            ]
+        );
+        assert!(chunks[1].text.starts_with("/// Connects to `addr`."));
+        assert!(chunks[1].text.ends_with('}'));
+    }
+
+    #[test]
+    fn large_impls_are_split_into_methods() {
+        let methods: String = (0..40)
+            .map(|i| format!("    /// Method number {i}.\n    pub fn m{i}(&self) -> usize {{ {i} }}\n"))
+            .collect();
+        let source = format!("struct Big;\n\nimpl std::fmt::Debug for Big {{}}\n\nimpl Big {{\n{methods}}}\n");
+
+        let chunks = chunk_rust_source(&source, "").unwrap();
+        let methods: Vec<&RustChunk> = chunks.iter().filter(|c| c.item_kind == "method").collect();
+
+        assert_eq!(methods.len(), 40);
+        assert_eq!(methods[3].item_path, "Big::m3");
+        assert!(methods[3].text.starts_with("// in impl Big\n/// Method number 3."));
+    }
+
+    #[test]
+    fn inline_modules_are_flattened_when_large() {
+        let fns: String = (0..150).map(|i| format!("pub fn f{i}() {{}}\n")).collect();
+        let source = format!("/// Outer.\nmod outer {{\n{fns}}}\n");
+
+        let chunks = chunk_rust_source(&source, "").unwrap();
+        assert_eq!(chunks[0].item_path, "outer");
+        assert_eq!(chunks[0].text, "Outer.");
+        assert_eq!(chunks[1].item_path, "outer::f0");
+    }
+
+    #[test]
+    fn public_mode_drops_private_items() {
+        let source = r#"
+fn helper() {}
+pub fn api() {}
+pub(crate) struct Internal;
+#[macro_export]
+macro_rules! exported { () => {} }
+macro_rules! local { () => {} }
+"#;
+        let chunks = chunk_public_items(source, "krate").unwrap();
+        let paths: Vec<&str> = chunks.iter().map(|c| c.item_path.as_str()).collect();
+        assert_eq!(paths, ["krate::api", "krate::exported"]);
+    }
+
+    #[test]
+    fn module_paths_follow_file_layout() {
+        assert_eq!(module_path_for_file("src/lib.rs"), "");
+        assert_eq!(module_path_for_file("src/net/tcp.rs"), "net::tcp");
+        assert_eq!(module_path_for_file("src/net/mod.rs"), "net");
+    }
+}
@@ -0,0 +1,432 @@
+//! Turns an HTML page into Markdown, keeping only its main content: the region
+//! that holds the page's prose and code, without navigation, sidebars, footers or
+//! scripts. Headings and lists become Markdown, and `<pre>` blocks become fenced
+//! code blocks with their language, so the result suits `markdown_chunker`.
+
+use std::collections::HashMap;
+
+use scraper::{ElementRef, Html, Node, Selector};
+
+/// Elements that mark the main content region, when a page has them.
+const MAIN_CONTENT_SELECTOR: &str = "main, article, [role=main], #main-content, #content";
+
+/// Main content regions with less text than this are ignored in favour of scoring.
+const MIN_MAIN_CONTENT_CHARS: usize = 200;
+
+/// Elements that are never content.
+const CHROME_TAGS: &[&str] = &[
+    "script", "style", "noscript", "template", "nav", "aside", "footer", "form", "button",
+    "iframe", "svg", "img", "select", "input", "head",
+];
+
+/// ARIA roles of page chrome.
+const CHROME_ROLES: &[&str] = &["navigation", "banner", "contentinfo", "search", "complementary"];
+
+/// The main content of a page as Markdown. Starts with the page's `<title>` as
+/// a heading when the content has no heading of its own before its first text.
+pub fn extract_markdown(document: &Html) -> String {
+    let root = main_content(document).or_else(|| {
+        let body = Selector::parse("body").unwrap();
+        document.select(&body).next()
+    });
+    let Some(root) = root else {
+        return String::new();
+    };
+    let markdown = render_blocks(root).join("\n\n");
+
+    match page_title(document) {
+        Some(title) if !markdown.starts_with('#') => {
+            format!("# {}\n\n{}", title, markdown).trim_end().to_string()
+        }
+        _ => markdown,
+    }
+}
+
+fn page_title(document: &Html) -> Option<String> {
+    let selector = Selector::parse("title").unwrap();
+    let title = document.select(&selector).next()?.text().collect::<String>();
+    let title = collapse_whitespace(&title);
+    (!title.is_empty()).then_some(title)
+}
+
+/// Picks the region holding the page's content: a `<main>`, `<article>` or
+/// similarly marked element with enough text, or else the element whose
+/// paragraphs and code blocks carry the most text.
+fn main_content(document: &Html) -> Option<ElementRef<'_>> {
+    let marked = Selector::parse(MAIN_CONTENT_SELECTOR).unwrap();
+    let best_marked = document
+        .select(&marked)
+        .filter(|e| !is_chrome(e))
+        .map(|e| (content_chars(e), e))
+        .max_by_key(|(chars, _)| *chars);
+    if let Some((_, element)) = best_marked.filter(|(chars, _)| *chars >= MIN_MAIN_CONTENT_CHARS) {
+        return Some(element);
+    }
+
+    // Readability-style scoring: each paragraph counts toward its parent and,
+    // at half weight, its grandparent. Link-heavy regions (menus) score low.
+    let paragraphs = Selector::parse("p, pre, li, td, blockquote").unwrap();
+    let mut scores: HashMap<_, (f64, ElementRef)> = HashMap::new();
+    for paragraph in document.select(&paragraphs) {
+        let chars = content_chars(paragraph) as f64;
+        if chars == 0.0 {
+            continue;
+        }
+        let parent = paragraph.parent().and_then(ElementRef::wrap);
+        let grandparent = parent.and_then(|p| p.parent()).and_then(ElementRef::wrap);
+        for (candidate, weight) in [(parent, 1.0), (grandparent, 0.5)] {
+            if let Some(candidate) = candidate {
+                scores.entry(candidate.id()).or_insert((0.0, candidate)).0 += chars * weight;
+            }
+        }
+    }
+    scores
+        .into_values()
+        .filter(|(_, e)| !is_chrome(e))
+        .map(|(score, e)| (score * (1.0 - link_density(e)), e))
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, e)| e)
+}
+
+/// Characters of text in an element, not counting chrome inside it.
+fn content_chars(element: ElementRef) -> usize {
+    element
+        .children()
+        .map(|child| match child.value() {
+            Node::Text(text) => text.trim().chars().count(),
+            Node::Element(_) => ElementRef::wrap(child)
+                .filter(|e| !is_chrome(e))
+                .map_or(0, content_chars),
+            _ => 0,
+        })
+        .sum()
+}
+
+/// The share of an element's text that sits inside links.
+fn link_density(element: ElementRef) -> f64 {
+    let total: usize = element.text().map(|t| t.trim().chars().count()).sum();
+    if total == 0 {
+        return 1.0;
+    }
+    let links = Selector::parse("a").unwrap();
+    let linked: usize = element
+        .select(&links)
+        .flat_map(|a| a.text())
+        .map(|t| t.trim().chars().count())
+        .sum();
+    linked as f64 / total as f64
+}
+
+fn is_chrome(element: &ElementRef) -> bool {
+    let e = element.value();
+    CHROME_TAGS.contains(&e.name())
+        || e.attr("role").is_some_and(|role| CHROME_ROLES.contains(&role))
+        || e.attr("hidden").is_some()
+        || e.attr("aria-hidden") == Some("true")
+        // Heading anchors, such as rustdoc's `§` links.
+        || (e.name() == "a" && e.classes().any(|c| c == "anchor"))
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Renders the children of `element` as Markdown blocks.
+fn render_blocks(element: ElementRef) -> Vec<String> {
+    let mut writer = BlockWriter::default();
+    writer.children(element);
+    writer.finish()
+}
+
+/// Renders the children of `element` as a single line.
+fn render_inline(element: ElementRef) -> String {
+    collapse_whitespace(&render_blocks(element).join(" "))
+}
+
+/// Collects Markdown blocks, with the inline text of the current paragraph
+/// gathered until the next block starts.
+#[derive(Default)]
+struct BlockWriter {
+    blocks: Vec<String>,
+    inline: String,
+}
+
+impl BlockWriter {
+    fn finish(mut self) -> Vec<String> {
+        self.flush();
+        self.blocks
+    }
+
+    fn flush(&mut self) {
+        let paragraph = self
+            .inline
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n");
+        if !paragraph.is_empty() {
+            self.blocks.push(paragraph);
+        }
+        self.inline.clear();
+    }
+
+    fn block(&mut self, block: String) {
+        self.flush();
+        if !block.trim().is_empty() {
+            self.blocks.push(block);
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        let collapsed = collapse_whitespace(text);
+        let needs_space = |s: &str| !s.is_empty() && !s.ends_with([' ', '\n']);
+        if text.starts_with(char::is_whitespace) && needs_space(&self.inline) {
+            self.inline.push(' ');
+        }
+        self.inline.push_str(&collapsed);
+        if text.ends_with(char::is_whitespace) && needs_space(&self.inline) {
+            self.inline.push(' ');
+        }
+    }
+
+    fn children(&mut self, element: ElementRef) {
+        for child in element.children() {
+            match child.value() {
+                Node::Text(text) => self.text(text),
+                Node::Element(_) => {
+                    if let Some(child) = ElementRef::wrap(child) {
+                        self.element(child);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn element(&mut self, element: ElementRef) {
+        if is_chrome(&element) {
+            return;
+        }
+        match element.value().name() {
+            name @ ("h1" | "h2" | "h3" | "h4" | "h5" | "h6") => {
+                let level = name[1..].parse().unwrap_or(1);
+                let text = render_inline(element);
+                if !text.is_empty() {
+                    self.block(format!("{} {}", "#".repeat(level), text));
+                }
+            }
+            "pre" => self.block(code_block(element)),
+            "code" | "kbd" | "samp" => {
+                let code = collapse_whitespace(&element.text().collect::<String>());
+                if !code.is_empty() {
+                    self.inline.push_str(&inline_code(&code));
+                }
+            }
+            "ul" => self.list(element, None),
+            "ol" => {
+                let start = element.attr("start").and_then(|s| s.parse().ok());
+                self.list(element, Some(start.unwrap_or(1)));
+            }
+            "blockquote" => {
+                let quoted = render_blocks(element).join("\n\n");
+                let quoted = quoted
+                    .lines()
+                    .map(|line| format!("> {}", line).trim_end().to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                self.block(quoted);
+            }
+            "table" => self.table(element),
+            "br" => self.inline.push('\n'),
+            "hr" => self.block("---".to_string()),
+            "p" | "div" | "section" | "article" | "main" | "header" | "figure" | "figcaption"
+            | "details" | "summary" | "dl" | "dt" | "dd" | "li" | "tr" | "address" => {
+                self.flush();
+                self.children(element);
+                self.flush();
+            }
+            _ => self.children(element),
+        }
+    }
+
+    /// A list, numbered from `start` when ordered. Nested blocks are indented
+    /// under their item.
+    fn list(&mut self, element: ElementRef, start: Option<usize>) {
+        let items = element
+            .children()
+            .filter_map(ElementRef::wrap)
+            .filter(|e| e.value().name() == "li" && !is_chrome(e));
+        let mut lines = Vec::new();
+        for (i, item) in items.enumerate() {
+            let marker = match start {
+                Some(start) => format!("{}. ", start + i),
+                None => "- ".to_string(),
+            };
+            let body = render_blocks(item).join("\n");
+            if body.is_empty() {
+                continue;
+            }
+            let indent = " ".repeat(marker.len());
+            let mut item_lines = body.lines();
+            lines.push(format!("{}{}", marker, item_lines.next().unwrap_or_default()));
+            for line in item_lines {
+                lines.push(if line.is_empty() { String::new() } else { format!("{indent}{line}") });
+            }
+        }
+        self.block(lines.join("\n"));
+    }
+
+    /// A table as one line per row, cells separated by ` | `.
+    fn table(&mut self, element: ElementRef) {
+        let rows = Selector::parse("tr").unwrap();
+        let cells = Selector::parse("th, td").unwrap();
+        let lines: Vec<String> = element
+            .select(&rows)
+            .map(|row| {
+                row.select(&cells)
+                    .map(render_inline)
+                    .collect::<Vec<_>>()
+                    .join(" | ")
+            })
+            .filter(|line| !line.trim().is_empty())
+            .collect();
+        self.block(lines.join("\n"));
+    }
+}
+
+fn inline_code(code: &str) -> String {
+    if code.contains('`') {
+        format!("`` {} ``", code)
+    } else {
+        format!("`{}`", code)
+    }
+}
+
+/// A `<pre>` block as a fenced code block, its text kept verbatim.
+fn code_block(pre: ElementRef) -> String {
+    let code: String = pre.text().collect();
+    let code = code.trim_matches('\n').trim_end();
+    let longest_backtick_run = code
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+    let fence = "`".repeat((longest_backtick_run + 1).max(3));
+    let language = code_language(pre).unwrap_or_default();
+    format!("{fence}{language}\n{code}\n{fence}")
+}
+
+/// The language of a code block, from the `language-*`/`lang-*` classes most
+/// highlighters use, a `data-lang` attribute, or rustdoc's `rust` class. Looks
+/// at the `<pre>`, the `<code>` inside it and the element around it.
+fn code_language(pre: ElementRef) -> Option<String> {
+    let code = Selector::parse("code").unwrap();
+    let parent = pre.parent().and_then(ElementRef::wrap);
+    let candidates = std::iter::once(pre)
+        .chain(pre.select(&code).take(1))
+        .chain(parent);
+    for element in candidates {
+        let e = element.value();
+        if let Some(lang) = e.attr("data-lang").filter(|l| !l.is_empty()) {
+            return Some(lang.to_string());
+        }
+        for class in e.classes() {
+            let lang = class
+                .strip_prefix("language-")
+                .or_else(|| class.strip_prefix("lang-"))
+                .or_else(|| class.strip_prefix("highlight-source-"))
+                .or((class == "rust").then_some("rust"));
+            if let Some(lang) = lang.filter(|l| !l.is_empty()) {
+                return Some(lang.to_string());
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn markdown(html: &str) -> String {
+        extract_markdown(&Html::parse_document(html))
+    }
+
+    #[test]
+    fn keeps_the_main_content_and_drops_chrome() {
+        let html = r##"<html><head><title>Mutex</title><script>track()</script></head><body>
+<nav><a href="/">Home</a> <a href="/docs">Docs</a></nav>
+<main>
+  <h1>Mutex <a class="anchor" href="#">§</a></h1>
+  <p>An asynchronous <code>Mutex</code>-like type.
+     It can be held across an <em>.await</em> point.</p>
+  <aside>Related: RwLock</aside>
+  <h2>Examples</h2>
+  <pre class="rust"><code>let m = Mutex::new(1);
+{
+    let mut n = m.lock().await;
+    *n += 1;
+}</code></pre>
+  <p>Long enough to count as the main content of this page, which needs some text.</p>
+</main>
+<footer>Copyright</footer>
+</body></html>"##;
+        assert_eq!(
+            markdown(html),
+            "# Mutex\n\n\
+             An asynchronous `Mutex`-like type. It can be held across an .await point.\n\n\
+             ## Examples\n\n\
+             ```rust\nlet m = Mutex::new(1);\n{\n    let mut n = m.lock().await;\n    *n += 1;\n}\n```\n\n\
+             Long enough to count as the main content of this page, which needs some text."
+        );
+    }
+
+    #[test]
+    fn converts_lists_and_quotes() {
+        let html = r#"<body><article>
+<p>Steps:</p>
+<ol start="3"><li>Add the crate</li><li>Enable features<ul><li><code>full</code></li><li>macros</li></ul></li></ol>
+<blockquote><p>Note: this is a quote.</p></blockquote>
+<p>Padding so that this article is long enough to be picked as the main content region.</p>
+<p>And a bit more padding text, to be safe about the threshold for marked regions.</p>
+</article></body>"#;
+        assert_eq!(
+            markdown(html),
+            "Steps:\n\n\
+             3. Add the crate\n\
+             4. Enable features\n   - `full`\n   - macros\n\n\
+             > Note: this is a quote.\n\n\
+             Padding so that this article is long enough to be picked as the main content region.\n\n\
+             And a bit more padding text, to be safe about the threshold for marked regions."
+        );
+    }
+
+    #[test]
+    fn scores_pages_without_a_marked_main_region() {
+        let html = r#"<html><head><title>Guide</title></head><body>
+<div class="menu"><ul><li><a href="/a">Alpha</a></li><li><a href="/b">Beta</a></li></ul></div>
+<div class="body">
+  <p>The first paragraph of the guide explains what the library is for.</p>
+  <p>The second paragraph says how to install it.</p>
+  <pre><code class="language-toml">[dependencies]
+serde = "1"</code></pre>
+</div>
+</body></html>"#;
+        assert_eq!(
+            markdown(html),
+            "# Guide\n\n\
+             The first paragraph of the guide explains what the library is for.\n\n\
+             The second paragraph says how to install it.\n\n\
+             ```toml\n[dependencies]\nserde = \"1\"\n```"
+        );
+    }
+
+    #[test]
+    fn fences_outgrow_backticks_in_the_code() {
+        let pre = Html::parse_fragment("<pre>let s = \"```\";</pre>");
+        let selector = Selector::parse("pre").unwrap();
+        let block = code_block(pre.select(&selector).next().unwrap());
+        assert_eq!(block, "````\nlet s = \"```\";\n````");
+    }
+}
@@ -0,0 +1,909 @@
+//! Research on the crates a query needs, for the generation prompt. Crates are
+//! looked up on crates.io (or a local mirror of its index) for their latest
+//! version, features, description and repository, and their docs.rs pages for
+//! that exact version are read. Crate names that crates.io doesn't know are
+//! reported, so made-up crates are dropped before any code is generated.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use reqwest::{Url, header};
+use scraper::{Html, Selector};
+use serde::Deserialize;
+
+use crate::{
+    AppState, content_extractor::extract_markdown, http_cache::FetchedPage, qdrant, warn,
+    web_scraper::user_agent, web_search::search_and_scrape,
+};
+
+/// crates.io asks crawlers for at most one request per second.
+const REGISTRY_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct CrateResearchSettings {
+    #[serde(default = "default_registry_api_url")]
+    pub registry_api_url: String,
+    /// A local copy of the crates.io index, in the sparse index layout
+    /// (`se/rd/serde`). When set, crates are looked up there instead of the API.
+    #[serde(default)]
+    pub local_index_dir: Option<String>,
+    #[serde(default = "default_docs_url")]
+    pub docs_url: String,
+    /// docs.rs item pages read per crate besides its front page, picked by the
+    /// words of the query.
+    #[serde(default = "default_docs_item_pages")]
+    pub docs_item_pages: usize,
+    /// Each docs.rs page is cut to this many characters.
+    #[serde(default = "default_max_page_chars")]
+    pub max_page_chars: usize,
+}
+
+fn default_registry_api_url() -> String {
+    "https://crates.io/api/v1".to_string()
+}
+
+fn default_docs_url() -> String {
+    "https://docs.rs".to_string()
+}
+
+fn default_docs_item_pages() -> usize {
+    2
+}
+
+fn default_max_page_chars() -> usize {
+    6000
+}
+
+impl Default for CrateResearchSettings {
+    fn default() -> Self {
+        Self {
+            registry_api_url: default_registry_api_url(),
+            local_index_dir: None,
+            docs_url: default_docs_url(),
+            docs_item_pages: default_docs_item_pages(),
+            max_page_chars: default_max_page_chars(),
+        }
+    }
+}
+
+/// What the registry knows about one version of a crate, by default the latest.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CrateMetadata {
+    /// The crate's name as published, which may differ from the one asked for in `-`/`_`.
+    pub name: String,
+    pub version: String,
+    pub description: Option<String>,
+    pub repository: Option<String>,
+    /// Feature name to what it enables, including the implicit features of
+    /// optional dependencies.
+    pub features: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CrateLookup {
+    Found(CrateMetadata),
+    /// No crate of that name is published.
+    NotFound,
+    /// The crate exists, but none of its versions matches the requirement.
+    NoMatchingVersion,
+}
+
+#[derive(Deserialize)]
+struct ApiCrateResponse {
+    #[serde(rename = "crate")]
+    krate: ApiCrate,
+    #[serde(default)]
+    versions: Vec<ApiVersion>,
+}
+
+#[derive(Deserialize)]
+struct ApiCrate {
+    name: String,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    repository: Option<String>,
+    #[serde(default)]
+    max_stable_version: Option<String>,
+    max_version: String,
+}
+
+#[derive(Deserialize)]
+struct ApiVersion {
+    num: String,
+    #[serde(default)]
+    features: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    yanked: bool,
+}
+
+#[derive(Deserialize)]
+struct ApiDependencies {
+    #[serde(default)]
+    dependencies: Vec<ApiDependency>,
+}
+
+#[derive(Deserialize)]
+struct ApiDependency {
+    crate_id: String,
+    #[serde(default)]
+    optional: bool,
+}
+
+/// One line of a registry index file.
+#[derive(Deserialize)]
+struct IndexEntry {
+    name: String,
+    vers: String,
+    #[serde(default)]
+    features: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    features2: Option<BTreeMap<String, Vec<String>>>,
+    #[serde(default)]
+    deps: Vec<IndexDependency>,
+    #[serde(default)]
+    yanked: bool,
+}
+
+#[derive(Deserialize)]
+struct IndexDependency {
+    name: String,
+    #[serde(default)]
+    optional: bool,
+}
+
+/// Adds the implicit feature of every optional dependency that no feature
+/// enables with `dep:`.
+fn add_implicit_features<'a>(
+    features: &mut BTreeMap<String, Vec<String>>,
+    optional_dependencies: impl IntoIterator<Item = &'a str>,
+) {
+    for dependency in optional_dependencies {
+        let explicit = format!("dep:{}", dependency);
+        let uses_dep_syntax = features.values().flatten().any(|v| *v == explicit);
+        if !uses_dep_syntax {
+            features
+                .entry(dependency.to_string())
+                .or_insert_with(|| vec![explicit]);
+        }
+    }
+}
+
+/// Orders versions by their numbers, with every release above every pre-release.
+fn version_key(version: &str) -> (bool, Vec<u64>) {
+    let version = version.split('+').next().unwrap_or(version);
+    let (release, is_release) = match version.split_once('-') {
+        Some((release, _)) => (release, false),
+        None => (version, true),
+    };
+    let numbers = release.split('.').map(|n| n.parse().unwrap_or(0)).collect();
+    (is_release, numbers)
+}
+
+/// A version's major, minor and patch numbers, and whether it is a pre-release.
+fn version_numbers(version: &str) -> Option<([u64; 3], bool)> {
+    let version = version.split('+').next()?;
+    let (release, is_release) = match version.split_once('-') {
+        Some((release, _)) => (release, false),
+        None => (version, true),
+    };
+    let mut numbers = [0; 3];
+    let mut parts = release.split('.');
+    for number in &mut numbers {
+        *number = parts.next()?.parse().ok()?;
+    }
+    Some((numbers, is_release))
+}
+
+/// Whether `version` satisfies one comparator of a Cargo version requirement,
+/// such as `1.2`, `^0.11`, `~1.2.3`, `>= 1.0`, `=0.9.1` or `1.*`.
+fn matches_comparator(version: [u64; 3], comparator: &str) -> bool {
+    let comparator = comparator.trim();
+    let operator_len = comparator
+        .find(|c: char| c.is_ascii_digit() || c == '*' || c == 'x' || c == 'X')
+        .unwrap_or(comparator.len());
+    let (operator, bound) = comparator.split_at(operator_len);
+    let bound = bound.split(['-', '+']).next().unwrap_or_default();
+
+    let mut given = Vec::new();
+    let mut wildcard = false;
+    for part in bound.split('.').filter(|p| !p.is_empty()) {
+        if matches!(part, "*" | "x" | "X") {
+            wildcard = true;
+            break;
+        }
+        match part.parse::<u64>() {
+            Ok(number) if given.len() < 3 => given.push(number),
+            _ => return false,
+        }
+    }
+    if given.is_empty() {
+        return wildcard || (operator.trim().is_empty() && bound.is_empty());
+    }
+
+    let mut lower = [0; 3];
+    lower[..given.len()].copy_from_slice(&given);
+    // The first version past `lower` when only its first `parts` numbers count.
+    let next = |parts: usize| {
+        let mut upper = [0; 3];
+        upper[..parts].copy_from_slice(&lower[..parts]);
+        upper[parts - 1] += 1;
+        upper
+    };
+    let operator = if wildcard { "=" } else { operator.trim() };
+    match operator {
+        "=" => lower <= version && version < next(given.len()),
+        ">" => version >= next(given.len()),
+        ">=" => version >= lower,
+        "<" => version < lower,
+        "<=" => version < next(given.len()),
+        "~" => lower <= version && version < next(given.len().min(2)),
+        "^" | "" => {
+            // Compatible versions share everything up to the first non-zero number.
+            let parts = given
+                .iter()
+                .position(|n| *n != 0)
+                .map_or(given.len(), |i| i + 1);
+            lower <= version && version < next(parts)
+        }
+        _ => false,
+    }
+}
+
+/// Whether `version` satisfies a Cargo version requirement. As with Cargo,
+/// pre-releases only match requirements that name a pre-release.
+fn matches_requirement(version: &str, requirement: &str) -> bool {
+    let Some((numbers, is_release)) = version_numbers(version) else {
+        return false;
+    };
+    if !is_release && !requirement.contains('-') {
+        return false;
+    }
+    requirement
+        .split(',')
+        .all(|comparator| matches_comparator(numbers, comparator))
+}
+
+/// The version Cargo would pick for `requirement`: the highest one matching it.
+/// Without a requirement, the highest version, preferring releases.
+fn select_version<'a>(
+    versions: impl IntoIterator<Item = &'a str>,
+    requirement: Option<&str>,
+) -> Option<&'a str> {
+    versions
+        .into_iter()
+        .filter(|v| requirement.is_none_or(|r| matches_requirement(v, r)))
+        .max_by_key(|v| version_key(v))
+}
+
+/// Reads a crates.io API response. With a requirement, its features are those
+/// of the version the requirement resolves to; `None` if no version matches.
+fn parse_api_response(json: &str, requirement: Option<&str>) -> Result<Option<CrateMetadata>> {
+    let response: ApiCrateResponse =
+        serde_json::from_str(json).context("Failed to parse crates.io response")?;
+    let krate = response.krate;
+    let version = match requirement {
+        Some(requirement) => select_version(
+            response
+                .versions
+                .iter()
+                .filter(|v| !v.yanked)
+                .map(|v| v.num.as_str()),
+            Some(requirement),
+        )
+        .map(str::to_string),
+        None => Some(krate.max_stable_version.unwrap_or(krate.max_version)),
+    };
+    let Some(version) = version else {
+        return Ok(None);
+    };
+    let features = response
+        .versions
+        .into_iter()
+        .find(|v| v.num == version)
+        .map(|v| v.features)
+        .unwrap_or_default();
+    Ok(Some(CrateMetadata {
+        name: krate.name,
+        version,
+        description: krate.description.map(|d| d.trim().to_string()),
+        repository: krate.repository,
+        features,
+    }))
+}
+
+/// Reads a crate's index file: the highest version that isn't yanked and
+/// matches `requirement`, preferring releases to pre-releases without one.
+fn parse_index_file(contents: &str, requirement: Option<&str>) -> Result<Option<CrateMetadata>> {
+    let entries = contents
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|line| serde_json::from_str(line).context("Failed to parse registry index entry"))
+        .collect::<Result<Vec<IndexEntry>>>()?;
+    let selected = select_version(
+        entries
+            .iter()
+            .filter(|e| !e.yanked)
+            .map(|e| e.vers.as_str()),
+        requirement,
+    )
+    .map(str::to_string);
+    let latest = selected.and_then(|version| entries.into_iter().find(|e| e.vers == version));
+    Ok(latest.map(|entry| {
+        let mut features = entry.features;
+        features.extend(entry.features2.unwrap_or_default());
+        add_implicit_features(
+            &mut features,
+            entry
+                .deps
+                .iter()
+                .filter(|d| d.optional)
+                .map(|d| d.name.as_str()),
+        );
+        CrateMetadata {
+            name: entry.name,
+            version: entry.vers,
+            features,
+            ..Default::default()
+        }
+    }))
+}
+
+/// Whether `name` can be a crates.io crate name: 1 to 64 ASCII letters,
+/// digits, `-` or `_`. Anything else can't exist, and mustn't reach a URL or path.
+pub fn is_valid_crate_name(name: &str) -> bool {
+    (1..=64).contains(&name.len())
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// Where a crate's file is in the sparse index layout: `1/a`, `2/ab`,
+/// `3/a/abc`, and `se/rd/serde` for longer names. `None` for invalid names.
+pub fn index_path(name: &str) -> Option<PathBuf> {
+    if !is_valid_crate_name(name) {
+        return None;
+    }
+    // Valid names are ASCII, so byte offsets are character boundaries.
+    let name = name.to_ascii_lowercase();
+    Some(match name.len() {
+        1 => Path::new("1").join(&name),
+        2 => Path::new("2").join(&name),
+        3 => Path::new("3").join(&name[..1]).join(&name),
+        _ => Path::new(&name[..2]).join(&name[2..4]).join(&name),
+    })
+}
+
+/// Fails unless the configured local index mirror is a directory, so a wrong
+/// path isn't mistaken for an index in which no crate exists.
+pub fn check_local_index(settings: &CrateResearchSettings) -> Result<()> {
+    match &settings.local_index_dir {
+        Some(dir) if !Path::new(dir).is_dir() => {
+            bail!("The local crates.io index {} is not a directory", dir)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// GETs `url` through the HTTP cache, keeping to `interval` between requests to its host.
+async fn fetch(state: &AppState, url: &Url, interval: Duration) -> Result<FetchedPage> {
+    if let Some(page) = state.http_cache.cached_page(url.as_str()).await? {
+        return Ok(page);
+    }
+    state
+        .host_throttle
+        .wait(url.host_str().unwrap_or_default(), interval)
+        .await;
+    let request = state
+        .http_client
+        .get(url.clone())
+        .header(header::USER_AGENT, user_agent())
+        .timeout(Duration::from_secs(
+            state.search_settings.request_timeout_secs,
+        ));
+    state.http_cache.fetch(url.as_str(), request).await
+}
+
+/// Looks the latest version of a crate up on crates.io, or in the local index
+/// mirror when one is configured.
+pub async fn lookup_crate(state: &AppState, name: &str) -> Result<CrateLookup> {
+    lookup_crate_version(state, name, None).await
+}
+
+/// Like `lookup_crate`, but for the version a Cargo version requirement such as
+/// `0.11` resolves to, since features come and go between versions.
+pub async fn lookup_crate_version(
+    state: &AppState,
+    name: &str,
+    requirement: Option<&str>,
+) -> Result<CrateLookup> {
+    if !is_valid_crate_name(name) {
+        return Ok(CrateLookup::NotFound);
+    }
+    let settings = &state.crate_research;
+    check_local_index(settings)?;
+    if let Some(index_dir) = &settings.local_index_dir {
+        // The index stores names as published, so try both spellings.
+        for candidate in [
+            name.to_string(),
+            name.replace('-', "_"),
+            name.replace('_', "-"),
+        ] {
+            let Some(path) = index_path(&candidate).map(|p| Path::new(index_dir).join(p)) else {
+                continue;
+            };
+            if let Ok(contents) = tokio::fs::read_to_string(&path).await {
+                return Ok(match parse_index_file(&contents, requirement)? {
+                    Some(metadata) => CrateLookup::Found(metadata),
+                    None if requirement.is_some() => CrateLookup::NoMatchingVersion,
+                    None => CrateLookup::NotFound,
+                });
+            }
+        }
+        return Ok(CrateLookup::NotFound);
+    }
+
+    let api = settings.registry_api_url.trim_end_matches('/');
+    let url = Url::parse(&format!("{}/crates/{}", api, name))?;
+    let page = fetch(state, &url, REGISTRY_INTERVAL).await?;
+    if page.status == 404 {
+        return Ok(CrateLookup::NotFound);
+    }
+    if !page.is_ok() {
+        bail!("crates.io answered {} for {}", page.status, url);
+    }
+    let Some(mut metadata) = parse_api_response(&page.body, requirement)? else {
+        return Ok(CrateLookup::NoMatchingVersion);
+    };
+
+    // The API lists declared features only; optional dependencies add their own.
+    let url = Url::parse(&format!(
+        "{}/crates/{}/{}/dependencies",
+        api, metadata.name, metadata.version
+    ))?;
+    let page = fetch(state, &url, REGISTRY_INTERVAL).await?;
+    if page.is_ok() {
+        let dependencies: ApiDependencies =
+            serde_json::from_str(&page.body).context("Failed to parse crates.io dependencies")?;
+        add_implicit_features(
+            &mut metadata.features,
+            dependencies
+                .dependencies
+                .iter()
+                .filter(|d| d.optional)
+                .map(|d| d.crate_id.as_str()),
+        );
+    }
+    Ok(CrateLookup::Found(metadata))
+}
+
+/// The lowercase words of a query that are long enough to name an item.
+fn query_words(query: &str) -> Vec<String> {
+    query
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|w| w.len() >= 3)
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Picks the item pages listed on a docs.rs `all.html` page whose names the
+/// query mentions: exact names first, then names containing a query word.
+fn relevant_items(all_html: &str, base: &Url, query: &str, limit: usize) -> Vec<Url> {
+    let words = query_words(query);
+    let document = Html::parse_document(all_html);
+    let links = Selector::parse("ul.all-items a[href]").unwrap();
+
+    let mut scored: Vec<(u8, usize, Url)> = document
+        .select(&links)
+        .filter_map(|link| {
+            let path = link.text().collect::<String>();
+            let name = path.rsplit("::").next()?.to_lowercase();
+            let score = if words.contains(&name) {
+                2
+            } else if name.len() >= 4 && words.iter().any(|w| name.contains(w.as_str())) {
+                1
+            } else {
+                return None;
+            };
+            let url = base.join(link.value().attr("href")?).ok()?;
+            Some((score, path.len(), url))
+        })
+        .collect();
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    scored
+        .into_iter()
+        .take(limit)
+        .map(|(_, _, url)| url)
+        .collect()
+}
+
+fn truncate_chars(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}\n…", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+/// The crates.io facts about a crate, as given to the LLM.
+pub fn format_metadata(metadata: &CrateMetadata) -> String {
+    let mut text = format!(
+        "\n--- Crate '{}' {} (crates.io) ---\n",
+        metadata.name, metadata.version
+    );
+    if let Some(description) = &metadata.description {
+        text.push_str(&format!("{}\n", description));
+    }
+    if let Some(repository) = &metadata.repository {
+        text.push_str(&format!("Repository: {}\n", repository));
+    }
+    let features: Vec<&str> = metadata
+        .features
+        .keys()
+        .map(String::as_str)
+        .filter(|f| *f != "default")
+        .collect();
+    if !features.is_empty() {
+        text.push_str(&format!("Features: {}\n", features.join(", ")));
+    }
+    if let Some(default) = metadata.features.get("default") {
+        text.push_str(&format!("Default features: [{}]\n", default.join(", ")));
+    }
+    text
+}
+
+/// What was found out about one crate for the generation prompt.
+#[derive(Debug, Default)]
+pub struct CrateResearch {
+    pub text: String,
+    pub sources: Vec<String>,
+    pub warnings: Vec<String>,
+    /// crates.io has no crate of this name.
+    pub missing: bool,
+}
+
+/// Reads the docs.rs front page of the crate's version and the item pages the
+/// query mentions. Returns the pages that could be read, in order.
+async fn read_docs(
+    state: &AppState,
+    query: &str,
+    metadata: &CrateMetadata,
+    research: &mut CrateResearch,
+) -> Vec<(Url, String)> {
+    let settings = &state.crate_research;
+    let root = format!(
+        "{}/{}/{}/{}/",
+        settings.docs_url.trim_end_matches('/'),
+        metadata.name,
+        metadata.version,
+        metadata.name.replace('-', "_")
+    );
+    let Ok(root) = Url::parse(&root) else {
+        return Vec::new();
+    };
+
+    let mut pages = vec![root.clone()];
+    let all_items = root
+        .join("all.html")
+        .ok()
+        .filter(|_| settings.docs_item_pages > 0);
+    if let Some(all) = all_items {
+        match fetch(state, &all, Duration::ZERO).await {
+            Ok(page) if page.is_ok() => pages.extend(relevant_items(
+                &page.body,
+                &all,
+                query,
+                settings.docs_item_pages,
+            )),
+            Ok(page) => warn(
+                &mut research.warnings,
+                format!("Skipped {}: HTTP {}", all, page.status),
+            ),
+            Err(e) => warn(&mut research.warnings, format!("Skipped {}: {:#}", all, e)),
+        }
+    }
+
+    let fetched =
+        futures_util::future::join_all(pages.iter().map(|url| fetch(state, url, Duration::ZERO)))
+            .await;
+    let mut read = Vec::new();
+    for (url, page) in pages.into_iter().zip(fetched) {
+        match page {
+            Ok(page) if page.is_ok() => {
+                let markdown = extract_markdown(&Html::parse_document(&page.body));
+                read.push((url, truncate_chars(&markdown, settings.max_page_chars)));
+            }
+            Ok(page) => warn(
+                &mut research.warnings,
+                format!("Skipped {}: HTTP {}", url, page.status),
+            ),
+            Err(e) => warn(&mut research.warnings, format!("Skipped {}: {:#}", url, e)),
+        }
+    }
+    read
+}
+
+/// Researches a crate on the web, for when the registry can't be asked.
+async fn research_on_the_web(state: &AppState, crate_name: &str, research: &mut CrateResearch) {
+    let search_query = format!("crates.io rust crate {} latest API examples", crate_name);
+    println!("Researching crate: {}", search_query);
+    match search_and_scrape(state, &search_query).await {
+        Ok(results) => {
+            research.text.push_str(&format!(
+                "\n--- Research for crate '{}': ---\n{}\n",
+                crate_name, results.text
+            ));
+            research.sources.extend(results.sources);
+            for error in results.errors {
+                warn(&mut research.warnings, error);
+            }
+        }
+        Err(e) => warn(
+            &mut research.warnings,
+            format!("Research for crate '{}' failed: {:#}", crate_name, e),
+        ),
+    }
+}
+
+/// Looks a crate up in the indexed crate docs, or else on crates.io and docs.rs,
+/// falling back to a web search when those can't be reached. Never fails:
+/// whatever can't be looked up is reported in `warnings` and left out.
+pub async fn research_crate(state: &AppState, query: &str, crate_name: &str) -> CrateResearch {
+    let mut research = CrateResearch::default();
+
+    // Crates indexed from a local checkout answer with their real API.
+    match qdrant::search_crate_docs(state, query, crate_name).await {
+        Ok(indexed) if !indexed.is_empty() => {
+            println!("Using indexed API docs for crate: {}", crate_name);
+            research.text = format!(
+                "\n--- Indexed API of crate '{}': ---\n{}\n",
+                crate_name, indexed
+            );
+            return research;
+        }
+        Ok(_) => {}
+        Err(e) => warn(
+            &mut research.warnings,
+            format!(
+                "Indexed docs of crate '{}' unavailable: {:#}",
+                crate_name, e
+            ),
+        ),
+    }
+
+    let metadata = match lookup_crate(state, crate_name).await {
+        Ok(CrateLookup::Found(metadata)) => metadata,
+        Ok(CrateLookup::NotFound | CrateLookup::NoMatchingVersion) => {
+            warn(
+                &mut research.warnings,
+                format!(
+                    "Crate '{}' does not exist on crates.io; left out",
+                    crate_name
+                ),
+            );
+            research.missing = true;
+            return research;
+        }
+        Err(e) => {
+            warn(
+                &mut research.warnings,
+                format!(
+                    "Could not look up crate '{}' on crates.io: {:#}",
+                    crate_name, e
+                ),
+            );
+            research_on_the_web(state, crate_name, &mut research).await;
+            return research;
+        }
+    };
+
+    println!(
+        "Researching crate {} {} on docs.rs",
+        metadata.name, metadata.version
+    );
+    research.text = format_metadata(&metadata);
+    research.sources.push(format!(
+        "https://crates.io/crates/{}/{}",
+        metadata.name, metadata.version
+    ));
+    let docs = read_docs(state, query, &metadata, &mut research).await;
+    if docs.is_empty() {
+        research_on_the_web(state, crate_name, &mut research).await;
+    }
+    for (url, markdown) in docs {
+        research
+            .text
+            .push_str(&format!("\n--- docs.rs: {} ---\n{}\n", url, markdown));
+        research.sources.push(url.to_string());
+    }
+    research
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_latest_stable_version_from_the_api() {
+        let json = r#"{
+            "crate": {"name": "serde_json", "description": " A JSON library \n",
+                      "repository": "https://github.com/serde-rs/json",
+                      "max_version": "2.0.0-alpha.1", "max_stable_version": "1.0.140"},
+            "versions": [
+                {"num": "2.0.0-alpha.1", "features": {"default": []}},
+                {"num": "1.0.140", "features": {"default": ["std"], "std": [], "preserve_order": ["indexmap"]}}
+            ]
+        }"#;
+        let metadata = parse_api_response(json, None).unwrap().unwrap();
+        assert_eq!(metadata.version, "1.0.140");
+        assert_eq!(metadata.description.as_deref(), Some("A JSON library"));
+        assert_eq!(
+            metadata.features.keys().collect::<Vec<_>>(),
+            ["default", "preserve_order", "std"]
+        );
+    }
+
+    #[test]
+    fn reads_the_version_a_requirement_resolves_to_from_the_api() {
+        let json = r#"{
+            "crate": {"name": "reqwest", "max_version": "0.12.22", "max_stable_version": "0.12.22"},
+            "versions": [
+                {"num": "0.12.22", "features": {"hickory-dns": []}},
+                {"num": "0.11.28", "features": {}, "yanked": true},
+                {"num": "0.11.27", "features": {"trust-dns": []}},
+                {"num": "0.11.3", "features": {}}
+            ]
+        }"#;
+        let latest = parse_api_response(json, None).unwrap().unwrap();
+        assert!(latest.features.contains_key("hickory-dns"));
+        let old = parse_api_response(json, Some("0.11")).unwrap().unwrap();
+        assert_eq!(old.version, "0.11.27");
+        assert!(old.features.contains_key("trust-dns"));
+        assert_eq!(parse_api_response(json, Some("0.10")).unwrap(), None);
+    }
+
+    #[test]
+    fn requirements_match_like_cargo() {
+        let cases = [
+            ("1.2.3", "1", true),
+            ("1.2.3", "1.2", true),
+            ("1.2.3", "^1.3", false),
+            ("2.0.0", "1", false),
+            ("0.11.27", "0.11", true),
+            ("0.12.0", "0.11", false),
+            ("0.0.4", "0.0.3", false),
+            ("1.2.9", "~1.2.3", true),
+            ("1.3.0", "~1.2.3", false),
+            ("1.4.0", "~1", true),
+            ("0.9.1", "=0.9.1", true),
+            ("0.9.2", "=0.9.1", false),
+            ("1.5.0", ">=1.2, <2", true),
+            ("2.0.0", ">=1.2, <2", false),
+            ("1.3.0", ">1.2", true),
+            ("1.2.7", ">1.2", false),
+            ("1.2.7", "<=1.2", true),
+            ("1.7.0", "1.*", true),
+            ("1.2.5", "1.2.*", true),
+            ("1.3.0", "1.2.*", false),
+            ("4.0.0", "*", true),
+            ("2.0.0-rc.1", "2", false),
+            ("2.0.0-rc.1", "2.0.0-rc.1", true),
+            ("1.0.0", "latest", false),
+        ];
+        for (version, requirement, expected) in cases {
+            assert_eq!(
+                matches_requirement(version, requirement),
+                expected,
+                "{} {}",
+                version,
+                requirement
+            );
+        }
+    }
+
+    #[test]
+    fn reads_the_latest_version_from_an_index_file() {
+        let index = r#"{"name":"uuid","vers":"1.9.0","features":{"v4":["getrandom"]},"deps":[],"yanked":false}
+{"name":"uuid","vers":"1.10.0","features":{"v4":["dep:getrandom"]},"features2":{"serde":["dep:serde"]},"deps":[{"name":"getrandom","optional":true},{"name":"zerocopy","optional":true},{"name":"serde","optional":true}],"yanked":false}
+{"name":"uuid","vers":"1.11.0","features":{},"deps":[],"yanked":true}
+{"name":"uuid","vers":"2.0.0-rc.1","features":{},"deps":[],"yanked":false}"#;
+        let metadata = parse_index_file(index, None).unwrap().unwrap();
+        assert_eq!(metadata.version, "1.10.0");
+        assert_eq!(
+            metadata.features.keys().collect::<Vec<_>>(),
+            ["serde", "v4", "zerocopy"]
+        );
+        assert_eq!(metadata.features["zerocopy"], ["dep:zerocopy"]);
+        assert_eq!(parse_index_file("", None).unwrap(), None);
+
+        let older = parse_index_file(index, Some("~1.9")).unwrap().unwrap();
+        assert_eq!(older.version, "1.9.0");
+        assert_eq!(older.features["v4"], ["getrandom"]);
+        let pre = parse_index_file(index, Some("2.0.0-rc.1"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(pre.version, "2.0.0-rc.1");
+        assert_eq!(parse_index_file(index, Some("3")).unwrap(), None);
+    }
+
+    #[test]
+    fn index_paths_follow_the_sparse_layout() {
+        let path = |name| index_path(name).unwrap();
+        assert_eq!(path("a"), Path::new("1/a"));
+        assert_eq!(path("cc"), Path::new("2/cc"));
+        assert_eq!(path("syn"), Path::new("3/s/syn"));
+        assert_eq!(path("Serde_JSON"), Path::new("se/rd/serde_json"));
+    }
+
+    #[test]
+    fn invalid_names_have_no_index_path() {
+        for name in [
+            "",
+            "ünïcode",
+            "tokio\u{e9}",
+            "../etc/passwd",
+            "a b",
+            &"x".repeat(65),
+        ] {
+            assert!(!is_valid_crate_name(name), "{:?}", name);
+            assert_eq!(index_path(name), None);
+        }
+        assert!(is_valid_crate_name(&"x".repeat(64)));
+        assert!(is_valid_crate_name("rt-multi_thread2"));
+    }
+
+    #[test]
+    fn missing_local_index_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings = |dir: &Path| CrateResearchSettings {
+            local_index_dir: Some(dir.display().to_string()),
+            ..Default::default()
+        };
+        assert!(check_local_index(&settings(dir.path())).is_ok());
+        assert!(check_local_index(&settings(&dir.path().join("missing"))).is_err());
+        assert!(check_local_index(&CrateResearchSettings::default()).is_ok());
+    }
+
+    #[test]
+    fn picks_the_items_the_query_names() {
+        let all_html = r#"<ul class="all-items">
+            <li><a href="sync/struct.Mutex.html">sync::Mutex</a></li>
+            <li><a href="sync/struct.MutexGuard.html">sync::MutexGuard</a></li>
+            <li><a href="sync/struct.RwLock.html">sync::RwLock</a></li>
+            <li><a href="fn.spawn.html">spawn</a></li>
+            <li><a href="task/fn.spawn_blocking.html">task::spawn_blocking</a></li>
+        </ul>"#;
+        let base = Url::parse("https://docs.rs/tokio/1.47.1/tokio/all.html").unwrap();
+        let urls: Vec<String> = relevant_items(all_html, &base, "spawn tasks sharing a Mutex", 3)
+            .iter()
+            .map(|u| u.to_string())
+            .collect();
+        assert_eq!(
+            urls,
+            [
+                "https://docs.rs/tokio/1.47.1/tokio/fn.spawn.html",
+                "https://docs.rs/tokio/1.47.1/tokio/sync/struct.Mutex.html",
+                "https://docs.rs/tokio/1.47.1/tokio/sync/struct.MutexGuard.html",
+            ]
+        );
+    }
+
+    #[test]
+    fn metadata_lists_features_and_defaults() {
+        let metadata = CrateMetadata {
+            name: "tokio".to_string(),
+            version: "1.47.1".to_string(),
+            features: BTreeMap::from([
+                ("default".to_string(), vec![]),
+                ("full".to_string(), vec!["rt".to_string()]),
+                ("rt".to_string(), vec![]),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            format_metadata(&metadata),
+            "\n--- Crate 'tokio' 1.47.1 (crates.io) ---\nFeatures: full, rt\nDefault features: []\n"
+        );
+    }
+}
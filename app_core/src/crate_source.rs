@@ -0,0 +1,517 @@
+//! Indexes a crate checked out on disk (a directory under `~/.cargo/registry/src`,
+//! a vendored dependency, ...) so generation can use its real API instead of
+//! whatever the model or a web search remembers.
+//!
+//! Public items come from the crate's rustdoc JSON when it has been generated
+//! (`cargo +nightly rustdoc -- -Z unstable-options --output-format json` puts it
+//! in `target/doc/<crate>.json`), and from parsing `src/` with syn otherwise.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use proc_macro2::{LineColumn, Span};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use syn::{Fields, Item, spanned::Spanned};
+
+use crate::{
+    AppState,
+    code_chunker::{RustChunk, SourceText, chunk_public_items, join_path, module_path_for_file},
+    ingestion::{IngestReport, document_id_for_source, replace_chunks},
+    jobs::JobProgress,
+    payload::{KnowledgeChunk, normalize_crate_name},
+};
+
+/// Name and version from a crate's `Cargo.toml`.
+#[derive(Debug, Clone)]
+pub struct CrateInfo {
+    pub name: String,
+    pub version: String,
+    pub root: PathBuf,
+}
+
+/// Where the indexed items came from.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CrateIndexSource {
+    RustdocJson,
+    Source,
+}
+
+/// What `ingest_crate` stored.
+#[derive(Serialize, Debug, Clone)]
+pub struct CrateIngestSummary {
+    pub crate_name: String,
+    pub version: String,
+    pub indexed_from: CrateIndexSource,
+    #[serde(flatten)]
+    pub ingest: IngestReport,
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    package: ManifestPackage,
+}
+
+#[derive(Deserialize)]
+struct ManifestPackage {
+    name: String,
+    /// A string, or `{ workspace = true }` in workspace members.
+    #[serde(default)]
+    version: Option<toml::Value>,
+}
+
+pub fn read_crate_info(dir: &Path) -> Result<CrateInfo> {
+    let manifest_path = dir.join("Cargo.toml");
+    let manifest = std::fs::read_to_string(&manifest_path)
+        .with_context(|| format!("No Cargo.toml in {}", dir.display()))?;
+    let manifest: Manifest = toml::from_str(&manifest)
+        .with_context(|| format!("Failed to parse {}", manifest_path.display()))?;
+    let version = manifest
+        .package
+        .version
+        .as_ref()
+        .and_then(|v| v.as_str())
+        .unwrap_or("unknown")
+        .to_string();
+    Ok(CrateInfo {
+        name: manifest.package.name,
+        version,
+        root: dir.to_path_buf(),
+    })
+}
+
+/// Where `cargo rustdoc --output-format json` writes the crate's JSON.
+fn default_rustdoc_json(info: &CrateInfo) -> PathBuf {
+    info.root
+        .join("target")
+        .join("doc")
+        .join(format!("{}.json", normalize_crate_name(&info.name)))
+}
+
+/// Indexes the crate in `dir` into the knowledge base. `rustdoc_json` overrides
+/// where its rustdoc JSON is looked for. Passing the `document_id` of an earlier
+/// ingest replaces that document instead of adding a new one.
+pub async fn ingest_crate(
+    state: &AppState,
+    dir: &Path,
+    rustdoc_json: Option<PathBuf>,
+    document_id: Option<String>,
+    progress: &JobProgress,
+) -> Result<CrateIngestSummary> {
+    let info = read_crate_info(dir)?;
+    let json_path = rustdoc_json.unwrap_or_else(|| default_rustdoc_json(&info));
+
+    let (items, indexed_from) = match tokio::fs::read_to_string(&json_path).await {
+        Ok(json) => {
+            let items = tokio::task::spawn_blocking({
+                let info = info.clone();
+                move || items_from_rustdoc(&json, &info)
+            })
+            .await??;
+            (items, CrateIndexSource::RustdocJson)
+        }
+        Err(_) => {
+            println!(
+                "INFO: No rustdoc JSON at {}, indexing {} from source.",
+                json_path.display(),
+                info.name
+            );
+            let items = tokio::task::spawn_blocking({
+                let info = info.clone();
+                move || items_from_source(&info)
+            })
+            .await??;
+            (items, CrateIndexSource::Source)
+        }
+    };
+
+    let source = info.root.display().to_string();
+    let document_id = match document_id {
+        Some(id) => id,
+        None => document_id_for_source(state, &source).await?,
+    };
+    let chunks: Vec<KnowledgeChunk> = items
+        .into_iter()
+        .enumerate()
+        .map(|(index, item)| {
+            KnowledgeChunk::new(item.text, document_id.as_str(), source.as_str(), index)
+                .with_item(item.item_path, item.item_kind)
+                .with_crate(&info.name, info.version.as_str())
+        })
+        .collect();
+    println!(
+        "Indexing {} items of {} {}...",
+        chunks.len(),
+        info.name,
+        info.version
+    );
+    let ingest = replace_chunks(state, &document_id, &chunks, progress).await?;
+
+    Ok(CrateIngestSummary {
+        crate_name: info.name,
+        version: info.version,
+        indexed_from,
+        ingest,
+    })
+}
+
+/// Parses every `.rs` file under `src/` (binaries excluded) and keeps its public items.
+pub fn items_from_source(info: &CrateInfo) -> Result<Vec<RustChunk>> {
+    let src = info.root.join("src");
+    let mut files = Vec::new();
+    collect_rust_files(&src, &mut files)
+        .with_context(|| format!("Failed to read {}", src.display()))?;
+    files.sort();
+
+    let crate_ident = normalize_crate_name(&info.name);
+    let mut items = Vec::new();
+    for file in files {
+        let relative = file.strip_prefix(&info.root).unwrap_or(&file);
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        let source = std::fs::read_to_string(&file)?;
+        let module_path = match module_path_for_file(&relative) {
+            module if module.is_empty() => crate_ident.clone(),
+            module => join_path(&crate_ident, &module),
+        };
+        match chunk_public_items(&source, &module_path) {
+            Ok(chunks) => items.extend(chunks),
+            Err(e) => println!("Warning: skipping {}: {:#}", file.display(), e),
+        }
+    }
+    Ok(items)
+}
+
+fn collect_rust_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if path.file_name().is_some_and(|n| n != "bin") {
+                collect_rust_files(&path, files)?;
+            }
+        } else if path.extension().is_some_and(|e| e == "rs") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Rustdoc item ids are strings in older format versions and integers in newer ones.
+fn id_key(id: &Value) -> Option<String> {
+    match id {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// Our item kinds for rustdoc's `inner` keys, matching the ones `code_chunker` uses.
+fn item_kind(inner_key: &str) -> Option<&'static str> {
+    Some(match inner_key {
+        "function" => "fn",
+        "struct" => "struct",
+        "enum" => "enum",
+        "union" => "union",
+        "trait" => "trait",
+        "trait_alias" => "trait",
+        "type_alias" | "typedef" => "type",
+        "constant" => "const",
+        "static" => "static",
+        "macro" | "proc_macro" => "macro",
+        _ => return None,
+    })
+}
+
+/// Reads public items from rustdoc JSON: path, kind, the signature (taken from
+/// the source file the item's span points at) and the doc comment with its examples.
+pub fn items_from_rustdoc(json: &str, info: &CrateInfo) -> Result<Vec<RustChunk>> {
+    let doc: Value = serde_json::from_str(json).context("Failed to parse rustdoc JSON")?;
+    let index = doc
+        .get("index")
+        .and_then(Value::as_object)
+        .context("rustdoc JSON has no index")?;
+    let paths = doc.get("paths").and_then(Value::as_object);
+
+    let full_path = |id: &str| -> Option<String> {
+        let segments = paths?.get(id)?.get("path")?.as_array()?;
+        let segments: Vec<&str> = segments.iter().filter_map(Value::as_str).collect();
+        (!segments.is_empty()).then(|| segments.join("::"))
+    };
+
+    // Methods aren't listed in `paths`; name them after the type of their inherent impl.
+    let mut method_owner: HashMap<String, String> = HashMap::new();
+    for item in index.values() {
+        let Some(imp) = item.get("inner").and_then(|i| i.get("impl")) else {
+            continue;
+        };
+        if !imp.get("trait").is_none_or(Value::is_null) {
+            continue;
+        }
+        let Some(ty) = imp.get("for").and_then(|f| f.get("resolved_path")) else {
+            continue;
+        };
+        let owner = ty
+            .get("id")
+            .and_then(id_key)
+            .and_then(|id| full_path(&id))
+            .or_else(|| {
+                ty.get("path")
+                    .or_else(|| ty.get("name"))
+                    .and_then(Value::as_str)
+                    .map(String::from)
+            });
+        let (Some(owner), Some(children)) = (owner, imp.get("items").and_then(Value::as_array))
+        else {
+            continue;
+        };
+        for child in children.iter().filter_map(id_key) {
+            method_owner.insert(child, owner.clone());
+        }
+    }
+
+    let mut sources: HashMap<PathBuf, Option<String>> = HashMap::new();
+    let mut items = Vec::new();
+    for (id, item) in index {
+        let is_local = item.get("crate_id").and_then(Value::as_u64) == Some(0);
+        let is_public = item.get("visibility").and_then(Value::as_str) == Some("public");
+        let Some(name) = item.get("name").and_then(Value::as_str) else {
+            continue;
+        };
+        let Some((inner_key, _)) = item
+            .get("inner")
+            .and_then(Value::as_object)
+            .and_then(|inner| inner.iter().next())
+        else {
+            continue;
+        };
+        let Some(kind) = item_kind(inner_key) else {
+            continue;
+        };
+        if !is_local || !is_public {
+            continue;
+        }
+
+        let (path, kind) = match (full_path(id), method_owner.get(id)) {
+            (Some(path), _) => (path, kind),
+            (None, Some(owner)) if kind == "fn" => (format!("{}::{}", owner, name), "method"),
+            _ => continue,
+        };
+
+        let signature = item
+            .get("span")
+            .and_then(|span| signature_from_span(span, &info.root, &mut sources));
+        let docs = item.get("docs").and_then(Value::as_str).unwrap_or_default();
+        if signature.is_none() && docs.is_empty() {
+            continue;
+        }
+
+        let mut text = format!("{} {}", kind, path);
+        if let Some(signature) = signature {
+            text.push_str(&format!("\n```rust\n{}\n```", signature));
+        }
+        if !docs.is_empty() {
+            text.push_str(&format!("\n{}", docs.trim_end()));
+        }
+        items.push(RustChunk {
+            text,
+            item_path: path,
+            item_kind: kind.to_string(),
+        });
+    }
+
+    // The index is a map; give the chunks a stable order.
+    items.sort_by(|a, b| a.item_path.cmp(&b.item_path));
+    Ok(items)
+}
+
+/// Reads the declaration an item's span covers, up to its body.
+fn signature_from_span(
+    span: &Value,
+    root: &Path,
+    sources: &mut HashMap<PathBuf, Option<String>>,
+) -> Option<String> {
+    let file = root.join(span.get("filename")?.as_str()?);
+    let line = |key: &str| -> Option<usize> {
+        Some(span.get(key)?.as_array()?.first()?.as_u64()? as usize)
+    };
+    // Whole lines, so column conventions don't matter; the body is cut off below anyway.
+    let start = LineColumn {
+        line: line("begin")?,
+        column: 0,
+    };
+    let end = LineColumn {
+        line: line("end")? + 1,
+        column: 0,
+    };
+
+    let source = sources
+        .entry(file.clone())
+        .or_insert_with(|| std::fs::read_to_string(&file).ok())
+        .as_deref()?;
+    let text = SourceText::new(source).between(start, end);
+    let signature = signature_of(&text);
+    (!signature.is_empty()).then_some(signature)
+}
+
+/// The declaration part of an item: everything before its body, or the whole
+/// item without its closing `;` when it has none, without doc comments and
+/// attributes. The body is found by parsing the item, so braces and `;` in
+/// array types, const generics and where clauses don't cut it short.
+fn signature_of(item_text: &str) -> String {
+    let text = SourceText::new(item_text);
+    let declaration = match syn::parse_str::<Item>(item_text) {
+        Ok(item) => {
+            let (attrs, body) = item_parts(&item);
+            let start = attrs
+                .last()
+                .map_or(LineColumn { line: 1, column: 0 }, |a| a.span().end());
+            let end = body.map_or_else(|| item.span().end(), |b| b.start());
+            text.between(start, end)
+        }
+        // Not a whole item, e.g. the span also covered its neighbours.
+        Err(_) => item_text[..top_level_end(item_text)].to_string(),
+    };
+    declaration
+        .lines()
+        .filter(|l| {
+            let l = l.trim_start();
+            !l.starts_with("//") && !l.starts_with("#[")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .trim_end_matches(';')
+        .trim_end()
+        .to_string()
+}
+
+/// An item's attributes and the span its body starts at, if it has one.
+fn item_parts(item: &Item) -> (&[syn::Attribute], Option<Span>) {
+    match item {
+        Item::Fn(f) => (&f.attrs, Some(f.block.brace_token.span.open())),
+        Item::Struct(s) => match &s.fields {
+            Fields::Named(fields) => (&s.attrs, Some(fields.brace_token.span.open())),
+            _ => (&s.attrs, None),
+        },
+        Item::Enum(e) => (&e.attrs, Some(e.brace_token.span.open())),
+        Item::Union(u) => (&u.attrs, Some(u.fields.brace_token.span.open())),
+        Item::Trait(t) => (&t.attrs, Some(t.brace_token.span.open())),
+        Item::Impl(i) => (&i.attrs, Some(i.brace_token.span.open())),
+        Item::Mod(m) => (&m.attrs, m.content.as_ref().map(|(b, _)| b.span.open())),
+        Item::ForeignMod(m) => (&m.attrs, Some(m.brace_token.span.open())),
+        Item::Macro(m) => (&m.attrs, Some(m.mac.delimiter.span().open())),
+        Item::Const(c) => (&c.attrs, None),
+        Item::Static(s) => (&s.attrs, None),
+        Item::Type(t) => (&t.attrs, None),
+        Item::TraitAlias(t) => (&t.attrs, None),
+        Item::Use(u) => (&u.attrs, None),
+        Item::ExternCrate(e) => (&e.attrs, None),
+        _ => (&[], None),
+    }
+}
+
+/// Where the first `{` or `;` outside parentheses and brackets is.
+fn top_level_end(text: &str) -> usize {
+    let mut depth = 0usize;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = depth.saturating_sub(1),
+            '{' | ';' if depth == 0 => return i,
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signatures_stop_at_the_body() {
+        let text = "/// Docs.\n#[inline]\npub fn add(a: i32, b: i32) -> i32 {\n    a + b\n}";
+        assert_eq!(signature_of(text), "pub fn add(a: i32, b: i32) -> i32");
+        assert_eq!(signature_of("pub struct Unit;"), "pub struct Unit");
+    }
+
+    #[test]
+    fn signatures_read_past_braces_and_semicolons_in_the_declaration() {
+        assert_eq!(
+            signature_of("pub fn sum(bytes: [u8; 4]) -> u32 {\n    0\n}"),
+            "pub fn sum(bytes: [u8; 4]) -> u32"
+        );
+        assert_eq!(
+            signature_of("pub fn pad<const N: usize>() -> Buf<{ N + 1 }> {\n    todo!()\n}"),
+            "pub fn pad<const N: usize>() -> Buf<{ N + 1 }>"
+        );
+        let text =
+            "/// Docs.\npub fn run<F>(f: F)\nwhere\n    F: Fn([u8; 2]),\n{\n    f([0; 2])\n}";
+        assert_eq!(
+            signature_of(text),
+            "pub fn run<F>(f: F)\nwhere\n    F: Fn([u8; 2]),"
+        );
+        assert_eq!(
+            signature_of("pub struct Pair(pub [u8; 2]);"),
+            "pub struct Pair(pub [u8; 2])"
+        );
+        assert_eq!(
+            signature_of("pub const ZEROS: [u8; 4] = [0; 4];"),
+            "pub const ZEROS: [u8; 4] = [0; 4]"
+        );
+        // Text that doesn't parse as one item still skips bracketed `;`.
+        assert_eq!(
+            signature_of("pub fn f(x: [u8; 4]) {}\n}"),
+            "pub fn f(x: [u8; 4])"
+        );
+    }
+
+    #[test]
+    fn rustdoc_items_are_named_by_path() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+        std::fs::write(
+            dir.path().join("src/lib.rs"),
+            "pub struct Point;\n\nimpl Point {\n    pub fn origin() -> Self {\n        Point\n    }\n}\n",
+        )
+        .unwrap();
+        let info = CrateInfo {
+            name: "geo-lite".to_string(),
+            version: "1.2.3".to_string(),
+            root: dir.path().to_path_buf(),
+        };
+        let json = r#"{
+            "root": 0,
+            "index": {
+                "1": {"crate_id": 0, "name": "Point", "visibility": "public", "docs": "A point.",
+                      "span": {"filename": "src/lib.rs", "begin": [1, 0], "end": [1, 17]},
+                      "inner": {"struct": {}}},
+                "2": {"crate_id": 0, "name": null, "visibility": "default", "docs": null,
+                      "inner": {"impl": {"trait": null, "items": [3],
+                                         "for": {"resolved_path": {"path": "Point", "id": 1}}}}},
+                "3": {"crate_id": 0, "name": "origin", "visibility": "public",
+                      "docs": "```\nlet p = Point::origin();\n```",
+                      "span": {"filename": "src/lib.rs", "begin": [4, 4], "end": [6, 5]},
+                      "inner": {"function": {}}},
+                "4": {"crate_id": 1, "name": "Vec", "visibility": "public", "docs": "std",
+                      "inner": {"struct": {}}}
+            },
+            "paths": {"1": {"crate_id": 0, "path": ["geo_lite", "Point"], "kind": "struct"}}
+        }"#;
+
+        let items = items_from_rustdoc(json, &info).unwrap();
+        let paths: Vec<(&str, &str)> = items
+            .iter()
+            .map(|i| (i.item_path.as_str(), i.item_kind.as_str()))
+            .collect();
+        assert_eq!(
+            paths,
+            [("geo_lite::Point", "struct"), ("geo_lite::Point::origin", "method")]
+        );
+        assert_eq!(
+            items[1].text,
+            "method geo_lite::Point::origin\n```rust\npub fn origin() -> Self\n```\n```\nlet p = Point::origin();\n```"
+        );
+    }
+}
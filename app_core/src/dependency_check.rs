@@ -0,0 +1,265 @@
+//! Checks the dependencies the LLM chose against the registry before they reach
+//! Cargo. A misspelt feature makes Cargo's resolver reject the whole manifest
+//! with an error that says little about the cause, so near misses are corrected
+//! here and anything that can't be corrected is reported back to the LLM.
+
+use std::collections::HashSet;
+
+use futures_util::future::join_all;
+
+use crate::{
+    AppState,
+    crate_research::{CrateLookup, CrateMetadata, lookup_crate_version},
+    llm::Dependency,
+};
+
+/// What checking a set of dependencies found.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DependencyCheck {
+    /// Corrections made in place, e.g. "tokio: feature `fulll` is `full`".
+    pub corrections: Vec<String>,
+    /// Crates and features that don't exist, for the LLM to fix.
+    pub problems: Vec<String>,
+    /// Dependencies that couldn't be checked.
+    pub warnings: Vec<String>,
+}
+
+impl DependencyCheck {
+    /// The problems as a prompt section, listing what each crate does offer.
+    pub fn report(&self) -> String {
+        format!(
+            "Dependency check against crates.io (before building):\n{}",
+            self.problems
+                .iter()
+                .map(|p| format!("- {}", p))
+                .collect::<Vec<_>>()
+                .join("\n")
+        )
+    }
+}
+
+/// Lowercase, with `_` read as `-`, which is how crates.io compares names.
+fn normalize(name: &str) -> String {
+    name.to_lowercase().replace('_', "-")
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// The crate's feature that `requested` most likely meant: the same name up to
+/// case and `-`/`_`, or else the only feature within a typo or two of it.
+fn closest_feature<'a>(metadata: &'a CrateMetadata, requested: &str) -> Option<&'a str> {
+    let requested = normalize(requested);
+    let features = || metadata.features.keys().map(String::as_str);
+    if let Some(feature) = features().find(|f| normalize(f) == requested) {
+        return Some(feature);
+    }
+
+    let allowed = if requested.len() > 6 { 2 } else { 1 };
+    let mut candidates: Vec<(usize, &str)> = features()
+        .map(|f| (edit_distance(&normalize(f), &requested), f))
+        .filter(|(distance, _)| *distance <= allowed)
+        .collect();
+    candidates.sort();
+    match candidates.as_slice() {
+        [(_, only)] => Some(only),
+        [(best, feature), (next, _), ..] if best < next => Some(feature),
+        _ => None,
+    }
+}
+
+/// Checks one dependency's features against its crate's metadata, correcting
+/// near misses in place and dropping the features that don't exist.
+pub fn check_features(
+    dependency: &mut Dependency,
+    metadata: &CrateMetadata,
+    check: &mut DependencyCheck,
+) {
+    let mut features = Vec::new();
+    let mut seen = HashSet::new();
+    let mut unknown = Vec::new();
+    for requested in std::mem::take(&mut dependency.features) {
+        // Every crate can be asked for its default features.
+        let feature = if requested == "default" || metadata.features.contains_key(&requested) {
+            requested
+        } else {
+            match closest_feature(metadata, &requested) {
+                Some(feature) => {
+                    check.corrections.push(format!(
+                        "{}: feature `{}` is `{}`",
+                        dependency.name, requested, feature
+                    ));
+                    feature.to_string()
+                }
+                None => {
+                    unknown.push(requested);
+                    continue;
+                }
+            }
+        };
+        if seen.insert(feature.clone()) {
+            features.push(feature);
+        }
+    }
+    dependency.features = features;
+
+    if !unknown.is_empty() {
+        let available: Vec<&str> = metadata
+            .features
+            .keys()
+            .map(String::as_str)
+            .filter(|f| *f != "default")
+            .collect();
+        check.problems.push(format!(
+            "{} {} has no feature(s) {}; its features are: {}",
+            metadata.name,
+            metadata.version,
+            unknown
+                .iter()
+                .map(|f| format!("`{}`", f))
+                .collect::<Vec<_>>()
+                .join(", "),
+            if available.is_empty() {
+                "none".to_string()
+            } else {
+                available.join(", ")
+            }
+        ));
+    }
+}
+
+/// Looks every dependency up on the registry and checks its features against
+/// the version its requirement resolves to. Names and features are corrected in
+/// place and unknown features dropped; crates that don't exist, or have no
+/// matching version, are left for the LLM to fix.
+pub async fn check_dependencies(
+    state: &AppState,
+    dependencies: &mut [Dependency],
+) -> DependencyCheck {
+    let lookups = join_all(
+        dependencies
+            .iter()
+            .map(|d| lookup_crate_version(state, &d.name, d.version.as_deref())),
+    )
+    .await;
+
+    let mut check = DependencyCheck::default();
+    for (dependency, lookup) in dependencies.iter_mut().zip(lookups) {
+        match lookup {
+            Ok(CrateLookup::Found(metadata)) => {
+                if dependency.name != metadata.name {
+                    check.corrections.push(format!(
+                        "crate `{}` is published as `{}`",
+                        dependency.name, metadata.name
+                    ));
+                    dependency.name = metadata.name.clone();
+                }
+                check_features(dependency, &metadata, &mut check);
+            }
+            Ok(CrateLookup::NotFound) => check.problems.push(format!(
+                "crate `{}` does not exist on crates.io",
+                dependency.name
+            )),
+            Ok(CrateLookup::NoMatchingVersion) => check.problems.push(format!(
+                "crate `{}` has no version matching `{}`",
+                dependency.name,
+                dependency.version.as_deref().unwrap_or("*")
+            )),
+            Err(e) => check.warnings.push(format!(
+                "Could not check dependency '{}' against crates.io: {:#}",
+                dependency.name, e
+            )),
+        }
+    }
+    check
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    fn tokio() -> CrateMetadata {
+        let features = ["full", "macros", "rt", "rt-multi-thread", "sync", "time"];
+        CrateMetadata {
+            name: "tokio".to_string(),
+            version: "1.47.1".to_string(),
+            features: features
+                .iter()
+                .map(|f| (f.to_string(), Vec::new()))
+                .collect::<BTreeMap<_, _>>(),
+            ..Default::default()
+        }
+    }
+
+    fn dependency(features: &[&str]) -> Dependency {
+        Dependency {
+            name: "tokio".to_string(),
+            version: Some("1".to_string()),
+            features: features.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn corrects_near_misses() {
+        let mut dep = dependency(&["fulll", "rt_multi_thread", "Macros", "default"]);
+        let mut check = DependencyCheck::default();
+        check_features(&mut dep, &tokio(), &mut check);
+        assert_eq!(
+            dep.features,
+            ["full", "rt-multi-thread", "macros", "default"]
+        );
+        assert_eq!(check.corrections.len(), 3);
+        assert!(check.problems.is_empty());
+    }
+
+    #[test]
+    fn drops_and_reports_unknown_features() {
+        let mut dep = dependency(&["sync", "async-std-compat", "tim"]);
+        let mut check = DependencyCheck::default();
+        check_features(&mut dep, &tokio(), &mut check);
+        assert_eq!(dep.features, ["sync", "time"]);
+        assert_eq!(
+            check.problems,
+            [
+                "tokio 1.47.1 has no feature(s) `async-std-compat`; its features are: full, macros, rt, rt-multi-thread, sync, time"
+            ]
+        );
+    }
+
+    #[test]
+    fn features_are_kept_once() {
+        let mut dep = dependency(&["full", "macros", "fulll", "full", "Macros"]);
+        let mut check = DependencyCheck::default();
+        check_features(&mut dep, &tokio(), &mut check);
+        assert_eq!(dep.features, ["full", "macros"]);
+    }
+
+    #[test]
+    fn ambiguous_typos_are_not_guessed() {
+        assert_eq!(closest_feature(&tokio(), "rtt"), Some("rt"));
+        assert_eq!(closest_feature(&tokio(), "tme"), Some("time"));
+        let mut metadata = tokio();
+        metadata.features.insert("rts".to_string(), Vec::new());
+        assert_eq!(closest_feature(&metadata, "rtt"), None);
+    }
+
+    #[test]
+    fn edit_distance_counts_single_edits() {
+        assert_eq!(edit_distance("full", "fulll"), 1);
+        assert_eq!(edit_distance("sync", "snyc"), 2);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+}
@@ -0,0 +1,517 @@
+use serde::{Deserialize, Serialize};
+
+/// A compiler message from `cargo build --message-format=json`.
+#[derive(Serialize, Debug, Clone)]
+pub struct Diagnostic {
+    /// `error`, `warning`, `note`, ...
+    pub level: String,
+    /// The error code, e.g. `E0599`.
+    pub code: Option<String>,
+    pub message: String,
+    pub primary_span: Option<DiagnosticSpan>,
+    /// Concrete code changes rustc proposes, from the diagnostic and its children.
+    pub suggestions: Vec<Suggestion>,
+    /// Name of the crate being compiled when the message was emitted.
+    pub crate_name: String,
+    /// The message as rustc would print it on a terminal.
+    pub rendered: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DiagnosticSpan {
+    pub file_name: String,
+    pub byte_start: usize,
+    pub byte_end: usize,
+    pub line_start: usize,
+    pub line_end: usize,
+    pub column_start: usize,
+    pub column_end: usize,
+    pub label: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Suggestion {
+    pub message: String,
+    pub span: DiagnosticSpan,
+    pub replacement: String,
+    /// `MachineApplicable`, `MaybeIncorrect`, `HasPlaceholders` or `Unspecified`.
+    pub applicability: Option<String>,
+    /// Suggestions of one diagnostic that share a group came from the same
+    /// message and only make sense applied together.
+    pub group: usize,
+}
+
+impl Suggestion {
+    pub fn is_machine_applicable(&self) -> bool {
+        self.applicability.as_deref() == Some("MachineApplicable")
+    }
+}
+
+impl Diagnostic {
+    pub fn is_error(&self) -> bool {
+        self.level == "error"
+    }
+}
+
+// Raw shapes of the JSON cargo prints, trimmed down to what we use.
+
+#[derive(Deserialize)]
+struct CargoMessage {
+    reason: String,
+    target: Option<CargoTarget>,
+    message: Option<RustcMessage>,
+}
+
+#[derive(Deserialize)]
+struct CargoTarget {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct RustcMessage {
+    message: String,
+    level: String,
+    code: Option<RustcCode>,
+    #[serde(default)]
+    spans: Vec<RustcSpan>,
+    #[serde(default)]
+    children: Vec<RustcMessage>,
+    rendered: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RustcCode {
+    code: String,
+}
+
+#[derive(Deserialize)]
+struct RustcSpan {
+    file_name: String,
+    byte_start: usize,
+    byte_end: usize,
+    line_start: usize,
+    line_end: usize,
+    column_start: usize,
+    column_end: usize,
+    is_primary: bool,
+    label: Option<String>,
+    suggested_replacement: Option<String>,
+    suggestion_applicability: Option<String>,
+}
+
+impl RustcSpan {
+    fn to_span(&self) -> DiagnosticSpan {
+        DiagnosticSpan {
+            file_name: self.file_name.clone(),
+            byte_start: self.byte_start,
+            byte_end: self.byte_end,
+            line_start: self.line_start,
+            line_end: self.line_end,
+            column_start: self.column_start,
+            column_end: self.column_end,
+            label: self.label.clone(),
+        }
+    }
+}
+
+/// Collects the suggested replacements of a message and all of its children,
+/// giving each message that suggests something its own group.
+fn collect_suggestions(message: &RustcMessage, suggestions: &mut Vec<Suggestion>) {
+    let group = suggestions.last().map_or(0, |s| s.group + 1);
+    for span in &message.spans {
+        if let Some(replacement) = &span.suggested_replacement {
+            suggestions.push(Suggestion {
+                message: message.message.clone(),
+                span: span.to_span(),
+                replacement: replacement.clone(),
+                applicability: span.suggestion_applicability.clone(),
+                group,
+            });
+        }
+    }
+    for child in &message.children {
+        collect_suggestions(child, suggestions);
+    }
+}
+
+fn parse_message(line: &str) -> Option<CargoMessage> {
+    if !line.starts_with('{') {
+        return None;
+    }
+    serde_json::from_str(line).ok()
+}
+
+/// Parses one line of cargo's JSON output. Returns `None` for lines that are
+/// not compiler messages (artifacts, build-script output, plain text).
+pub fn parse_cargo_line(line: &str) -> Option<Diagnostic> {
+    let parsed = parse_message(line)?;
+    if parsed.reason != "compiler-message" {
+        return None;
+    }
+    let message = parsed.message?;
+
+    let mut suggestions = Vec::new();
+    collect_suggestions(&message, &mut suggestions);
+
+    Some(Diagnostic {
+        level: message.level.clone(),
+        code: message.code.as_ref().map(|c| c.code.clone()),
+        message: message.message.clone(),
+        primary_span: message
+            .spans
+            .iter()
+            .find(|s| s.is_primary)
+            .map(RustcSpan::to_span),
+        suggestions,
+        crate_name: parsed.target.map(|t| t.name).unwrap_or_default(),
+        rendered: message.rendered,
+    })
+}
+
+/// What a line of cargo's JSON output looks like to a human: the rendered text
+/// for compiler messages, nothing for other JSON records, and the line itself otherwise.
+pub fn human_readable(line: &str) -> Option<String> {
+    match parse_message(line) {
+        Some(parsed) if parsed.reason == "compiler-message" => parsed
+            .message
+            .and_then(|m| m.rendered)
+            .map(|r| r.trim_end().to_string()),
+        Some(_) => None,
+        None => Some(line.to_string()),
+    }
+}
+
+/// Summarises the errors for the repair prompt, one line per error with its location,
+/// followed by rustc's full rendering.
+pub fn format_for_prompt(diagnostics: &[Diagnostic]) -> String {
+    let errors: Vec<&Diagnostic> = diagnostics.iter().filter(|d| d.is_error()).collect();
+
+    let mut summary = String::new();
+    for d in &errors {
+        let code = d
+            .code
+            .as_deref()
+            .map(|c| format!("[{}]", c))
+            .unwrap_or_default();
+        let location = d
+            .primary_span
+            .as_ref()
+            .map(|s| format!(" at {}:{}:{}", s.file_name, s.line_start, s.column_start))
+            .unwrap_or_default();
+        summary.push_str(&format!("- error{}{}: {}\n", code, location, d.message));
+        for s in &d.suggestions {
+            summary.push_str(&format!(
+                "  suggestion ({}): replace line {} col {}-{} with `{}`\n",
+                s.message, s.span.line_start, s.span.column_start, s.span.column_end, s.replacement
+            ));
+        }
+    }
+
+    let rendered = errors
+        .iter()
+        .filter_map(|d| d.rendered.as_deref())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!("ERRORS:\n{}\nFULL COMPILER OUTPUT:\n{}", summary, rendered)
+}
+
+/// The file generated code lives in, as rustc names it in spans.
+const MAIN_FILE: &str = "src/main.rs";
+
+/// Applies rustc's machine-applicable suggestions for errors in `src/main.rs` to
+/// `code`. Warnings are left alone: fixing them can't make the build pass.
+/// Returns `None` when there is nothing to apply. A multipart suggestion is
+/// applied whole or not at all, and suggestions overlapping one already
+/// accepted are skipped.
+pub fn apply_machine_applicable(code: &str, diagnostics: &[Diagnostic]) -> Option<String> {
+    let mut accepted: Vec<&Suggestion> = Vec::new();
+    for d in diagnostics.iter().filter(|d| d.is_error()) {
+        let mut groups: Vec<usize> = d.suggestions.iter().map(|s| s.group).collect();
+        groups.dedup();
+        for group in groups {
+            let parts: Vec<&Suggestion> =
+                d.suggestions.iter().filter(|s| s.group == group).collect();
+            let applicable = parts.iter().all(|s| {
+                s.is_machine_applicable() && s.span.file_name == MAIN_FILE && in_bounds(code, s)
+            });
+            let conflicting = parts.iter().enumerate().any(|(i, s)| {
+                parts[..i]
+                    .iter()
+                    .chain(&accepted)
+                    .any(|other| conflicts(s, other))
+            });
+            if applicable && !conflicting {
+                accepted.extend(parts);
+            }
+        }
+    }
+    if accepted.is_empty() {
+        return None;
+    }
+
+    // Apply from the end of the file so earlier byte offsets stay valid.
+    accepted.sort_by(|a, b| {
+        (b.span.byte_start, b.span.byte_end).cmp(&(a.span.byte_start, a.span.byte_end))
+    });
+    let mut fixed = code.to_string();
+    for s in accepted {
+        fixed.replace_range(s.span.byte_start..s.span.byte_end, &s.replacement);
+    }
+
+    (fixed != code).then_some(fixed)
+}
+
+fn in_bounds(code: &str, s: &Suggestion) -> bool {
+    let (start, end) = (s.span.byte_start, s.span.byte_end);
+    start <= end && end <= code.len() && code.is_char_boundary(start) && code.is_char_boundary(end)
+}
+
+/// Whether two suggestions can't both be applied. Touching spans only conflict
+/// when one is an insertion, since two insertions at one offset have no defined order.
+fn conflicts(a: &Suggestion, b: &Suggestion) -> bool {
+    let (a_start, a_end) = (a.span.byte_start, a.span.byte_end);
+    let (b_start, b_end) = (b.span.byte_start, b.span.byte_end);
+    let inserts = a_start == a_end || b_start == b_end;
+    (a_start < b_end && b_start < a_end) || (inserts && (a_end == b_start || b_end == a_start))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// One line of `cargo build --message-format=json` for a rustc message.
+    fn cargo_line(level: &str, message: serde_json::Value) -> String {
+        let mut message = message;
+        message["level"] = json!(level);
+        json!({
+            "reason": "compiler-message",
+            "target": {"name": "sandbox"},
+            "message": message,
+        })
+        .to_string()
+    }
+
+    fn span(start: usize, end: usize) -> DiagnosticSpan {
+        DiagnosticSpan {
+            file_name: MAIN_FILE.to_string(),
+            byte_start: start,
+            byte_end: end,
+            line_start: 1,
+            line_end: 1,
+            column_start: start + 1,
+            column_end: end + 1,
+            label: None,
+        }
+    }
+
+    /// An error or warning where each fix is a suggestion of its own.
+    fn diagnostic(level: &str, fixes: &[(usize, usize, &str)]) -> Diagnostic {
+        Diagnostic {
+            level: level.to_string(),
+            code: None,
+            message: "fix me".to_string(),
+            primary_span: None,
+            suggestions: fixes
+                .iter()
+                .enumerate()
+                .map(|(group, (start, end, replacement))| Suggestion {
+                    message: "try this".to_string(),
+                    span: span(*start, *end),
+                    replacement: replacement.to_string(),
+                    applicability: Some("MachineApplicable".to_string()),
+                    group,
+                })
+                .collect(),
+            crate_name: "sandbox".to_string(),
+            rendered: None,
+        }
+    }
+
+    #[test]
+    fn parses_compiler_messages_with_child_suggestions() {
+        let line = cargo_line(
+            "error",
+            json!({
+                "message": "cannot find type `HashMap` in this scope",
+                "code": {"code": "E0412"},
+                "spans": [{
+                    "file_name": "src/main.rs", "byte_start": 20, "byte_end": 27,
+                    "line_start": 2, "line_end": 2, "column_start": 12, "column_end": 19,
+                    "is_primary": true, "label": "not found in this scope",
+                    "suggested_replacement": null, "suggestion_applicability": null
+                }],
+                "children": [{
+                    "message": "consider importing this struct", "level": "help",
+                    "spans": [{
+                        "file_name": "src/main.rs", "byte_start": 0, "byte_end": 0,
+                        "line_start": 1, "line_end": 1, "column_start": 1, "column_end": 1,
+                        "is_primary": true, "label": null,
+                        "suggested_replacement": "use std::collections::HashMap;\n",
+                        "suggestion_applicability": "MaybeIncorrect"
+                    }],
+                    "children": []
+                }],
+                "rendered": "error[E0412]: cannot find type `HashMap` in this scope"
+            }),
+        );
+
+        let diagnostic = parse_cargo_line(&line).unwrap();
+        assert!(diagnostic.is_error());
+        assert_eq!(diagnostic.code.as_deref(), Some("E0412"));
+        assert_eq!(diagnostic.crate_name, "sandbox");
+        assert_eq!(diagnostic.primary_span.unwrap().line_start, 2);
+        assert_eq!(diagnostic.suggestions.len(), 1);
+        assert_eq!(
+            diagnostic.suggestions[0].message,
+            "consider importing this struct"
+        );
+        assert!(!diagnostic.suggestions[0].is_machine_applicable());
+    }
+
+    #[test]
+    fn other_lines_are_not_diagnostics() {
+        assert!(parse_cargo_line(r#"{"reason": "build-finished", "success": true}"#).is_none());
+        assert!(parse_cargo_line("   Compiling sandbox v0.1.0").is_none());
+        assert!(parse_cargo_line("{not json").is_none());
+    }
+
+    #[test]
+    fn prompt_lists_errors_with_locations_and_suggestions() {
+        let mut error = diagnostic("error", &[(4, 7, "bar")]);
+        error.code = Some("E0425".to_string());
+        error.primary_span = Some(span(4, 7));
+        error.rendered = Some("error[E0425]: rendered".to_string());
+        let mut warning = diagnostic("warning", &[]);
+        warning.message = "unused variable".to_string();
+        warning.rendered = Some("warning: rendered".to_string());
+
+        let prompt = format_for_prompt(&[warning, error]);
+        assert!(prompt.contains("- error[E0425] at src/main.rs:1:5: fix me"));
+        assert!(prompt.contains("replace line 1 col 5-8 with `bar`"));
+        assert!(prompt.contains("error[E0425]: rendered"));
+        assert!(!prompt.contains("unused variable"));
+        assert!(!prompt.contains("warning: rendered"));
+    }
+
+    #[test]
+    fn applies_several_suggestions_from_the_end() {
+        let code = "let a = 1; let b = 2;";
+        let diagnostics = [
+            diagnostic("error", &[(4, 5, "x")]),
+            diagnostic("error", &[(15, 16, "y"), (0, 0, "// fixed\n")]),
+        ];
+        assert_eq!(
+            apply_machine_applicable(code, &diagnostics).unwrap(),
+            "// fixed\nlet x = 1; let y = 2;"
+        );
+    }
+
+    #[test]
+    fn skips_overlapping_and_out_of_bounds_suggestions() {
+        let code = "fn main() {}";
+        let diagnostics = [diagnostic(
+            "error",
+            &[
+                (3, 7, "run"),
+                (0, 4, "pub fn m"),
+                (20, 25, "past the end"),
+                (9, 8, "reversed"),
+            ],
+        )];
+        assert_eq!(
+            apply_machine_applicable(code, &diagnostics).unwrap(),
+            "fn run() {}"
+        );
+    }
+
+    #[test]
+    fn inserts_only_once_at_the_same_offset() {
+        let code = "fn main() {}";
+        let diagnostics = [
+            diagnostic("error", &[(0, 0, "use a;\n")]),
+            diagnostic("error", &[(0, 0, "use b;\n")]),
+        ];
+        let fixed = apply_machine_applicable(code, &diagnostics).unwrap();
+        assert!(fixed == "use a;\nfn main() {}" || fixed == "use b;\nfn main() {}");
+    }
+
+    #[test]
+    fn applies_multipart_suggestions_whole_or_not_at_all() {
+        let code = "let a = f(1, 2);";
+        // Wrapping the arguments in a tuple takes both parts; the first
+        // overlaps the rename, which was accepted before it.
+        let mut wrap = diagnostic("error", &[(10, 10, "("), (14, 14, ")")]);
+        wrap.suggestions[1].group = 0;
+        let rename = diagnostic("error", &[(8, 11, "g(0")]);
+        assert_eq!(
+            apply_machine_applicable(code, &[rename.clone(), wrap.clone()]).unwrap(),
+            "let a = g(0, 2);"
+        );
+        assert_eq!(
+            apply_machine_applicable(code, &[wrap.clone()]).unwrap(),
+            "let a = f((1, 2));"
+        );
+
+        // One part that can't be applied holds back the rest.
+        wrap.suggestions[1].applicability = Some("MaybeIncorrect".to_string());
+        assert_eq!(apply_machine_applicable(code, &[wrap]), None);
+    }
+
+    #[test]
+    fn groups_suggestions_by_the_message_they_came_from() {
+        let line = cargo_line(
+            "error",
+            json!({
+                "message": "mismatched types",
+                "spans": [],
+                "children": [{
+                    "message": "use a tuple", "level": "help",
+                    "spans": [
+                        {
+                            "file_name": "src/main.rs", "byte_start": 10, "byte_end": 10,
+                            "line_start": 1, "line_end": 1, "column_start": 11, "column_end": 11,
+                            "is_primary": true, "label": null,
+                            "suggested_replacement": "(",
+                            "suggestion_applicability": "MachineApplicable"
+                        },
+                        {
+                            "file_name": "src/main.rs", "byte_start": 14, "byte_end": 14,
+                            "line_start": 1, "line_end": 1, "column_start": 15, "column_end": 15,
+                            "is_primary": true, "label": null,
+                            "suggested_replacement": ")",
+                            "suggestion_applicability": "MachineApplicable"
+                        }
+                    ],
+                    "children": []
+                }, {
+                    "message": "call another function", "level": "help",
+                    "spans": [{
+                        "file_name": "src/main.rs", "byte_start": 8, "byte_end": 9,
+                        "line_start": 1, "line_end": 1, "column_start": 9, "column_end": 10,
+                        "is_primary": true, "label": null,
+                        "suggested_replacement": "g",
+                        "suggestion_applicability": "MachineApplicable"
+                    }],
+                    "children": []
+                }],
+                "rendered": null
+            }),
+        );
+
+        let diagnostic = parse_cargo_line(&line).unwrap();
+        let groups: Vec<usize> = diagnostic.suggestions.iter().map(|s| s.group).collect();
+        assert_eq!(groups, [0, 0, 1]);
+    }
+
+    #[test]
+    fn ignores_warnings_and_other_files() {
+        let code = "fn main() {}";
+        let mut elsewhere = diagnostic("error", &[(0, 2, "pub fn")]);
+        elsewhere.suggestions[0].span.file_name = "src/other_main.rs".to_string();
+        let warning = diagnostic("warning", &[(0, 2, "pub fn")]);
+        assert_eq!(apply_machine_applicable(code, &[elsewhere, warning]), None);
+    }
+}
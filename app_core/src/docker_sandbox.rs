@@ -0,0 +1,417 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+use bollard::{
+    Docker,
+    container::LogOutput,
+    errors::Error as DockerError,
+    models::{ContainerCreateBody, HostConfig},
+    query_parameters::{
+        CreateContainerOptions, CreateImageOptions, LogsOptions, RemoveContainerOptions,
+        StartContainerOptions, WaitContainerOptions,
+    },
+};
+use futures_util::StreamExt;
+use serde::Deserialize;
+
+use crate::{
+    events::{QueryEvent, QueryEvents},
+    diagnostics::{self, Diagnostic},
+    sandbox::{RunOptions, RunOutcome, SandboxExecutor, SandboxPaths, SandboxResult, human_output},
+};
+
+/// Where the project is mounted inside the container.
+const CONTAINER_WORKDIR: &str = "/sandbox";
+/// Where the shared target directory from the build cache is mounted.
+const CONTAINER_TARGET_DIR: &str = "/target";
+/// Where the build cache's cargo home is mounted, so fetched crates survive between runs.
+const CONTAINER_CARGO_HOME: &str = "/cargo-home";
+/// File in the project directory that is fed to the program's stdin.
+const STDIN_FILE: &str = ".stdin";
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct DockerSandboxSettings {
+    /// Image with a Rust toolchain, e.g. `rust:latest`.
+    #[serde(default = "default_image")]
+    pub image: String,
+    #[serde(default = "default_memory_mb")]
+    pub memory_mb: i64,
+    #[serde(default = "default_cpus")]
+    pub cpus: f64,
+    #[serde(default = "default_pids_limit")]
+    pub pids_limit: i64,
+    /// Wall-clock limit for each container run (fetch and build), in seconds.
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_image() -> String {
+    "rust:latest".to_string()
+}
+
+fn default_memory_mb() -> i64 {
+    2048
+}
+
+fn default_cpus() -> f64 {
+    2.0
+}
+
+fn default_pids_limit() -> i64 {
+    256
+}
+
+fn default_timeout_secs() -> u64 {
+    300
+}
+
+impl Default for DockerSandboxSettings {
+    fn default() -> Self {
+        Self {
+            image: default_image(),
+            memory_mb: default_memory_mb(),
+            cpus: default_cpus(),
+            pids_limit: default_pids_limit(),
+            timeout_secs: default_timeout_secs(),
+        }
+    }
+}
+
+/// Output of one container run.
+#[derive(Default)]
+struct ContainerRun {
+    exit_code: i64,
+    stdout: String,
+    stderr: String,
+    runtime: Duration,
+    timed_out: bool,
+    truncated: bool,
+}
+
+impl ContainerRun {
+    /// Cargo writes its diagnostics to stderr, so that's what a failed build reports.
+    fn failed(&self) -> bool {
+        self.exit_code != 0 || self.timed_out
+    }
+}
+
+/// Builds generated code inside a throwaway container. Dependencies are fetched
+/// with network access first; the build itself runs with no network, a read-only
+/// root filesystem, no capabilities and CPU/memory/pids limits. Only the project,
+/// the shared target directory and the sandbox cargo home are writable.
+pub struct DockerExecutor {
+    docker: Docker,
+    settings: DockerSandboxSettings,
+}
+
+impl DockerExecutor {
+    /// Connects using the local defaults, which honour `DOCKER_HOST`.
+    pub fn new(settings: DockerSandboxSettings) -> Result<Self> {
+        let docker = Docker::connect_with_local_defaults()
+            .context("Failed to connect to Docker for the sandbox")?;
+        Ok(Self { docker, settings })
+    }
+
+    /// Pulls the toolchain image if it isn't available locally.
+    async fn ensure_image(&self) -> Result<()> {
+        if self.docker.inspect_image(&self.settings.image).await.is_ok() {
+            return Ok(());
+        }
+        println!("INFO: Pulling sandbox image: '{}'...", self.settings.image);
+        let mut stream = self.docker.create_image(
+            Some(CreateImageOptions {
+                from_image: Some(self.settings.image.clone()),
+                ..Default::default()
+            }),
+            None,
+            None,
+        );
+        while let Some(result) = stream.next().await {
+            result.context("Failed to pull sandbox image")?;
+        }
+        Ok(())
+    }
+
+    fn host_config(&self, paths: &SandboxPaths, network: bool) -> Result<HostConfig> {
+        let bind = |host: &Path, container: &str| -> Result<String> {
+            let host = host.to_str().context("Sandbox path is not valid UTF-8")?;
+            Ok(format!("{}:{}", host, container))
+        };
+        let mut tmpfs = HashMap::new();
+        tmpfs.insert("/tmp".to_string(), "rw,noexec,nosuid,size=512m".to_string());
+
+        Ok(HostConfig {
+            binds: Some(vec![
+                bind(&paths.project_dir, CONTAINER_WORKDIR)?,
+                bind(&paths.target_dir, CONTAINER_TARGET_DIR)?,
+                bind(&paths.cargo_home, CONTAINER_CARGO_HOME)?,
+            ]),
+            memory: Some(self.settings.memory_mb * 1024 * 1024),
+            memory_swap: Some(self.settings.memory_mb * 1024 * 1024),
+            nano_cpus: Some((self.settings.cpus * 1_000_000_000.0) as i64),
+            pids_limit: Some(self.settings.pids_limit),
+            readonly_rootfs: Some(true),
+            tmpfs: Some(tmpfs),
+            cap_drop: Some(vec!["ALL".to_string()]),
+            security_opt: Some(vec!["no-new-privileges".to_string()]),
+            network_mode: Some(if network { "bridge" } else { "none" }.to_string()),
+            ..Default::default()
+        })
+    }
+
+    /// Runs one command in a fresh container, streaming its output, and removes the container.
+    /// Each of stdout and stderr keeps at most `output_limit` bytes.
+    async fn run_container(
+        &self,
+        paths: &SandboxPaths,
+        cmd: &[&str],
+        network: bool,
+        timeout: Duration,
+        output_limit: usize,
+        events: &QueryEvents,
+    ) -> Result<ContainerRun> {
+        let config = ContainerCreateBody {
+            image: Some(self.settings.image.clone()),
+            cmd: Some(cmd.iter().map(|s| s.to_string()).collect()),
+            working_dir: Some(CONTAINER_WORKDIR.to_string()),
+            env: Some(vec![
+                format!("CARGO_TARGET_DIR={}", CONTAINER_TARGET_DIR),
+                format!("CARGO_HOME={}", CONTAINER_CARGO_HOME),
+            ]),
+            user: container_user(&paths.project_dir).await?,
+            network_disabled: Some(!network),
+            host_config: Some(self.host_config(paths, network)?),
+            ..Default::default()
+        };
+
+        let id = self
+            .docker
+            .create_container(None::<CreateContainerOptions>, config)
+            .await
+            .context("Failed to create sandbox container")?
+            .id;
+
+        let run = self
+            .start_and_collect(&id, timeout, output_limit, events)
+            .await;
+
+        let remove = self
+            .docker
+            .remove_container(
+                &id,
+                Some(RemoveContainerOptions {
+                    force: true,
+                    ..Default::default()
+                }),
+            )
+            .await;
+        if let Err(e) = remove {
+            println!("Warning: Failed to remove sandbox container {}: {}", id, e);
+        }
+
+        run
+    }
+
+    async fn start_and_collect(
+        &self,
+        id: &str,
+        timeout: Duration,
+        output_limit: usize,
+        events: &QueryEvents,
+    ) -> Result<ContainerRun> {
+        self.docker
+            .start_container(id, None::<StartContainerOptions>)
+            .await
+            .context("Failed to start sandbox container")?;
+
+        let mut logs = self.docker.logs(
+            id,
+            Some(LogsOptions {
+                follow: true,
+                stdout: true,
+                stderr: true,
+                ..Default::default()
+            }),
+        );
+
+        let mut run = ContainerRun::default();
+        let started = Instant::now();
+        let collect = async {
+            while let Some(chunk) = logs.next().await {
+                let chunk = chunk.context("Failed to read sandbox container logs")?;
+                let is_stdout = matches!(chunk, LogOutput::StdOut { .. });
+                let text = String::from_utf8_lossy(&chunk.into_bytes()).into_owned();
+                for line in text.lines() {
+                    // Stdout carries cargo's JSON messages; show those the way a terminal would.
+                    let line = if is_stdout {
+                        diagnostics::human_readable(line)
+                    } else {
+                        Some(line.to_string())
+                    };
+                    if let Some(line) = line {
+                        events.emit(QueryEvent::BuildOutput { line });
+                    }
+                }
+                let target = if is_stdout {
+                    &mut run.stdout
+                } else {
+                    &mut run.stderr
+                };
+                run.truncated |= !push_capped(target, &text, output_limit);
+            }
+            Ok::<_, anyhow::Error>(())
+        };
+
+        let collected = tokio::time::timeout(timeout, collect).await;
+        run.runtime = started.elapsed();
+        if let Ok(result) = collected {
+            result?;
+        } else {
+            run.stderr.push_str(&format!(
+                "\nSandbox timed out after {} seconds.\n",
+                timeout.as_secs()
+            ));
+            run.exit_code = -1;
+            run.timed_out = true;
+            return Ok(run);
+        }
+
+        // Bollard reports a non-zero exit code as an error.
+        run.exit_code = match self
+            .docker
+            .wait_container(id, None::<WaitContainerOptions>)
+            .next()
+            .await
+        {
+            Some(Ok(response)) => response.status_code,
+            Some(Err(DockerError::DockerContainerWaitError { code, .. })) => code,
+            Some(Err(e)) => return Err(anyhow!("Failed to wait for sandbox container: {}", e)),
+            None => -1,
+        };
+
+        Ok(run)
+    }
+}
+
+/// Appends as much of `text` as fits in `limit` bytes. Returns false if anything was cut.
+fn push_capped(target: &mut String, text: &str, limit: usize) -> bool {
+    let room = limit.saturating_sub(target.len());
+    if text.len() <= room {
+        target.push_str(text);
+        return true;
+    }
+    let mut end = room;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    target.push_str(&text[..end]);
+    false
+}
+
+/// Runs the container as the owner of the project directory, so everything it
+/// writes to the bind mounts stays owned by us and can be evicted later.
+#[cfg(unix)]
+async fn container_user(project_dir: &Path) -> Result<Option<String>> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = tokio::fs::metadata(project_dir)
+        .await
+        .context("Failed to read sandbox directory owner")?;
+    Ok(Some(format!("{}:{}", metadata.uid(), metadata.gid())))
+}
+
+#[cfg(not(unix))]
+async fn container_user(_project_dir: &Path) -> Result<Option<String>> {
+    Ok(None)
+}
+
+#[async_trait]
+impl SandboxExecutor for DockerExecutor {
+    async fn build(&self, paths: &SandboxPaths, events: &QueryEvents) -> Result<SandboxResult> {
+        self.ensure_image().await?;
+        let timeout = Duration::from_secs(self.settings.timeout_secs);
+
+        // Phase 1: fetch dependencies, the only step with network access.
+        let fetch = self
+            .run_container(paths, &["cargo", "fetch"], true, timeout, usize::MAX, events)
+            .await?;
+        if fetch.failed() {
+            return Ok(SandboxResult {
+                success: false,
+                output: fetch.stderr,
+                diagnostics: Vec::new(),
+                run: None,
+                manifest: None,
+            });
+        }
+
+        // Phase 2: build offline, so build scripts and proc macros can't reach the network.
+        let build = self
+            .run_container(
+                paths,
+                &["cargo", "build", "--offline", "--message-format=json"],
+                false,
+                timeout,
+                usize::MAX,
+                events,
+            )
+            .await?;
+        let success = !build.failed();
+        let diagnostics: Vec<Diagnostic> = build
+            .stdout
+            .lines()
+            .filter_map(diagnostics::parse_cargo_line)
+            .collect();
+
+        Ok(SandboxResult {
+            success,
+            output: if success {
+                String::new()
+            } else {
+                human_output(&diagnostics, &build.stderr)
+            },
+            diagnostics,
+            run: None,
+            manifest: None,
+        })
+    }
+
+    async fn run(&self, paths: &SandboxPaths, options: &RunOptions) -> Result<RunOutcome> {
+        // Stdin goes through a file so we don't have to attach to the container.
+        tokio::fs::write(
+            paths.project_dir.join(STDIN_FILE),
+            options.stdin.as_deref().unwrap_or_default(),
+        )
+        .await
+        .context("Failed to write sandbox stdin")?;
+
+        let command = format!(
+            "exec {}/{} < {}",
+            CONTAINER_TARGET_DIR,
+            SandboxPaths::BINARY,
+            STDIN_FILE
+        );
+        let run = self
+            .run_container(
+                paths,
+                &["sh", "-c", &command],
+                false,
+                Duration::from_secs(options.timeout_secs),
+                options.max_output_bytes,
+                &QueryEvents::none(),
+            )
+            .await?;
+
+        Ok(RunOutcome {
+            exit_code: (!run.timed_out).then_some(run.exit_code as i32),
+            stdout: run.stdout,
+            stderr: run.stderr,
+            runtime_ms: run.runtime.as_millis() as u64,
+            // 137 is SIGKILL, which is also what the OOM killer sends.
+            killed: run.timed_out || run.exit_code == 137,
+            truncated: run.truncated,
+        })
+    }
+}
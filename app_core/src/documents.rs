@@ -0,0 +1,300 @@
+//! The document registry: everything ingested into the knowledge base, grouped
+//! by the `document_id` each chunk carries. The chunk payloads are the registry,
+//! so there is no second store to keep in sync.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{Result, bail};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    AppState,
+    crate_source::ingest_crate,
+    ingestion::{DocumentFormat, IngestReport, is_url, replace_document},
+    jobs::JobProgress,
+    payload::{KnowledgeChunk, derived_id},
+    qdrant::KNOWLEDGE_BASE_COLLECTION,
+    vector_store::{
+        PayloadFilter, PayloadUpdate, PointPayload, PointSelector, StoredPoint, VectorStore,
+    },
+    web_scraper::scrape_website,
+};
+
+/// Page size used when scrolling through the knowledge base.
+const SCROLL_PAGE: u32 = 256;
+
+/// One ingested document.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DocumentSummary {
+    pub document_id: String,
+    /// A URL, a file name, a crate directory, or `pasted text`.
+    pub source: String,
+    pub chunks: usize,
+    /// Unix timestamp of the most recent ingest, in seconds.
+    pub ingested_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crate_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crate_version: Option<String>,
+}
+
+fn document_filter(document_id: &str) -> PayloadFilter {
+    PayloadFilter::new().with_match("document_id", document_id)
+}
+
+async fn scroll_all(
+    store: &dyn VectorStore,
+    filter: Option<PayloadFilter>,
+) -> Result<Vec<StoredPoint>> {
+    let mut points = Vec::new();
+    let mut offset = None;
+    loop {
+        let page = store
+            .scroll(
+                KNOWLEDGE_BASE_COLLECTION,
+                filter.clone(),
+                SCROLL_PAGE,
+                offset,
+            )
+            .await?;
+        points.extend(page.points);
+        match page.next_offset {
+            Some(next) => offset = Some(next),
+            None => return Ok(points),
+        }
+    }
+}
+
+/// All stored points of a document.
+pub async fn document_points(
+    store: &dyn VectorStore,
+    document_id: &str,
+) -> Result<Vec<StoredPoint>> {
+    scroll_all(store, Some(document_filter(document_id))).await
+}
+
+/// The id of a document ingested from `source`, if there is one.
+pub async fn find_document_by_source(
+    store: &dyn VectorStore,
+    source: &str,
+) -> Result<Option<String>> {
+    let filter = PayloadFilter::new().with_match("source", source);
+    Ok(scroll_all(store, Some(filter))
+        .await?
+        .iter()
+        .filter_map(|p| KnowledgeChunk::from_payload(&p.payload).ok())
+        .map(|chunk| chunk.document_id)
+        .find(|id| !id.is_empty()))
+}
+
+/// Gives chunks stored before documents had ids the id of their source's
+/// document, so they can be listed, re-ingested and deleted like any other.
+/// Sources without a registered document get an id derived from the source,
+/// which keeps running this again harmless. Chunks are grouped by source
+/// alone, so legacy chunks stored without a source all land in one document.
+/// This scrolls the whole knowledge base, so it runs as an explicit admin
+/// action rather than at startup. Returns how many chunks changed.
+pub async fn migrate_legacy_chunks(store: &dyn VectorStore) -> Result<usize> {
+    let mut legacy: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for point in scroll_all(store, None).await? {
+        let Ok(chunk) = KnowledgeChunk::from_payload(&point.payload) else {
+            continue;
+        };
+        if chunk.document_id.is_empty() {
+            legacy.entry(chunk.source).or_default().push(point.id);
+        }
+    }
+
+    let mut migrated = 0;
+    let mut updates = Vec::new();
+    for (source, ids) in legacy {
+        let document_id = match find_document_by_source(store, &source).await? {
+            Some(id) => id,
+            None => derived_id(&format!("legacy document\n{}", source)),
+        };
+        migrated += ids.len();
+        let mut payload = PointPayload::new();
+        payload.insert("document_id".to_string(), Value::String(document_id));
+        updates.push(PayloadUpdate { ids, payload });
+    }
+    store
+        .set_payloads(KNOWLEDGE_BASE_COLLECTION, updates)
+        .await?;
+    if migrated > 0 {
+        println!(
+            "INFO: Assigned document ids to {} chunks stored without one",
+            migrated
+        );
+    }
+    Ok(migrated)
+}
+
+/// Lists every document in the knowledge base, sorted by source.
+pub async fn list_documents(store: &dyn VectorStore) -> Result<Vec<DocumentSummary>> {
+    let mut documents: BTreeMap<String, DocumentSummary> = BTreeMap::new();
+    for point in scroll_all(store, None).await? {
+        let Ok(chunk) = KnowledgeChunk::from_payload(&point.payload) else {
+            continue;
+        };
+        let summary = documents
+            .entry(chunk.document_id.clone())
+            .or_insert_with(|| DocumentSummary {
+                document_id: chunk.document_id.clone(),
+                source: chunk.source.clone(),
+                chunks: 0,
+                ingested_at: 0,
+                crate_name: chunk.crate_name.clone(),
+                crate_version: chunk.crate_version.clone(),
+            });
+        summary.chunks += 1;
+        summary.ingested_at = summary.ingested_at.max(chunk.ingested_at);
+    }
+
+    let mut documents: Vec<DocumentSummary> = documents.into_values().collect();
+    documents.sort_by(|a, b| {
+        a.source
+            .cmp(&b.source)
+            .then(a.document_id.cmp(&b.document_id))
+    });
+    Ok(documents)
+}
+
+/// The chunks of a document in their original order. Empty if the document doesn't exist.
+pub async fn document_chunks(
+    store: &dyn VectorStore,
+    document_id: &str,
+) -> Result<Vec<KnowledgeChunk>> {
+    let mut chunks: Vec<KnowledgeChunk> = document_points(store, document_id)
+        .await?
+        .iter()
+        .filter_map(|point| KnowledgeChunk::from_payload(&point.payload).ok())
+        .collect();
+    chunks.sort_by_key(|c| c.chunk_index);
+    Ok(chunks)
+}
+
+/// Removes a document's points from the knowledge base. Returns how many there were.
+pub async fn delete_document(store: &dyn VectorStore, document_id: &str) -> Result<usize> {
+    let ids: Vec<String> = document_points(store, document_id)
+        .await?
+        .into_iter()
+        .map(|point| point.id)
+        .collect();
+    if !ids.is_empty() {
+        store
+            .delete(KNOWLEDGE_BASE_COLLECTION, PointSelector::Ids(ids.clone()))
+            .await?;
+    }
+    Ok(ids.len())
+}
+
+/// Ingests a document again under the same id. `content` replaces the document's
+/// text; without it, URLs are scraped again and crates re-indexed from their
+/// directory. Returns `None` if there is no such document.
+pub async fn reingest_document(
+    state: &AppState,
+    document_id: &str,
+    content: Option<String>,
+    format: Option<DocumentFormat>,
+    progress: &JobProgress,
+) -> Result<Option<IngestReport>> {
+    let existing = document_chunks(state.vector_store.as_ref(), document_id).await?;
+    let Some(first) = existing.first() else {
+        return Ok(None);
+    };
+    let source = first.source.clone();
+
+    if content.is_none() && first.crate_name.is_some() {
+        let summary = ingest_crate(
+            state,
+            Path::new(&source),
+            None,
+            Some(document_id.to_string()),
+            progress,
+        )
+        .await?;
+        return Ok(Some(summary.ingest));
+    }
+
+    let (content, default_format) = match content {
+        Some(content) => (content, DocumentFormat::Auto),
+        None if is_url(&source) => (
+            scrape_website(state, &source, &state.crawl_settings, progress).await?,
+            DocumentFormat::Markdown,
+        ),
+        None => bail!(
+            "Document {} came from {}; send its new content to re-ingest it",
+            document_id,
+            source
+        ),
+    };
+    let report = replace_document(
+        state,
+        document_id,
+        &source,
+        &content,
+        format.unwrap_or(default_format),
+        progress,
+    )
+    .await?;
+    Ok(Some(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        qdrant::ensure_collections_exist,
+        vector_store::{MemoryVectorStore, VectorPoint},
+    };
+
+    async fn store_chunks(store: &dyn VectorStore, chunks: &[KnowledgeChunk]) {
+        let points = chunks
+            .iter()
+            .enumerate()
+            .map(|(i, chunk)| VectorPoint {
+                id: derived_id(&format!("point {}", i)),
+                vector: vec![1.0; crate::qdrant::EMBEDDING_DIMENSIONS as usize],
+                payload: chunk.to_payload().unwrap(),
+            })
+            .collect();
+        store
+            .upsert(KNOWLEDGE_BASE_COLLECTION, points)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn legacy_chunks_join_their_sources_document() {
+        let store = MemoryVectorStore::new();
+        ensure_collections_exist(&store).await.unwrap();
+        store_chunks(
+            &store,
+            &[
+                KnowledgeChunk::new("new", "doc-1", "https://a.example", 0),
+                KnowledgeChunk::new("old", "", "https://a.example", 1),
+                KnowledgeChunk::new("orphan one", "", "notes.md", 0),
+                KnowledgeChunk::new("orphan two", "", "notes.md", 1),
+            ],
+        )
+        .await;
+
+        assert_eq!(migrate_legacy_chunks(&store).await.unwrap(), 3);
+        assert_eq!(migrate_legacy_chunks(&store).await.unwrap(), 0);
+
+        let documents = list_documents(&store).await.unwrap();
+        assert_eq!(documents.len(), 2);
+        assert_eq!(documents[0].document_id, "doc-1");
+        assert_eq!(documents[0].chunks, 2);
+        assert_eq!(documents[1].source, "notes.md");
+        assert_eq!(documents[1].chunks, 2);
+        assert!(!documents[1].document_id.is_empty());
+
+        let deleted = delete_document(&store, &documents[1].document_id)
+            .await
+            .unwrap();
+        assert_eq!(deleted, 2);
+    }
+}
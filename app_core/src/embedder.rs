@@ -0,0 +1,79 @@
+//! Turns text into the vectors the vector store searches by. The application
+//! uses fastembed's model; tests and offline runs use `HashEmbedder`, which
+//! needs no model download or GPU.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use anyhow::{Context, Result, anyhow};
+use fastembed::TextEmbedding;
+
+use crate::qdrant::EMBEDDING_DIMENSIONS;
+
+/// Anything that can embed text into `EMBEDDING_DIMENSIONS`-long vectors.
+/// Embedding is CPU-bound, so async callers should run it in `spawn_blocking`.
+pub trait Embedder: Send + Sync {
+    /// One vector per text, in order.
+    fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>>;
+}
+
+/// Embeds a single text on the blocking thread pool.
+pub async fn embed_one(model: Arc<dyn Embedder>, text: String) -> Result<Vec<f32>> {
+    tokio::task::spawn_blocking(move || model.embed(vec![text]))
+        .await
+        .context("Task panicked while generating embeddings")??
+        .pop()
+        .ok_or_else(|| anyhow!("The embedding model returned no vector"))
+}
+
+impl Embedder for TextEmbedding {
+    fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        Ok(TextEmbedding::embed(self, texts, None)?)
+    }
+}
+
+/// A deterministic stand-in for the embedding model: every word is hashed onto
+/// one axis, so texts sharing words are close. Good enough for retrieval in
+/// tests, useless for real semantic search.
+#[derive(Default)]
+pub struct HashEmbedder;
+
+impl Embedder for HashEmbedder {
+    fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        Ok(texts
+            .iter()
+            .map(|text| {
+                let mut vector = vec![0.0f32; EMBEDDING_DIMENSIONS as usize];
+                for word in text
+                    .split(|c: char| !c.is_alphanumeric())
+                    .filter(|w| !w.is_empty())
+                {
+                    let mut hasher = DefaultHasher::new();
+                    word.to_lowercase().hash(&mut hasher);
+                    vector[hasher.finish() as usize % vector.len()] += 1.0;
+                }
+                let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+                if norm > 0.0 {
+                    vector.iter_mut().for_each(|v| *v /= norm);
+                }
+                vector
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_embeddings_are_stable_and_normalized() {
+        let texts = vec!["Tokio spawn".to_string(), "tokio SPAWN".to_string()];
+        let vectors = HashEmbedder.embed(texts).unwrap();
+        assert_eq!(vectors[0].len(), EMBEDDING_DIMENSIONS as usize);
+        assert_eq!(vectors[0], vectors[1]);
+        let norm: f32 = vectors[0].iter().map(|v| v * v).sum();
+        assert!((norm - 1.0).abs() < 1e-5);
+    }
+}
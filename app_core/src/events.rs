@@ -0,0 +1,105 @@
+use anyhow::{Result, bail};
+use serde::Serialize;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::QueryResult;
+
+/// The stages `process_query` goes through, in order.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryStage {
+    WebSearch,
+    KnowledgeRetrieval,
+    CratePlanning,
+    CrateResearch,
+    Generation,
+    Build,
+    Repair,
+}
+
+/// A progress event emitted while a query is being processed.
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum QueryEvent {
+    StageStarted { stage: QueryStage },
+    StageFinished { stage: QueryStage, elapsed_ms: u64 },
+    /// A piece of LLM output, as it arrives from the provider.
+    LlmToken { text: String },
+    /// One line of `cargo` output from the sandbox.
+    BuildOutput { line: String },
+    /// The query finished; this is always the last event on success.
+    Finished { result: Box<QueryResult> },
+    /// The query failed; this is always the last event on failure.
+    Error { message: String },
+}
+
+impl QueryEvent {
+    /// The event name used for Server-Sent Events.
+    pub fn name(&self) -> &'static str {
+        match self {
+            QueryEvent::StageStarted { .. } => "stage_started",
+            QueryEvent::StageFinished { .. } => "stage_finished",
+            QueryEvent::LlmToken { .. } => "llm_token",
+            QueryEvent::BuildOutput { .. } => "build_output",
+            QueryEvent::Finished { .. } => "finished",
+            QueryEvent::Error { .. } => "error",
+        }
+    }
+}
+
+/// Where pipeline stages report progress. Emitting on an inactive sink is a no-op,
+/// so the non-streaming code path pays nothing for it.
+#[derive(Clone, Default)]
+pub struct QueryEvents {
+    sender: Option<UnboundedSender<QueryEvent>>,
+}
+
+impl QueryEvents {
+    /// A sink that drops every event.
+    pub fn none() -> Self {
+        Self { sender: None }
+    }
+
+    pub fn new(sender: UnboundedSender<QueryEvent>) -> Self {
+        Self {
+            sender: Some(sender),
+        }
+    }
+
+    /// Whether anyone is listening. Used to pick streaming over one-shot LLM calls.
+    pub fn is_active(&self) -> bool {
+        self.sender.as_ref().is_some_and(|s| !s.is_closed())
+    }
+
+    /// Fails once the streaming client has gone away. A sink that never had a
+    /// client, like `none()`, never fails.
+    pub fn ensure_connected(&self) -> Result<()> {
+        if self.sender.as_ref().is_some_and(|s| s.is_closed()) {
+            bail!("Query cancelled: the client disconnected");
+        }
+        Ok(())
+    }
+
+    pub fn emit(&self, event: QueryEvent) {
+        if let Some(sender) = &self.sender {
+            // The receiver going away just means the client disconnected.
+            let _ = sender.send(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disconnected_clients_cancel_the_query() {
+        assert!(QueryEvents::none().ensure_connected().is_ok());
+
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let events = QueryEvents::new(sender);
+        assert!(events.ensure_connected().is_ok());
+        drop(receiver);
+        assert!(events.ensure_connected().is_err());
+    }
+}
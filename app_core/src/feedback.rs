@@ -1,33 +1,39 @@
 use anyhow::Result;
-use qdrant_client::{qdrant::{PointStruct, UpsertPoints}, Payload};
-use crate::{qdrant::APPROVED_SOLUTIONS_COLLECTION, AppState};
+use crate::{embedder::embed_one, lockfile::PinnedManifest, payload::ApprovedSolution, qdrant::APPROVED_SOLUTIONS_COLLECTION, vector_store::VectorPoint, AppState};
 
-/// Stores an upvoted solution in the Qdrant database
+/// Stores an upvoted solution in the vector store, together with the pinned
+/// manifest it was built with so it can be reproduced later.
 pub async fn process_upvoted_solution(
     state: &AppState,
     query: String,
     code: String,
+    manifest: Option<PinnedManifest>,
 ) -> Result<()> {
     // Create a single embedding for the query-code pair to capture the semantic relationship.
     let text_to_embed = format!("Query: {}\n---\nCode:\n{}", query, code);
-    let embedding = state.embedding_model.embed(vec![text_to_embed], None)?[0].clone();
+    let embedding = embed_one(state.embedding_model.clone(), text_to_embed).await?;
 
-    let payload:Payload = serde_json::json!({
-        "query": query,
-        "code": code,
-    })
-    .try_into()?;
+    let (cargo_toml, cargo_lock) = match manifest {
+        Some(m) => (Some(m.cargo_toml), Some(m.cargo_lock)),
+        None => (None, None),
+    };
+    let solution = ApprovedSolution {
+        query,
+        code,
+        cargo_toml,
+        cargo_lock,
+    };
 
-    let point = PointStruct::new(uuid::Uuid::new_v4().to_string(), embedding, payload);
+    let point = VectorPoint {
+        id: uuid::Uuid::new_v4().to_string(),
+        vector: embedding,
+        payload: solution.to_payload()?,
+    };
 
     state
-        .qdrant_client
-        .upsert_points(UpsertPoints {
-            collection_name: APPROVED_SOLUTIONS_COLLECTION.to_string(),
-            points: vec![point],
-            ..Default::default()
-        })
+        .vector_store
+        .upsert(APPROVED_SOLUTIONS_COLLECTION, vec![point])
         .await?;
 
     Ok(())
-}
\ No newline at end of file
+}
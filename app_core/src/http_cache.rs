@@ -0,0 +1,497 @@
+//! A persistent cache for web searches and page fetches. Each query researches
+//! the same handful of crates, so without it the same pages are downloaded over
+//! and over. Pages are revalidated with `ETag`/`Last-Modified` once their TTL
+//! runs out, and in replay-only mode nothing is fetched at all: the pipeline
+//! runs from a previously recorded cache, deterministically and offline.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, bail};
+use async_trait::async_trait;
+use reqwest::{RequestBuilder, StatusCode, header};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    payload::content_hash,
+    search_provider::{SearchHit, SearchProvider},
+};
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheMode {
+    /// Nothing is cached.
+    Off,
+    /// Fresh entries are served from the cache; everything else is fetched and stored.
+    #[default]
+    ReadWrite,
+    /// Everything is served from the cache, however old. Anything missing is an error.
+    ReplayOnly,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct HttpCacheSettings {
+    #[serde(default)]
+    pub mode: CacheMode,
+    #[serde(default = "default_dir")]
+    pub dir: String,
+    /// How long a fetched page is used before it is revalidated.
+    #[serde(default = "default_page_ttl_secs")]
+    pub page_ttl_secs: u64,
+    /// How long search results are used before the search runs again.
+    #[serde(default = "default_search_ttl_secs")]
+    pub search_ttl_secs: u64,
+}
+
+fn default_dir() -> String {
+    ".http_cache".to_string()
+}
+
+fn default_page_ttl_secs() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_search_ttl_secs() -> u64 {
+    6 * 60 * 60
+}
+
+impl Default for HttpCacheSettings {
+    fn default() -> Self {
+        Self {
+            mode: CacheMode::default(),
+            dir: default_dir(),
+            page_ttl_secs: default_page_ttl_secs(),
+            search_ttl_secs: default_search_ttl_secs(),
+        }
+    }
+}
+
+/// A fetched page, from the network or the cache.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FetchedPage {
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub body: String,
+}
+
+impl FetchedPage {
+    pub fn is_ok(&self) -> bool {
+        self.status == StatusCode::OK.as_u16()
+    }
+}
+
+/// What is stored per URL or search.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CacheEntry {
+    key: String,
+    /// Unix timestamp of the last fetch or revalidation, in seconds.
+    stored_at: u64,
+    #[serde(default)]
+    etag: Option<String>,
+    #[serde(default)]
+    last_modified: Option<String>,
+    page: FetchedPage,
+}
+
+#[derive(Clone, Copy)]
+enum EntryKind {
+    Page,
+    Search,
+}
+
+impl EntryKind {
+    fn dir_name(self) -> &'static str {
+        match self {
+            EntryKind::Page => "pages",
+            EntryKind::Search => "search",
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn header_value(response: &reqwest::Response, name: header::HeaderName) -> Option<String> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from)
+}
+
+/// The cache, stored as one JSON file per entry under `<dir>/pages` and
+/// `<dir>/search`, named by the hash of the URL or search.
+pub struct HttpCache {
+    root: PathBuf,
+    settings: HttpCacheSettings,
+}
+
+impl HttpCache {
+    pub fn new(settings: HttpCacheSettings) -> Result<Self> {
+        let root = PathBuf::from(&settings.dir);
+        if settings.mode == CacheMode::ReplayOnly && !root.is_dir() {
+            bail!(
+                "HTTP cache directory {} does not exist; record a cache before replaying it",
+                root.display()
+            );
+        }
+        Ok(Self { root, settings })
+    }
+
+    pub fn mode(&self) -> CacheMode {
+        self.settings.mode
+    }
+
+    fn entry_path(&self, kind: EntryKind, key: &str) -> PathBuf {
+        self.root
+            .join(kind.dir_name())
+            .join(format!("{}.json", content_hash(key)))
+    }
+
+    fn ttl(&self, kind: EntryKind) -> Duration {
+        Duration::from_secs(match kind {
+            EntryKind::Page => self.settings.page_ttl_secs,
+            EntryKind::Search => self.settings.search_ttl_secs,
+        })
+    }
+
+    async fn load(&self, kind: EntryKind, key: &str) -> Option<CacheEntry> {
+        let json = tokio::fs::read_to_string(self.entry_path(kind, key)).await.ok()?;
+        // A hash collision would hand back another key's entry.
+        serde_json::from_str::<CacheEntry>(&json)
+            .ok()
+            .filter(|entry| entry.key == key)
+    }
+
+    async fn save(&self, kind: EntryKind, entry: &CacheEntry) -> Result<()> {
+        let path = self.entry_path(kind, &entry.key);
+        let dir = path.parent().context("Cache entry has no directory")?;
+        tokio::fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("Failed to create cache directory {}", dir.display()))?;
+        // Write then rename, so readers never see half an entry. Each write gets
+        // its own temporary file, so concurrent writers can't interleave.
+        let partial = path.with_extension(format!("{}.partial", uuid::Uuid::new_v4()));
+        tokio::fs::write(&partial, serde_json::to_vec(entry)?).await?;
+        tokio::fs::rename(&partial, &path)
+            .await
+            .with_context(|| format!("Failed to write cache entry {}", path.display()))
+    }
+
+    fn is_fresh(&self, kind: EntryKind, entry: &CacheEntry) -> bool {
+        unix_now().saturating_sub(entry.stored_at) < self.ttl(kind).as_secs()
+    }
+
+    /// The cached page for `url` if it can be used without asking the server:
+    /// a fresh entry, or in replay-only mode any entry. Fails in replay-only mode
+    /// when there is none, and returns `None` when the cache is off.
+    pub async fn cached_page(&self, url: &str) -> Result<Option<FetchedPage>> {
+        if self.settings.mode == CacheMode::Off {
+            return Ok(None);
+        }
+        match self.load(EntryKind::Page, url).await {
+            Some(entry)
+                if self.settings.mode == CacheMode::ReplayOnly
+                    || self.is_fresh(EntryKind::Page, &entry) =>
+            {
+                Ok(Some(entry.page))
+            }
+            None if self.settings.mode == CacheMode::ReplayOnly => {
+                bail!("{} is not in the HTTP cache, which is in replay-only mode", url)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Fetches `url` with `request`, going through the cache. Stale entries are
+    /// revalidated with their `ETag`/`Last-Modified`, and used as they are when
+    /// the server can't be reached. Server errors (5xx) are not cached.
+    pub async fn fetch(&self, url: &str, request: RequestBuilder) -> Result<FetchedPage> {
+        if let Some(page) = self.cached_page(url).await? {
+            return Ok(page);
+        }
+        if self.settings.mode == CacheMode::Off {
+            let response = request.send().await?;
+            return read_page(response).await;
+        }
+
+        let stale = self.load(EntryKind::Page, url).await;
+        let mut request = request;
+        if let Some(entry) = &stale {
+            if let Some(etag) = &entry.etag {
+                request = request.header(header::IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &entry.last_modified {
+                request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(e) => match stale {
+                Some(entry) => {
+                    println!("Warning: Using stale cache entry for {}: {}", url, e);
+                    return Ok(entry.page);
+                }
+                None => return Err(e.into()),
+            },
+        };
+
+        if let Some(mut entry) = stale.filter(|_| response.status() == StatusCode::NOT_MODIFIED) {
+            entry.stored_at = unix_now();
+            self.save_logged(EntryKind::Page, &entry).await;
+            return Ok(entry.page);
+        }
+
+        let etag = header_value(&response, header::ETAG);
+        let last_modified = header_value(&response, header::LAST_MODIFIED);
+        let page = read_page(response).await?;
+        if is_cacheable(page.status) {
+            let entry = CacheEntry {
+                key: url.to_string(),
+                stored_at: unix_now(),
+                etag,
+                last_modified,
+                page: page.clone(),
+            };
+            self.save_logged(EntryKind::Page, &entry).await;
+        }
+        Ok(page)
+    }
+
+    /// Stores a page as if it had just been fetched.
+    pub async fn store_page(&self, url: &str, page: FetchedPage) -> Result<()> {
+        let entry = CacheEntry {
+            key: url.to_string(),
+            stored_at: unix_now(),
+            etag: None,
+            last_modified: None,
+            page,
+        };
+        self.save(EntryKind::Page, &entry).await
+    }
+
+    /// A failure to write the cache shouldn't fail the fetch it was caching.
+    async fn save_logged(&self, kind: EntryKind, entry: &CacheEntry) {
+        if let Err(e) = self.save(kind, entry).await {
+            println!("Warning: Failed to cache {}: {:#}", entry.key, e);
+        }
+    }
+}
+
+/// Successful pages, and the 404s and 410s that say a page is gone. Other
+/// answers, like a 429 from a rate limiter or a 403 from a bot check, may well
+/// be different on the next try.
+fn is_cacheable(status: u16) -> bool {
+    (200..300).contains(&status) || status == 404 || status == 410
+}
+
+async fn read_page(response: reqwest::Response) -> Result<FetchedPage> {
+    let status = response.status().as_u16();
+    let content_type = header_value(&response, header::CONTENT_TYPE);
+    let body = response.text().await?;
+    Ok(FetchedPage {
+        status,
+        content_type,
+        body,
+    })
+}
+
+/// Wraps a `SearchProvider` so its results are cached like pages are. Searches
+/// are keyed by provider, query and limit.
+pub struct CachedSearchProvider {
+    inner: Arc<dyn SearchProvider>,
+    /// Tells the results of different providers apart.
+    provider_name: String,
+    cache: Arc<HttpCache>,
+}
+
+impl CachedSearchProvider {
+    pub fn new(
+        inner: Arc<dyn SearchProvider>,
+        provider_name: impl Into<String>,
+        cache: Arc<HttpCache>,
+    ) -> Self {
+        Self {
+            inner,
+            provider_name: provider_name.into(),
+            cache,
+        }
+    }
+}
+
+#[async_trait]
+impl SearchProvider for CachedSearchProvider {
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>> {
+        let cache = &self.cache;
+        if cache.mode() == CacheMode::Off {
+            return self.inner.search(query, limit).await;
+        }
+
+        let key = format!("{}\n{}\n{}", self.provider_name, limit, query);
+        let entry = cache.load(EntryKind::Search, &key).await;
+        let usable = entry.filter(|entry| {
+            cache.mode() == CacheMode::ReplayOnly || cache.is_fresh(EntryKind::Search, entry)
+        });
+        if let Some(entry) = usable {
+            return serde_json::from_str(&entry.page.body)
+                .with_context(|| format!("Corrupt cached search results for {}", query));
+        }
+        if cache.mode() == CacheMode::ReplayOnly {
+            bail!(
+                "Search for {:?} is not in the HTTP cache, which is in replay-only mode",
+                query
+            );
+        }
+
+        let hits = self.inner.search(query, limit).await?;
+        let entry = CacheEntry {
+            key,
+            stored_at: unix_now(),
+            etag: None,
+            last_modified: None,
+            page: FetchedPage {
+                status: StatusCode::OK.as_u16(),
+                content_type: Some("application/json".to_string()),
+                body: serde_json::to_string(&hits)?,
+            },
+        };
+        cache.save_logged(EntryKind::Search, &entry).await;
+        Ok(hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    fn settings(dir: &Path, mode: CacheMode) -> HttpCacheSettings {
+        HttpCacheSettings {
+            mode,
+            dir: dir.to_string_lossy().into_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn html(body: &str) -> FetchedPage {
+        FetchedPage {
+            status: 200,
+            content_type: Some("text/html".to_string()),
+            body: body.to_string(),
+        }
+    }
+
+    #[test]
+    fn only_lasting_answers_are_cached() {
+        for status in [200, 203, 404, 410] {
+            assert!(is_cacheable(status), "{}", status);
+        }
+        for status in [301, 403, 429, 500, 503] {
+            assert!(!is_cacheable(status), "{}", status);
+        }
+    }
+
+    #[tokio::test]
+    async fn fresh_pages_come_from_the_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = HttpCache::new(settings(dir.path(), CacheMode::ReadWrite)).unwrap();
+        let url = "https://docs.rs/tokio";
+        assert_eq!(cache.cached_page(url).await.unwrap(), None);
+
+        cache.store_page(url, html("<p>tokio</p>")).await.unwrap();
+        assert_eq!(cache.cached_page(url).await.unwrap(), Some(html("<p>tokio</p>")));
+
+        let expired = HttpCache::new(HttpCacheSettings {
+            page_ttl_secs: 0,
+            ..settings(dir.path(), CacheMode::ReadWrite)
+        })
+        .unwrap();
+        assert_eq!(expired.cached_page(url).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn concurrent_writes_of_one_page_all_succeed() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = HttpCache::new(settings(dir.path(), CacheMode::ReadWrite)).unwrap();
+        let url = "https://docs.rs/tokio";
+        let (a, b, c) = tokio::join!(
+            cache.store_page(url, html("<p>a</p>")),
+            cache.store_page(url, html("<p>b</p>")),
+            cache.store_page(url, html("<p>c</p>")),
+        );
+        assert!(a.is_ok() && b.is_ok() && c.is_ok());
+
+        let page = cache.cached_page(url).await.unwrap().unwrap();
+        assert!(["<p>a</p>", "<p>b</p>", "<p>c</p>"].contains(&page.body.as_str()));
+        let path = cache.entry_path(EntryKind::Page, url);
+        let leftovers = std::fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .filter(|e| e.as_ref().unwrap().path() != path)
+            .count();
+        assert_eq!(leftovers, 0);
+    }
+
+    #[tokio::test]
+    async fn replay_serves_stale_pages_and_rejects_misses() {
+        let dir = tempfile::tempdir().unwrap();
+        let recorder = HttpCache::new(settings(dir.path(), CacheMode::ReadWrite)).unwrap();
+        recorder
+            .store_page("https://docs.rs/serde", html("<p>serde</p>"))
+            .await
+            .unwrap();
+
+        let replay = HttpCache::new(HttpCacheSettings {
+            page_ttl_secs: 0,
+            ..settings(dir.path(), CacheMode::ReplayOnly)
+        })
+        .unwrap();
+        assert_eq!(
+            replay.cached_page("https://docs.rs/serde").await.unwrap(),
+            Some(html("<p>serde</p>"))
+        );
+        assert!(replay.cached_page("https://docs.rs/rand").await.is_err());
+        let request = reqwest::Client::new().get("https://docs.rs/rand");
+        assert!(replay.fetch("https://docs.rs/rand", request).await.is_err());
+    }
+
+    struct CountingProvider(AtomicUsize);
+
+    #[async_trait]
+    impl SearchProvider for CountingProvider {
+        async fn search(&self, query: &str, _limit: usize) -> Result<Vec<SearchHit>> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(vec![SearchHit {
+                title: query.to_string(),
+                url: "https://example.com".to_string(),
+                snippet: String::new(),
+                content: None,
+            }])
+        }
+    }
+
+    #[tokio::test]
+    async fn searches_are_recorded_and_replayed() {
+        let dir = tempfile::tempdir().unwrap();
+        let inner = Arc::new(CountingProvider(AtomicUsize::new(0)));
+        let cache = Arc::new(HttpCache::new(settings(dir.path(), CacheMode::ReadWrite)).unwrap());
+        let provider = CachedSearchProvider::new(inner.clone(), "counting", cache);
+
+        let first = provider.search("tokio mutex", 2).await.unwrap();
+        let second = provider.search("tokio mutex", 2).await.unwrap();
+        assert_eq!(first, second);
+        assert_eq!(inner.0.load(Ordering::Relaxed), 1);
+
+        let replay = Arc::new(HttpCache::new(settings(dir.path(), CacheMode::ReplayOnly)).unwrap());
+        let provider = CachedSearchProvider::new(inner.clone(), "counting", replay);
+        assert_eq!(provider.search("tokio mutex", 2).await.unwrap(), first);
+        assert!(provider.search("serde derive", 2).await.is_err());
+        assert_eq!(inner.0.load(Ordering::Relaxed), 1);
+    }
+}
@@ -1,39 +1,326 @@
+use crate::{
+    AppState,
+    code_chunker::{chunk_rust_source, module_path_for_file},
+    documents::{document_points, find_document_by_source},
+    jobs::JobProgress,
+    markdown_chunker::chunk_markdown,
+    payload::{KnowledgeChunk, content_hash, derived_id},
+    qdrant::KNOWLEDGE_BASE_COLLECTION,
+    vector_store::{PayloadUpdate, PointSelector, StoredPoint, VectorPoint},
+};
+use std::collections::{HashMap, HashSet};
 
-
-use crate::{AppState, qdrant::KNOWLEDGE_BASE_COLLECTION};
-use anyhow::{Context, Result};
-use qdrant_client::Payload;
-use qdrant_client::qdrant::{PointStruct, UpsertPoints};
-
-
-
-use serde_json::json;
+use anyhow::{Context, Result, ensure};
+use serde::{Deserialize, Serialize};
 use text_splitter::{Characters, ChunkConfig, TextSplitter};
 
+/// How a document is broken into chunks.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DocumentFormat {
+    /// Picked from the source's file extension, plain text otherwise.
+    #[default]
+    Auto,
+    Text,
+    /// Rust source, chunked along item boundaries.
+    Rust,
+    /// Markdown, chunked by section without splitting code blocks.
+    Markdown,
+}
 
+impl DocumentFormat {
+    fn resolve(self, source: &str) -> Self {
+        match self {
+            DocumentFormat::Auto if source.ends_with(".rs") => DocumentFormat::Rust,
+            DocumentFormat::Auto if source.ends_with(".md") || source.ends_with(".markdown") => {
+                DocumentFormat::Markdown
+            }
+            DocumentFormat::Auto => DocumentFormat::Text,
+            other => other,
+        }
+    }
+}
 
-pub async fn ingest_document(state: AppState, document: String) -> Result<()> {
+/// Splits a document into overlapping chunks small enough to embed.
+pub fn split_into_chunks(document: &str) -> Result<Vec<String>> {
     let chunk_config = ChunkConfig::<Characters>::new(1000)
         .with_overlap(100)?
         .with_trim(true);
 
     let splitter = TextSplitter::new(chunk_config);
-    let chunks: Vec<String> = splitter
-        .chunks(&document)
-        .map(|s| s.to_owned())
+    Ok(splitter.chunks(document).map(|s| s.to_owned()).collect())
+}
+
+/// Pairs chunks with their embeddings as points for the knowledge base collection.
+pub fn knowledge_points(
+    chunks: &[KnowledgeChunk],
+    embeddings: Vec<Vec<f32>>,
+) -> Result<Vec<VectorPoint>> {
+    ensure!(
+        chunks.len() == embeddings.len(),
+        "Got {} embeddings for {} chunks",
+        embeddings.len(),
+        chunks.len()
+    );
+    chunks
+        .iter()
+        .zip(embeddings)
+        .map(|(chunk, vector)| {
+            Ok(VectorPoint {
+                id: chunk.point_id(),
+                vector,
+                payload: chunk.to_payload()?,
+            })
+        })
+        .collect()
+}
+
+/// Breaks a document into knowledge base chunks according to `format`.
+/// Rust that fails to parse is split as plain text.
+pub fn chunk_document(
+    document_id: &str,
+    source: &str,
+    document: &str,
+    format: DocumentFormat,
+) -> Result<Vec<KnowledgeChunk>> {
+    let format = format.resolve(source);
+    if format == DocumentFormat::Markdown {
+        return Ok(chunk_markdown(document)
+            .into_iter()
+            .enumerate()
+            .map(|(index, section)| {
+                let heading_path = section.breadcrumb();
+                KnowledgeChunk::new(section.text, document_id, source, index)
+                    .with_heading_path(heading_path)
+            })
+            .collect());
+    }
+    if format == DocumentFormat::Rust {
+        let file_name = source.rsplit('/').next().unwrap_or(source);
+        match chunk_rust_source(document, &module_path_for_file(file_name)) {
+            Ok(items) => {
+                return Ok(items
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, item)| {
+                        KnowledgeChunk::new(item.text, document_id, source, index)
+                            .with_item(item.item_path, item.item_kind)
+                    })
+                    .collect());
+            }
+            Err(e) => println!(
+                "Warning: {} is not valid Rust ({:#}), splitting it as text.",
+                source, e
+            ),
+        }
+    }
+
+    Ok(split_into_chunks(document)?
+        .into_iter()
+        .enumerate()
+        .map(|(index, text)| KnowledgeChunk::new(text, document_id, source, index))
+        .collect())
+}
+
+/// Source recorded for text pasted into the UI. It names no particular document,
+/// so such documents are identified by their content instead.
+pub const PASTED_TEXT_SOURCE: &str = "pasted text";
+
+/// What an ingest stored.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct IngestReport {
+    /// Identifies the document in the registry; see `documents`.
+    pub document_id: String,
+    /// Number of distinct chunks the document now has.
+    pub chunks: usize,
+    /// Chunks that were new or changed, and had to be embedded.
+    pub added: usize,
+    /// Chunks already stored that only changed position; they weren't embedded again.
+    pub moved: usize,
+    /// Chunks already stored exactly as they are.
+    pub unchanged: usize,
+    /// Chunks that are no longer in the document and were deleted.
+    pub removed: usize,
+}
+
+/// Chunks, embeds and stores a document. `source` is where the document came
+/// from (a URL or file name) and is kept with every chunk.
+///
+/// The document replaces `document_id` when one is given. Otherwise a URL
+/// updates the document already ingested from it, and pasted text and uploaded
+/// files are identified by their source and content, so ingesting any of them
+/// again doesn't duplicate anything.
+pub async fn ingest_document(
+    state: AppState,
+    source: String,
+    document: String,
+    format: DocumentFormat,
+    document_id: Option<String>,
+    progress: &JobProgress,
+) -> Result<IngestReport> {
+    let document_id = match document_id {
+        Some(id) => id,
+        None if source == PASTED_TEXT_SOURCE => derived_id(&content_hash(&document)),
+        None if is_url(&source) => document_id_for_source(&state, &source).await?,
+        None => derived_id(&format!("{}\n{}", source, content_hash(&document))),
+    };
+    replace_document(&state, &document_id, &source, &document, format, progress).await
+}
+
+/// Whether `source` is a web address rather than a file name.
+pub fn is_url(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
+
+/// The id of the registered document from `source`, or a fresh one. Only for
+/// sources that name a single document, such as URLs and crate directories.
+pub async fn document_id_for_source(state: &AppState, source: &str) -> Result<String> {
+    Ok(find_document_by_source(state.vector_store.as_ref(), source)
+        .await?
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string()))
+}
+
+/// Stores `document` under `document_id`, replacing whatever chunks that document had.
+pub async fn replace_document(
+    state: &AppState,
+    document_id: &str,
+    source: &str,
+    document: &str,
+    format: DocumentFormat,
+    progress: &JobProgress,
+) -> Result<IngestReport> {
+    let chunks = chunk_document(document_id, source, document, format)?;
+    println!(
+        "Document from {} split into {} chunks. Processing in batches...",
+        source,
+        chunks.len()
+    );
+    replace_chunks(state, document_id, &chunks, progress).await
+}
+
+/// How to turn a document's stored points into a new set of chunks.
+#[derive(Debug, Default)]
+pub struct ReplacementPlan {
+    /// Chunks to embed and upsert.
+    pub to_store: Vec<KnowledgeChunk>,
+    /// Stored chunks that only moved within the document; their payload is
+    /// rewritten and their vector kept.
+    pub to_update: Vec<KnowledgeChunk>,
+    pub unchanged: usize,
+    /// Ids of stored points that are not part of the new chunks.
+    pub to_remove: Vec<String>,
+}
+
+/// Compares a document's stored points with its new chunks. Chunks with the same
+/// text appear once; stored points that already hold a chunk are left alone, or
+/// only have their payload updated when the chunk moved.
+pub fn plan_replacement(existing: &[StoredPoint], chunks: &[KnowledgeChunk]) -> ReplacementPlan {
+    let stored: HashMap<&str, Option<KnowledgeChunk>> = existing
+        .iter()
+        .map(|p| (p.id.as_str(), KnowledgeChunk::from_payload(&p.payload).ok()))
+        .collect();
+
+    let mut plan = ReplacementPlan::default();
+    let mut kept = HashSet::new();
+    for chunk in chunks {
+        let id = chunk.point_id();
+        if !kept.insert(id.clone()) {
+            continue;
+        }
+        match stored.get(id.as_str()) {
+            Some(Some(old)) if old.same_content(chunk) => {
+                if old.chunk_index == chunk.chunk_index {
+                    plan.unchanged += 1;
+                } else {
+                    plan.to_update.push(chunk.clone());
+                }
+            }
+            _ => plan.to_store.push(chunk.clone()),
+        }
+    }
+    plan.to_remove = existing
+        .iter()
+        .filter(|p| !kept.contains(&p.id))
+        .map(|p| p.id.clone())
         .collect();
-    println!("Document split into {} chunks. Processing in batches...", chunks.len());
+    plan
+}
+
+/// How many moved chunks have their payload rewritten in one write.
+const UPDATE_BATCH_SIZE: usize = 256;
 
+/// Makes `chunks` the content of `document_id`: embeds only new or changed
+/// chunks and deletes the ones that disappeared. Stale chunks are only removed
+/// once every new one is stored, so a cancelled ingest leaves nothing missing.
+pub async fn replace_chunks(
+    state: &AppState,
+    document_id: &str,
+    chunks: &[KnowledgeChunk],
+    progress: &JobProgress,
+) -> Result<IngestReport> {
+    let existing = document_points(state.vector_store.as_ref(), document_id).await?;
+    let plan = plan_replacement(&existing, chunks);
+
+    store_chunks(state, &plan.to_store, progress).await?;
+    for batch in plan.to_update.chunks(UPDATE_BATCH_SIZE) {
+        progress.check_cancelled()?;
+        let updates = batch
+            .iter()
+            .map(|chunk| {
+                Ok(PayloadUpdate {
+                    ids: vec![chunk.point_id()],
+                    payload: chunk.to_payload()?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        state
+            .vector_store
+            .set_payloads(KNOWLEDGE_BASE_COLLECTION, updates)
+            .await
+            .context("Failed to update moved chunks")?;
+    }
+    if !plan.to_remove.is_empty() {
+        state
+            .vector_store
+            .delete(
+                KNOWLEDGE_BASE_COLLECTION,
+                PointSelector::Ids(plan.to_remove.clone()),
+            )
+            .await
+            .context("Failed to remove stale chunks")?;
+    }
+
+    let report = IngestReport {
+        document_id: document_id.to_string(),
+        chunks: plan.to_store.len() + plan.to_update.len() + plan.unchanged,
+        added: plan.to_store.len(),
+        moved: plan.to_update.len(),
+        unchanged: plan.unchanged,
+        removed: plan.to_remove.len(),
+    };
+    println!(
+        "Document {}: {} added, {} moved, {} unchanged, {} removed.",
+        document_id, report.added, report.moved, report.unchanged, report.removed
+    );
+    Ok(report)
+}
+
+/// Embeds chunks in batches and upserts them into the knowledge base.
+pub async fn store_chunks(
+    state: &AppState,
+    chunks: &[KnowledgeChunk],
+    progress: &JobProgress,
+) -> Result<()> {
     const BATCH_SIZE: usize = 32;
 
     for chunk_batch in chunks.chunks(BATCH_SIZE) {
-        let batch_size = chunk_batch.len();
-        println!("Processing batch of {} chunks...", batch_size);
+        progress.check_cancelled()?;
+        println!("Processing batch of {} chunks...", chunk_batch.len());
 
         let model_arc = state.embedding_model.clone();
-        let batch_to_embed = chunk_batch.to_vec();
+        let batch_to_embed: Vec<String> = chunk_batch.iter().map(|c| c.text.clone()).collect();
         let embeddings = tokio::task::spawn_blocking(move || {
-            model_arc.embed(batch_to_embed, None)
+            model_arc.embed(batch_to_embed)
         })
         .await
         .context("Task panicked while generating embeddings")??;
@@ -43,36 +330,17 @@ pub async fn ingest_document(state: AppState, document: String) -> Result<()> {
             continue;
         }
 
-        let points: Vec<PointStruct> = embeddings
-            .into_iter()
-            .zip(chunk_batch.iter())
-            .map(|(embedding, chunk_text)| {
-                let payload: Payload = json!({ "text": *chunk_text }).try_into().unwrap();
-                PointStruct::new(uuid::Uuid::new_v4().to_string(), embedding, payload)
-            })
-            .collect();
-
-        // ======================== THE IMPORTANT CHANGE IS HERE ========================
-        println!("Attempting to upsert batch of {} points...", points.len());
-        
-        // Capture the result instead of immediately using '?'
-        let upsert_result = state
-            .qdrant_client
-            .upsert_points(UpsertPoints {
-                collection_name: KNOWLEDGE_BASE_COLLECTION.to_string(),
-                points,
-                ..Default::default()
-            })
-            .await;
-
-        // Print the full result from the Qdrant client
-        println!("QDRANT RESPONSE: {:?}", upsert_result);
-        
-        // Now, handle the error if it exists
-        upsert_result?;
-        // ==============================================================================
+        progress.chunks_embedded(embeddings.len());
+
+        let points = knowledge_points(chunk_batch, embeddings)?;
+        state
+            .vector_store
+            .upsert(KNOWLEDGE_BASE_COLLECTION, points)
+            .await
+            .context("Failed to store knowledge chunks")?;
+        progress.batch_upserted();
     }
 
     println!("--- Ingestion Complete! All batches processed. ---");
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,337 @@
+//! Background ingestion jobs. Crawling a site and embedding it can take far longer
+//! than an HTTP request should, so the ingest endpoints start a job and return its
+//! id, and clients poll the job for progress.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Result, anyhow, bail};
+use serde::Serialize;
+
+use crate::ingestion::IngestReport;
+
+/// Finished jobs kept for status queries; older ones are forgotten.
+const MAX_FINISHED_JOBS: usize = 100;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn is_finished(self) -> bool {
+        self != JobStatus::Running
+    }
+}
+
+/// Counters updated as a job runs.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct JobCounters {
+    pub pages_fetched: usize,
+    pub chunks_embedded: usize,
+    pub batches_upserted: usize,
+    /// Problems the job carried on after, such as pages that failed to load.
+    pub errors: Vec<String>,
+}
+
+/// A snapshot of a job, as returned by the jobs API.
+#[derive(Serialize, Debug, Clone)]
+pub struct JobInfo {
+    pub id: String,
+    /// What is being ingested, e.g. `url https://tokio.rs`.
+    pub description: String,
+    pub status: JobStatus,
+    pub progress: JobCounters,
+    /// Set once the job completed.
+    pub report: Option<IngestReport>,
+    /// Why the job failed.
+    pub error: Option<String>,
+    /// Unix timestamps, in seconds.
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+}
+
+struct JobState {
+    info: Mutex<JobInfo>,
+    cancelled: AtomicBool,
+}
+
+/// Reports progress for the job a piece of ingestion work runs in, and tells it
+/// when to stop. `JobProgress::none()` is for work that runs outside a job.
+#[derive(Clone)]
+pub struct JobProgress {
+    job: Option<Arc<JobState>>,
+}
+
+impl JobProgress {
+    pub fn none() -> Self {
+        Self { job: None }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut JobCounters)) {
+        if let Some(job) = &self.job {
+            f(&mut job.info.lock().unwrap().progress);
+        }
+    }
+
+    pub fn page_fetched(&self) {
+        self.update(|c| c.pages_fetched += 1);
+    }
+
+    pub fn chunks_embedded(&self, count: usize) {
+        self.update(|c| c.chunks_embedded += count);
+    }
+
+    pub fn batch_upserted(&self) {
+        self.update(|c| c.batches_upserted += 1);
+    }
+
+    pub fn error(&self, message: impl Into<String>) {
+        let message = message.into();
+        println!("Warning: {}", message);
+        self.update(|c| c.errors.push(message));
+    }
+
+    /// Fails once the job has been cancelled. Called between units of work, so
+    /// a cancelled job stops at the next page or batch.
+    pub fn check_cancelled(&self) -> Result<()> {
+        if self
+            .job
+            .as_ref()
+            .is_some_and(|job| job.cancelled.load(Ordering::Relaxed))
+        {
+            bail!("Job was cancelled");
+        }
+        Ok(())
+    }
+}
+
+/// All ingestion jobs of this process.
+#[derive(Default)]
+pub struct IngestJobs {
+    jobs: Mutex<HashMap<String, Arc<JobState>>>,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn panic_message(error: tokio::task::JoinError) -> String {
+    match error.try_into_panic() {
+        Ok(payload) => payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "no message".to_string()),
+        Err(error) => error.to_string(),
+    }
+}
+
+impl IngestJobs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `work` in the background and returns the job's id right away.
+    pub fn spawn<F, Fut>(self: &Arc<Self>, description: impl Into<String>, work: F) -> String
+    where
+        F: FnOnce(JobProgress) -> Fut,
+        Fut: Future<Output = Result<IngestReport>> + Send + 'static,
+    {
+        let id = uuid::Uuid::new_v4().to_string();
+        let job = Arc::new(JobState {
+            info: Mutex::new(JobInfo {
+                id: id.clone(),
+                description: description.into(),
+                status: JobStatus::Running,
+                progress: JobCounters::default(),
+                report: None,
+                error: None,
+                started_at: unix_now(),
+                finished_at: None,
+            }),
+            cancelled: AtomicBool::new(false),
+        });
+        self.jobs.lock().unwrap().insert(id.clone(), job.clone());
+
+        let future = work(JobProgress {
+            job: Some(job.clone()),
+        });
+        let jobs = self.clone();
+        tokio::spawn(async move {
+            // The work runs in a task of its own, so a panic in it still finishes the job.
+            let result = match tokio::spawn(future).await {
+                Ok(result) => result,
+                Err(e) => Err(anyhow!("The job panicked: {}", panic_message(e))),
+            };
+            {
+                let mut info = job.info.lock().unwrap();
+                match result {
+                    Ok(report) => {
+                        info.status = JobStatus::Completed;
+                        info.report = Some(report);
+                    }
+                    Err(_) if job.cancelled.load(Ordering::Relaxed) => {
+                        info.status = JobStatus::Cancelled;
+                    }
+                    Err(e) => {
+                        println!("ERROR: Ingestion job {} failed: {:#}", info.id, e);
+                        info.status = JobStatus::Failed;
+                        info.error = Some(format!("{e:#}"));
+                    }
+                }
+                info.finished_at = Some(unix_now());
+            }
+            jobs.forget_old_jobs();
+        });
+        id
+    }
+
+    pub fn get(&self, id: &str) -> Option<JobInfo> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.get(id).map(|job| job.info.lock().unwrap().clone())
+    }
+
+    /// All known jobs, newest first.
+    pub fn list(&self) -> Vec<JobInfo> {
+        let mut jobs: Vec<JobInfo> = self
+            .jobs
+            .lock()
+            .unwrap()
+            .values()
+            .map(|job| job.info.lock().unwrap().clone())
+            .collect();
+        jobs.sort_by(|a, b| b.started_at.cmp(&a.started_at).then(a.id.cmp(&b.id)));
+        jobs
+    }
+
+    /// Asks a running job to stop. Returns `None` for unknown jobs and `Some(false)`
+    /// for jobs that already finished.
+    pub fn cancel(&self, id: &str) -> Option<bool> {
+        let jobs = self.jobs.lock().unwrap();
+        let job = jobs.get(id)?;
+        if job.info.lock().unwrap().status.is_finished() {
+            return Some(false);
+        }
+        job.cancelled.store(true, Ordering::Relaxed);
+        Some(true)
+    }
+
+    fn forget_old_jobs(&self) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let mut finished: Vec<(u64, String)> = jobs
+            .values()
+            .filter_map(|job| {
+                let info = job.info.lock().unwrap();
+                Some((info.finished_at?, info.id.clone()))
+            })
+            .collect();
+        if finished.len() <= MAX_FINISHED_JOBS {
+            return;
+        }
+        finished.sort();
+        let excess = finished.len() - MAX_FINISHED_JOBS;
+        for (_, id) in finished.into_iter().take(excess) {
+            jobs.remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn wait_until_finished(jobs: &IngestJobs, id: &str) -> JobInfo {
+        loop {
+            let info = jobs.get(id).unwrap();
+            if info.status.is_finished() {
+                return info;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn completed_jobs_keep_their_report_and_progress() {
+        let jobs = Arc::new(IngestJobs::new());
+        let id = jobs.spawn("test", |progress| async move {
+            progress.page_fetched();
+            progress.chunks_embedded(3);
+            progress.batch_upserted();
+            progress.error("one page failed");
+            Ok(IngestReport {
+                document_id: "doc-1".to_string(),
+                chunks: 3,
+                added: 3,
+                ..Default::default()
+            })
+        });
+
+        let info = wait_until_finished(&jobs, &id).await;
+        assert_eq!(info.status, JobStatus::Completed);
+        assert_eq!(info.progress.pages_fetched, 1);
+        assert_eq!(info.progress.chunks_embedded, 3);
+        assert_eq!(info.progress.errors, ["one page failed"]);
+        assert_eq!(info.report.unwrap().document_id, "doc-1");
+        assert_eq!(jobs.cancel(&id), Some(false));
+    }
+
+    #[tokio::test]
+    async fn cancelled_jobs_stop_at_the_next_check() {
+        let jobs = Arc::new(IngestJobs::new());
+        let (started_tx, started_rx) = tokio::sync::oneshot::channel();
+        let (resume_tx, resume_rx) = tokio::sync::oneshot::channel::<()>();
+        let id = jobs.spawn("test", |progress| async move {
+            started_tx.send(()).unwrap();
+            resume_rx.await.unwrap();
+            progress.check_cancelled()?;
+            Ok(IngestReport::default())
+        });
+
+        started_rx.await.unwrap();
+        assert_eq!(jobs.cancel(&id), Some(true));
+        resume_tx.send(()).unwrap();
+
+        let info = wait_until_finished(&jobs, &id).await;
+        assert_eq!(info.status, JobStatus::Cancelled);
+        assert_eq!(jobs.cancel("unknown"), None);
+    }
+
+    #[tokio::test]
+    async fn failures_are_reported() {
+        let jobs = Arc::new(IngestJobs::new());
+        let id = jobs.spawn("test", |_| async { bail!("no such crate") });
+
+        let info = wait_until_finished(&jobs, &id).await;
+        assert_eq!(info.status, JobStatus::Failed);
+        assert_eq!(info.error.as_deref(), Some("no such crate"));
+    }
+
+    fn explode() -> Result<IngestReport> {
+        panic!("out of cheese")
+    }
+
+    #[tokio::test]
+    async fn panicking_jobs_fail() {
+        let jobs = Arc::new(IngestJobs::new());
+        let id = jobs.spawn("test", |_| async { explode() });
+
+        let info = wait_until_finished(&jobs, &id).await;
+        assert_eq!(info.status, JobStatus::Failed);
+        assert_eq!(
+            info.error.as_deref(),
+            Some("The job panicked: out of cheese")
+        );
+        assert!(info.finished_at.is_some());
+    }
+}
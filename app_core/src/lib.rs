@@ -1,28 +1,107 @@
 #![allow(unused)]
 use std::sync::Arc;
+use std::time::Instant;
 
 use anyhow::{Context, Result};
 use config::{Config, File};
 use fastembed::{EmbeddingModel, InitOptions, TextEmbedding};
 use genai::Client;
 use ort::{execution_providers::CUDAExecutionProvider, session::Session};
-use qdrant_client::Qdrant;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use futures_util::{StreamExt, stream};
+use tokio_stream::{Stream, wrappers::UnboundedReceiverStream};
 
-use crate::{ sandbox::run_in_sandbox, web_search::search_and_scrape};
+use crate::{
+    build_cache::BuildCache,
+    chat_backend::{ChatBackend, GenaiBackend, ScriptedBackend},
+    crate_research::{CrateResearch, CrateResearchSettings, research_crate},
+    diagnostics::Diagnostic,
+    embedder::Embedder,
+    lockfile::PinnedManifest,
+    docker_sandbox::DockerExecutor,
+    events::{QueryEvent, QueryEvents, QueryStage},
+    http_cache::{CachedSearchProvider, HttpCache, HttpCacheSettings},
+    jobs::IngestJobs,
+    qdrant::QdrantStore,
+    sandbox::{
+        HostExecutor, RunOptions, RunOutcome, RunSettings, SandboxExecutor, SandboxExecutorKind,
+        SandboxSettings, run_in_sandbox,
+    },
+    search_provider::{SearchProvider, SearchSettings, search_provider},
+    vector_store::{MemoryVectorStore, VectorStore},
+    web_scraper::{CrawlSettings, HostThrottle},
+    web_search::{WebContext, search_and_scrape},
+};
 
+pub mod build_cache;
+pub mod chat_backend;
+pub mod code_chunker;
+pub mod content_extractor;
+pub mod crate_research;
+pub mod crate_source;
+pub mod dependency_check;
+pub mod diagnostics;
+pub mod documents;
+pub mod docker_sandbox;
+pub mod embedder;
+pub mod events;
 pub mod feedback;
+pub mod http_cache;
 pub mod ingestion;
+pub mod jobs;
 pub mod llm;
+pub mod lockfile;
+pub mod markdown_chunker;
+pub mod payload;
 pub mod qdrant;
+pub mod robots;
 pub mod sandbox;
+pub mod search_provider;
+pub mod sitemap;
+pub mod vector_store;
 pub mod web_scraper;
 pub mod web_search;
 
+/// Which `VectorStore` implementation backs the application.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VectorStoreKind {
+    #[default]
+    Qdrant,
+    Memory,
+}
+
 #[derive(Deserialize, Clone)]
 pub struct AppSettings {
+    #[serde(default)]
+    pub vector_store: VectorStoreKind,
     pub qdrant_url: String,
+    /// File the in-memory vector store is persisted to. Kept in memory only when unset.
+    #[serde(default)]
+    pub vector_store_path: Option<String>,
     pub llm_model: String,
+    /// How many times a failing build is sent back to the LLM for repair.
+    #[serde(default = "default_max_repair_attempts")]
+    pub max_repair_attempts: usize,
+    /// When set, LLM calls are answered from the fixture files in this directory
+    /// instead of a hosted model. See `chat_backend::ScriptedBackend`.
+    #[serde(default)]
+    pub llm_fixtures_dir: Option<String>,
+    #[serde(default)]
+    pub sandbox: SandboxSettings,
+    #[serde(default)]
+    pub crawler: CrawlSettings,
+    #[serde(default)]
+    pub search: SearchSettings,
+    #[serde(default)]
+    pub http_cache: HttpCacheSettings,
+    #[serde(default)]
+    pub crate_research: CrateResearchSettings,
+}
+
+fn default_max_repair_attempts() -> usize {
+    3
 }
 
 impl AppSettings {
@@ -38,19 +117,44 @@ impl AppSettings {
 
 #[derive(Clone)]
 pub struct AppState {
-    pub qdrant_client: Qdrant,
-    pub genai_client: Client,
-    pub model: String,
-    pub embedding_model: Arc<TextEmbedding>,
+    pub vector_store: Arc<dyn VectorStore>,
+    pub chat_backend: Arc<dyn ChatBackend>,
+    pub embedding_model: Arc<dyn Embedder>,
     pub http_client: Arc<reqwest::Client>, // for scraping
+    /// Web pages fetched by searches and crawls, kept on disk.
+    pub http_cache: Arc<HttpCache>,
+    pub search_provider: Arc<dyn SearchProvider>,
+    /// Result count, concurrency and timeouts of web searches.
+    pub search_settings: SearchSettings,
+    /// Where crates are looked up before code is generated.
+    pub crate_research: CrateResearchSettings,
+    pub sandbox: Arc<dyn SandboxExecutor>,
+    pub build_cache: Arc<BuildCache>,
+    pub ingest_jobs: Arc<IngestJobs>,
+    pub host_throttle: Arc<HostThrottle>,
+    /// Crawl limits used unless a request overrides them.
+    pub crawl_settings: CrawlSettings,
+    pub run_settings: RunSettings,
+    pub max_repair_attempts: usize,
 }
 
 impl AppState {
     /// Initializes the application state, connecting to required services.
     pub async fn new(settings: AppSettings) -> Result<Self> {
-        let qdrant_client = Qdrant::from_url(&settings.qdrant_url).build()?;
-        let genai_client = Client::default();
-        let model = settings.llm_model;
+        let vector_store: Arc<dyn VectorStore> = match settings.vector_store {
+            VectorStoreKind::Qdrant => Arc::new(QdrantStore::from_url(&settings.qdrant_url)?),
+            VectorStoreKind::Memory => match &settings.vector_store_path {
+                Some(path) => Arc::new(MemoryVectorStore::open(path)?),
+                None => Arc::new(MemoryVectorStore::new()),
+            },
+        };
+        let chat_backend: Arc<dyn ChatBackend> = match &settings.llm_fixtures_dir {
+            Some(dir) => Arc::new(ScriptedBackend::from_dir(dir)?),
+            None => Arc::new(GenaiBackend::new(
+                Client::default(),
+                settings.llm_model.clone(),
+            )),
+        };
 
         // 1. Create the CUDA execution provider using the correct builder pattern from the documentation.
         let cuda_provider = CUDAExecutionProvider::default().build();
@@ -65,15 +169,57 @@ impl AppState {
             TextEmbedding::try_new(model_options)
                 .context("Failed to initialize embedding model with CUDA")?,
         );
+
+        Self::from_parts(settings, vector_store, chat_backend, embedding_model).await
+    }
+
+    /// Builds the state around the given vector store, LLM and embedder, taking
+    /// everything else from `settings`. With `MemoryVectorStore`, `ScriptedBackend`,
+    /// `HashEmbedder` and the fixture search provider, the whole pipeline runs
+    /// offline, without Qdrant, a hosted model or CUDA.
+    pub async fn from_parts(
+        settings: AppSettings,
+        vector_store: Arc<dyn VectorStore>,
+        chat_backend: Arc<dyn ChatBackend>,
+        embedding_model: Arc<dyn Embedder>,
+    ) -> Result<Self> {
+        let max_repair_attempts = settings.max_repair_attempts;
+        let run_settings = settings.sandbox.run.clone();
+        let build_cache = Arc::new(BuildCache::new(settings.sandbox.cache.clone())?);
+        let sandbox: Arc<dyn SandboxExecutor> = match settings.sandbox.executor {
+            SandboxExecutorKind::Host => Arc::new(HostExecutor),
+            SandboxExecutorKind::Docker => {
+                Arc::new(DockerExecutor::new(settings.sandbox.docker.clone())?)
+            }
+        };
+
+        crate_research::check_local_index(&settings.crate_research)?;
+
         // initialize qdrant collection if !exists
-        qdrant::ensure_collections_exist(&qdrant_client).await?;
+        qdrant::ensure_collections_exist(vector_store.as_ref()).await?;
         let http_client = Arc::new(reqwest::Client::new());
+        let http_cache = Arc::new(HttpCache::new(settings.http_cache.clone())?);
+        let search_provider: Arc<dyn SearchProvider> = Arc::new(CachedSearchProvider::new(
+            search_provider(&settings.search, http_client.clone())?,
+            format!("{:?}", settings.search.provider),
+            http_cache.clone(),
+        ));
         Ok(Self {
-            qdrant_client,
-            genai_client,
-            model,
-            embedding_model: embedding_model,
+            vector_store,
+            chat_backend,
+            embedding_model,
             http_client,
+            http_cache,
+            search_provider,
+            search_settings: settings.search.clone(),
+            crate_research: settings.crate_research,
+            sandbox,
+            build_cache,
+            ingest_jobs: Arc::new(IngestJobs::new()),
+            host_throttle: Arc::new(HostThrottle::new()),
+            crawl_settings: settings.crawler,
+            run_settings,
+            max_repair_attempts,
         })
     }
 }
@@ -122,57 +268,410 @@ impl AppState {
 
 
 
+/// Overall outcome of a query.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryStatus {
+    /// The final attempt compiled.
+    Success,
+    /// Every attempt failed to compile, including the repairs.
+    CompileFailed,
+    /// The final attempt compiled, but running it failed, panicked or was killed.
+    RunFailed,
+    /// The LLM did not return any code to build.
+    NoCode,
+}
+
+/// Per-query switches supplied by the caller.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct QueryOptions {
+    /// Run the program after a successful build and report its output.
+    #[serde(default)]
+    pub execute: bool,
+    /// Fed to the program's stdin when `execute` is set.
+    #[serde(default)]
+    pub stdin: Option<String>,
+}
+
+/// Wall-clock time spent in each stage of `process_query`, in milliseconds.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct QueryTimings {
+    pub context_ms: u64,
+    pub planning_ms: u64,
+    pub research_ms: u64,
+    pub generation_ms: u64,
+    pub build_ms: u64,
+    pub total_ms: u64,
+}
+
+/// The typed result of `process_query`, meant to be serialized straight to API clients.
+#[derive(Serialize, Debug, Clone)]
+pub struct QueryResult {
+    pub status: QueryStatus,
+    /// The code of the final attempt. Empty when the status is `NoCode`.
+    pub code: String,
+    pub dependencies: Vec<llm::Dependency>,
+    /// Structured compiler messages of the final attempt, warnings included.
+    pub diagnostics: Vec<Diagnostic>,
+    /// Human-readable build output of the final attempt. Empty on success.
+    pub build_output: String,
+    pub identified_crates: Vec<String>,
+    /// URLs of the web pages that were fed to the LLM.
+    pub sources: Vec<String>,
+    /// Output of running the final attempt, when execution was requested and the build succeeded.
+    pub run: Option<RunOutcome>,
+    /// `Cargo.toml` pinned to exact versions and its `Cargo.lock`, for reproducing the build.
+    pub manifest: Option<PinnedManifest>,
+    pub attempts: Vec<BuildAttempt>,
+    pub timings: QueryTimings,
+    /// Context that couldn't be gathered, such as searches or pages that failed
+    /// or timed out. The query went on without it.
+    pub warnings: Vec<String>,
+}
+
+fn elapsed_ms(since: Instant) -> u64 {
+    since.elapsed().as_millis() as u64
+}
+
+/// Marks the start of a pipeline stage and returns when it began. Fails instead
+/// once a streaming client has disconnected, so no more LLM calls or builds are
+/// spent on a result nobody will read.
+fn start_stage(events: &QueryEvents, stage: QueryStage) -> Result<Instant> {
+    events.ensure_connected()?;
+    events.emit(QueryEvent::StageStarted { stage });
+    Ok(Instant::now())
+}
+
+/// Marks the end of a pipeline stage and returns how long it took.
+fn finish_stage(events: &QueryEvents, stage: QueryStage, since: Instant) -> u64 {
+    let elapsed_ms = elapsed_ms(since);
+    events.emit(QueryEvent::StageFinished { stage, elapsed_ms });
+    elapsed_ms
+}
+
+/// Records context the query has to go on without.
+pub(crate) fn warn(warnings: &mut Vec<String>, message: String) {
+    println!("Warning: {}", message);
+    warnings.push(message);
+}
+
 /// The core query processing logic using a two-pass strategy.
-pub async fn process_query(query: &str, state: &AppState) -> Result<String> {
+pub async fn process_query(
+    query: &str,
+    options: &QueryOptions,
+    state: &AppState,
+) -> Result<QueryResult> {
+    process_query_with_events(query, options, state, &QueryEvents::none()).await
+}
+
+/// Runs `process_query` in the background and returns its progress as a stream of events.
+/// The stream always ends with either `QueryEvent::Finished` or `QueryEvent::Error`.
+pub fn process_query_stream(
+    query: String,
+    options: QueryOptions,
+    state: AppState,
+) -> impl Stream<Item = QueryEvent> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let events = QueryEvents::new(sender);
+        let final_event = match process_query_with_events(&query, &options, &state, &events).await {
+            Ok(result) => QueryEvent::Finished {
+                result: Box::new(result),
+            },
+            Err(e) => QueryEvent::Error {
+                message: format!("{e:#}"),
+            },
+        };
+        events.emit(final_event);
+    });
+    UnboundedReceiverStream::new(receiver)
+}
+
+/// `process_query`, reporting each stage, LLM token and build line to `events`.
+pub async fn process_query_with_events(
+    query: &str,
+    options: &QueryOptions,
+    state: &AppState,
+    events: &QueryEvents,
+) -> Result<QueryResult> {
+    let started = Instant::now();
+    let mut timings = QueryTimings::default();
+
     // === Step 1: Initial Context Gathering ===
-    let web_context = search_and_scrape(&state.http_client, query).await?;
+    let mut warnings = Vec::new();
+    let stage = start_stage(events, QueryStage::WebSearch)?;
+    let web_context = match search_and_scrape(state, query).await {
+        Ok(context) => context,
+        Err(e) => {
+            warn(&mut warnings, format!("Web search failed: {:#}", e));
+            WebContext::default()
+        }
+    };
+    for error in &web_context.errors {
+        warn(&mut warnings, error.clone());
+    }
+    timings.context_ms = finish_stage(events, QueryStage::WebSearch, stage);
+
+    let stage = start_stage(events, QueryStage::KnowledgeRetrieval)?;
     let db_context = qdrant::search_for_context(state, query).await?;
+    timings.context_ms += finish_stage(events, QueryStage::KnowledgeRetrieval, stage);
+
     let initial_context = format!(
         "Live Web Context:\n{}\n\nInternal Knowledge:\n{}",
-        web_context, db_context
+        web_context.text, db_context
     );
+    let mut sources = web_context.sources;
 
     // === Step 2: First Pass - Identify Required Crates ===
-    let required_crates = llm::identify_required_crates(state, query, &initial_context).await?;
+    let stage = start_stage(events, QueryStage::CratePlanning)?;
+    let mut required_crates =
+        llm::identify_required_crates(state, query, &initial_context, events).await?;
     println!("LLM identified required crates: {:?}", required_crates);
+    timings.planning_ms = finish_stage(events, QueryStage::CratePlanning, stage);
 
     // === Step 3: Research Step - Look Up Latest Crate Info ===
+    let stage = start_stage(events, QueryStage::CrateResearch)?;
+    // Crates are researched a few at a time; each reports its own failures.
+    let research: Vec<CrateResearch> = stream::iter(&required_crates)
+        .map(|crate_name| research_crate(state, query, crate_name))
+        .buffered(state.search_settings.concurrency.max(1))
+        .collect()
+        .await;
     let mut crate_research = String::new();
-    for crate_name in &required_crates {
-        let search_query = format!("crates.io rust crate {} latest API examples", crate_name);
-        println!("Researching crate: {}", search_query);
-        // Use your existing web_search module to perform the research!
-        let search_results = web_search::search_and_scrape(&state.http_client, &search_query).await?;
+    let mut missing_crates = Vec::new();
+    for (crate_name, research) in required_crates.iter().zip(research) {
+        crate_research.push_str(&research.text);
+        sources.extend(research.sources);
+        warnings.extend(research.warnings);
+        if research.missing {
+            missing_crates.push(crate_name.clone());
+        }
+    }
+    // Crates the LLM made up are dropped before it writes any code.
+    if !missing_crates.is_empty() {
+        required_crates.retain(|name| !missing_crates.contains(name));
         crate_research.push_str(&format!(
-            "\n--- Research for crate '{}': ---\n{}\n",
-            crate_name, search_results
+            "\n--- Crates that do not exist on crates.io; do not depend on them: {} ---\n",
+            missing_crates.join(", ")
         ));
     }
+    timings.research_ms = finish_stage(events, QueryStage::CrateResearch, stage);
 
     // === Step 4: Second Pass - Generate Code with Up-to-Date Context ===
-    let llm_response = llm::generate_code_with_research(state, query, &initial_context, &crate_research)
-        .await
-        .context("LLM failed to generate code in the second pass")?;
+    let stage = start_stage(events, QueryStage::Generation)?;
+    let llm_response =
+        llm::generate_code_with_research(state, query, &initial_context, &crate_research, events)
+            .await
+            .context("LLM failed to generate code in the second pass")?;
+    timings.generation_ms = finish_stage(events, QueryStage::Generation, stage);
 
     if llm_response.code.is_empty() {
-        return Ok("LLM failed to return a valid code block.".to_string());
+        timings.total_ms = elapsed_ms(started);
+        return Ok(QueryResult {
+            status: QueryStatus::NoCode,
+            code: String::new(),
+            dependencies: llm_response.dependencies,
+            diagnostics: Vec::new(),
+            build_output: "LLM failed to return a valid code block.".to_string(),
+            identified_crates: required_crates,
+            sources,
+            run: None,
+            manifest: None,
+            attempts: Vec::new(),
+            timings,
+            warnings,
+        });
     }
 
-    // === Step 5: Sandbox Execution (No changes needed here) ===
-    let sandbox_result =
-        sandbox::run_in_sandbox(&llm_response.code, &llm_response.dependencies).await?;
+    // === Step 5: Sandbox Execution with Repair Loop ===
+    let stage = Instant::now();
+    let run_options = options.execute.then(|| RunOptions {
+        stdin: options.stdin.clone(),
+        timeout_secs: state.run_settings.timeout_secs,
+        max_output_bytes: state.run_settings.max_output_bytes,
+    });
+    let attempts = build_with_repair(
+        state,
+        query,
+        llm_response,
+        run_options.as_ref(),
+        &mut warnings,
+        events,
+    )
+    .await?;
+    let last = attempts.last().context("Repair loop produced no attempts")?.clone();
+    timings.build_ms = elapsed_ms(stage);
+    timings.total_ms = elapsed_ms(started);
 
-    let response = if sandbox_result.success {
-        format!(
-            "OK. AI-generated code compiled successfully.\n---\n{}",
-            llm_response.code
-        )
-    } else {
-        format!(
-            "FAIL. AI-generated code failed to compile.\n---\nErrors:\n{}",
-            sandbox_result.output
+    Ok(QueryResult {
+        status: match &last.run {
+            _ if !last.success => QueryStatus::CompileFailed,
+            Some(run) if !run.succeeded() => QueryStatus::RunFailed,
+            _ => QueryStatus::Success,
+        },
+        code: last.code,
+        dependencies: last
+            .manifest
+            .as_ref()
+            .map(|m| m.dependencies.clone())
+            .unwrap_or(last.dependencies),
+        diagnostics: last.diagnostics,
+        build_output: last.output,
+        identified_crates: required_crates,
+        sources,
+        run: last.run,
+        manifest: last.manifest,
+        attempts,
+        timings,
+        warnings,
+    })
+}
+
+/// One round of the build/repair loop: the code that was tried and what the sandbox said about it.
+#[derive(Serialize, Debug, Clone)]
+pub struct BuildAttempt {
+    pub code: String,
+    pub dependencies: Vec<llm::Dependency>,
+    pub success: bool,
+    pub output: String,
+    pub diagnostics: Vec<Diagnostic>,
+    pub run: Option<RunOutcome>,
+    pub manifest: Option<PinnedManifest>,
+    /// Whether this attempt's code came from rustc's machine-applicable suggestions
+    /// rather than from the LLM.
+    pub auto_fixed: bool,
+    /// Misspelt dependency names and features corrected before this build.
+    pub dependency_corrections: Vec<String>,
+}
+
+/// Builds the generated code in the sandbox and, on failure, first applies rustc's
+/// machine-applicable suggestions, then feeds the compiler diagnostics back to the
+/// LLM for a fix. Stops on the first successful build or after
+/// `state.max_repair_attempts` repairs, returning every attempt in order.
+///
+/// Before each build the dependencies are checked against crates.io: misspelt
+/// features are corrected, and crates or features that don't exist are sent
+/// back to the LLM, up to `state.max_repair_attempts` times, without building.
+pub async fn build_with_repair(
+    state: &AppState,
+    query: &str,
+    initial: llm::LlmCodeResponse,
+    run: Option<&RunOptions>,
+    warnings: &mut Vec<String>,
+    events: &QueryEvents,
+) -> Result<Vec<BuildAttempt>> {
+    let mut attempts: Vec<BuildAttempt> = Vec::new();
+    let mut current = initial;
+    let mut auto_fixed = false;
+    let mut dependency_repairs = 0;
+    let mut dependency_corrections = Vec::new();
+    // The dependencies as last checked, so fixes that leave them alone don't
+    // look them up (and warn about an unreachable registry) again.
+    let mut checked_dependencies: Option<Vec<llm::Dependency>> = None;
+
+    loop {
+        if checked_dependencies.as_ref() != Some(&current.dependencies) {
+            let check =
+                dependency_check::check_dependencies(state, &mut current.dependencies).await;
+            checked_dependencies = Some(current.dependencies.clone());
+            for correction in &check.corrections {
+                println!("Corrected dependency: {}", correction);
+            }
+            dependency_corrections.extend(check.corrections.iter().cloned());
+            for warning in &check.warnings {
+                warn(warnings, warning.clone());
+            }
+            if !check.problems.is_empty() && dependency_repairs < state.max_repair_attempts {
+                dependency_repairs += 1;
+                println!("Dependency check failed. Asking the LLM for a repair...");
+                let stage = start_stage(events, QueryStage::Repair)?;
+                let repaired =
+                    llm::repair_code(state, query, &current, &check.report(), events).await;
+                finish_stage(events, QueryStage::Repair, stage);
+                match repaired {
+                    Ok(repaired) if !repaired.code.is_empty() => {
+                        current = repaired;
+                        continue;
+                    }
+                    Ok(_) => warn(
+                        warnings,
+                        "LLM returned an empty dependency repair; building as is".to_string(),
+                    ),
+                    Err(e) => warn(
+                        warnings,
+                        format!("LLM dependency repair failed: {:#}; building as is", e),
+                    ),
+                }
+            }
+        }
+
+        let stage = start_stage(events, QueryStage::Build)?;
+        let sandbox_result = sandbox::run_in_sandbox(
+            state.sandbox.as_ref(),
+            &state.build_cache,
+            &current.code,
+            &current.dependencies,
+            run,
+            events,
         )
-    };
+        .await?;
+        finish_stage(events, QueryStage::Build, stage);
+        let success = sandbox_result.success;
+        attempts.push(BuildAttempt {
+            code: current.code.clone(),
+            dependencies: current.dependencies.clone(),
+            success,
+            output: sandbox_result.output.clone(),
+            diagnostics: sandbox_result.diagnostics.clone(),
+            run: sandbox_result.run,
+            manifest: sandbox_result.manifest.clone(),
+            auto_fixed,
+            dependency_corrections: std::mem::take(&mut dependency_corrections),
+        });
+
+        if success || attempts.len() > state.max_repair_attempts {
+            break;
+        }
 
-    Ok(response)
-}
\ No newline at end of file
+        // Rustc's own fixes are cheaper than an LLM round. Only try them once in a row,
+        // so a suggestion that doesn't help can't loop forever.
+        if !auto_fixed {
+            if let Some(fixed) =
+                diagnostics::apply_machine_applicable(&current.code, &sandbox_result.diagnostics)
+            {
+                println!("Applying rustc's machine-applicable suggestions...");
+                current.code = fixed;
+                auto_fixed = true;
+                continue;
+            }
+        }
+        auto_fixed = false;
+
+        let compiler_output = if sandbox_result.diagnostics.iter().any(|d| d.is_error()) {
+            diagnostics::format_for_prompt(&sandbox_result.diagnostics)
+        } else {
+            sandbox_result.output.clone()
+        };
+
+        println!(
+            "Build failed (attempt {}). Asking the LLM for a repair...",
+            attempts.len()
+        );
+        let stage = start_stage(events, QueryStage::Repair)?;
+        let repaired = llm::repair_code(state, query, &current, &compiler_output, events).await;
+        finish_stage(events, QueryStage::Repair, stage);
+        current = match repaired {
+            Ok(repaired) if !repaired.code.is_empty() => repaired,
+            Ok(_) => {
+                println!("Warning: LLM returned an empty repair. Stopping repair loop.");
+                break;
+            }
+            Err(e) => {
+                println!("Warning: LLM repair failed: {e:#}. Stopping repair loop.");
+                break;
+            }
+        };
+    }
+
+    Ok(attempts)
+}
@@ -1,15 +1,19 @@
 use anyhow::{anyhow, Context, Result};
-use genai::chat::{ChatMessage, ChatRequest};
-use serde::Deserialize;
-use crate::AppState;
+use serde::{Deserialize, Serialize};
+use crate::{AppState, chat_backend::ChatPrompt, events::QueryEvents};
 
 // This struct is for the FINAL response (code + deps with features)
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Dependency {
     pub name: String,
+    /// Version requirement, e.g. `1.38`. After a build it holds the exact
+    /// resolved version as `=1.38.2`. `None` lets cargo pick the latest.
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
     pub features: Vec<String>,
 }
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LlmCodeResponse {
     pub dependencies: Vec<Dependency>,
     pub code: String,
@@ -26,6 +30,7 @@ pub async fn identify_required_crates(
     state: &AppState,
     query: &str,
     context: &str,
+    events: &QueryEvents,
 ) -> Result<Vec<String>> {
     let system_prompt = r#"You are a Rust project planning expert. Your task is to analyze a user's query and the provided context, and determine which external crates from crates.io are necessary to solve the problem.
 
@@ -41,24 +46,15 @@ EXAMPLE:
         context, query
     );
 
-    let request = ChatRequest::new(vec![
-        ChatMessage::system(system_prompt),
-        ChatMessage::user(&user_prompt),
-    ]);
-
-    let response = &state
-        .genai_client
-        .exec_chat(&state.model, request, None)
-        .await?;
+    let prompt = ChatPrompt::new(system_prompt, user_prompt);
 
-    let generated_content = response
-        .content_text_as_str()
+    let generated_content = &state
+        .chat_backend
+        .chat(&prompt, events)
+        .await?
         .ok_or_else(|| anyhow!("No text content found in LLM planning response"))?;
 
-    let json_start = generated_content.find('{').unwrap_or(0);
-    let json_end = generated_content.rfind('}').unwrap_or_else(|| generated_content.len());
-    let json_str = &generated_content[json_start..=json_end];
-
+    let json_str = json_object(generated_content)?;
     let plan: LlmCratePlan = serde_json::from_str(json_str)
         .with_context(|| format!("Failed to parse crate plan from LLM: {}", json_str))?;
 
@@ -71,6 +67,7 @@ pub async fn generate_code_with_research(
     query: &str,
     context: &str,
     crate_research: &str,
+    events: &QueryEvents,
 ) -> Result<LlmCodeResponse> {
     // This prompt now includes the critical, reinforced rule about importing traits.
     let system_prompt = format!(
@@ -84,7 +81,7 @@ pub async fn generate_code_with_research(
 2.  The code you generate MUST be pure Rust and depend ONLY on real crates from crates.io as detailed in the research. It CANNOT require any external programs or libraries from other languages.
 3.  You MUST write code that is compatible with the latest crate versions found in the research provided.
 4.  The JSON object you provide MUST contain two keys:
-    a. `"dependencies"`: An array of objects. Each object must have a `"name"` (string, kebab-case) and a `"features"` (array of strings) key.
+    a. `"dependencies"`: An array of objects. Each object must have a `"name"` (string, kebab-case), a `"version"` (string, the semver requirement matching the version in the research, e.g. `"1.38"`) and a `"features"` (array of strings) key.
     b. `"code"`: A string containing the complete, runnable Rust code, self-contained in a `main` function.
 5.  When printing a struct or other complex type, you MUST use the debug formatter `{{:?}}`.
 "#,
@@ -96,24 +93,107 @@ pub async fn generate_code_with_research(
         context, query
     );
 
-    let request = ChatRequest::new(vec![
-        ChatMessage::system(&system_prompt),
-        ChatMessage::user(&user_prompt),
-    ]);
-
-    let response = &state
-        .genai_client
-        .exec_chat(&state.model, request, None)
-        .await?;
-        
-    let generated_content = response
-        .content_text_as_str()
+    let prompt = ChatPrompt::new(system_prompt, user_prompt);
+
+    let generated_content = &state
+        .chat_backend
+        .chat(&prompt, events)
+        .await?
         .ok_or_else(|| anyhow!("No text content found in LLM generation response"))?;
 
-    let json_start = generated_content.find('{').unwrap_or(0);
-    let json_end = generated_content.rfind('}').unwrap_or_else(|| generated_content.len());
-    let json_str = &generated_content[json_start..=json_end];
+    parse_code_response(generated_content)
+}
+
+/// REPAIR PASS: Feeds the failing code and the compiler diagnostics back to the LLM
+/// and asks for a corrected version.
+pub async fn repair_code(
+    state: &AppState,
+    query: &str,
+    previous: &LlmCodeResponse,
+    compiler_output: &str,
+    events: &QueryEvents,
+) -> Result<LlmCodeResponse> {
+    let system_prompt = r#"You are an expert Rust programmer fixing code that failed to compile. You will be given the original query, the dependencies and code that were tried, and either the compiler output from `cargo build` or the problems a check of the dependencies against crates.io found before building. Your task is to provide a single, corrected JSON object.
+
+# RULES
+1.  Read the compiler errors carefully and fix the root cause. Missing trait imports (`use` statements for traits that provide methods) and wrong feature flags on dependencies are the most common causes.
+2.  Change as little as possible. Keep the parts of the code that are not related to the errors.
+3.  You may add, remove or change dependencies and their features if the errors require it. Only use real crates from crates.io.
+4.  The JSON object you provide MUST contain two keys:
+    a. `"dependencies"`: An array of objects. Each object must have a `"name"` (string, kebab-case), a `"version"` (string, semver requirement) and a `"features"` (array of strings) key.
+    b. `"code"`: A string containing the complete, corrected Rust code, self-contained in a `main` function.
+"#;
+
+    let dependencies = previous
+        .dependencies
+        .iter()
+        .map(|d| {
+            format!(
+                "- {} {} (features: [{}])",
+                d.name,
+                d.version.as_deref().unwrap_or("*"),
+                d.features.join(", ")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let user_prompt = format!(
+        "QUERY: {}\n\n---\n\nDEPENDENCIES:\n{}\n\n---\n\nCODE:\n```rust\n{}\n```\n\n---\n\nCOMPILER OUTPUT:\n{}\n\n---\n\nTASK: Fix the code so that it compiles and still answers the query. Respond with the corrected JSON object.",
+        query, dependencies, previous.code, compiler_output
+    );
+
+    let prompt = ChatPrompt::new(system_prompt, user_prompt);
+
+    let generated_content = &state
+        .chat_backend
+        .chat(&prompt, events)
+        .await?
+        .ok_or_else(|| anyhow!("No text content found in LLM repair response"))?;
 
+    parse_code_response(generated_content)
+}
+
+/// The outermost `{...}` of an LLM answer, which may wrap it in prose or a code fence.
+fn json_object(generated_content: &str) -> Result<&str> {
+    generated_content
+        .find('{')
+        .zip(generated_content.rfind('}'))
+        .filter(|(start, end)| start < end)
+        .map(|(start, end)| &generated_content[start..=end])
+        .ok_or_else(|| {
+            anyhow!(
+                "No JSON object found in LLM response: {}",
+                generated_content
+            )
+        })
+}
+
+/// Extracts the outermost JSON object from an LLM answer and parses it as a code response.
+fn parse_code_response(generated_content: &str) -> Result<LlmCodeResponse> {
+    let json_str = json_object(generated_content)?;
     serde_json::from_str(json_str)
         .with_context(|| format!("Failed to parse JSON from LLM response: {}", json_str))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_json_object_around_prose() {
+        let response = parse_code_response(
+            "Here you go:\n```json\n{\"dependencies\": [], \"code\": \"fn main() {}\"}\n```",
+        )
+        .unwrap();
+        assert_eq!(response.code, "fn main() {}");
+    }
+
+    #[test]
+    fn replies_without_an_object_are_errors() {
+        assert!(parse_code_response("").is_err());
+        assert!(parse_code_response("no json here").is_err());
+        assert!(parse_code_response("{ unterminated").is_err());
+        assert!(parse_code_response("} backwards {").is_err());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,238 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::llm::Dependency;
+
+/// Name of the generated package, as it appears in `Cargo.lock`.
+pub const SANDBOX_PACKAGE: &str = "sandbox";
+
+/// A manifest whose dependencies are pinned to exact versions, with the lockfile that produced it.
+/// Building these two files again gives the same dependency graph.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PinnedManifest {
+    /// The dependencies with `version` set to the exact resolved version.
+    pub dependencies: Vec<Dependency>,
+    pub cargo_toml: String,
+    pub cargo_lock: String,
+}
+
+#[derive(Deserialize)]
+struct CargoLock {
+    #[serde(default)]
+    package: Vec<LockedPackage>,
+}
+
+#[derive(Deserialize)]
+struct LockedPackage {
+    name: String,
+    version: String,
+    source: Option<String>,
+    #[serde(default)]
+    dependencies: Vec<String>,
+}
+
+/// Crate names are matched loosely, since `serde_json` and `serde-json` are the same to cargo's resolver input.
+fn normalize(name: &str) -> String {
+    name.replace('_', "-")
+}
+
+#[derive(Serialize)]
+struct Manifest<'a> {
+    package: Package,
+    dependencies: BTreeMap<&'a str, DependencySpec<'a>>,
+}
+
+#[derive(Serialize)]
+struct Package {
+    name: &'static str,
+    version: &'static str,
+    edition: &'static str,
+}
+
+#[derive(Serialize)]
+struct DependencySpec<'a> {
+    version: &'a str,
+    features: &'a [String],
+}
+
+/// Renders the sandbox `Cargo.toml`. Dependencies without a version get `*`.
+/// Names, versions and features come from the model, so they are serialized
+/// as TOML rather than pasted in, and can't add keys or tables of their own.
+pub fn render_manifest(dependencies: &[Dependency]) -> Result<String> {
+    let manifest = Manifest {
+        package: Package {
+            name: SANDBOX_PACKAGE,
+            version: "0.1.0",
+            edition: "2024",
+        },
+        dependencies: dependencies
+            .iter()
+            .map(|dep| {
+                let spec = DependencySpec {
+                    version: dep.version.as_deref().unwrap_or("*"),
+                    features: &dep.features,
+                };
+                (dep.name.as_str(), spec)
+            })
+            .collect(),
+    };
+    toml::to_string(&manifest).context("Failed to render Cargo.toml")
+}
+
+/// Reads the versions cargo resolved for the sandbox's direct dependencies from `Cargo.lock`.
+pub fn resolved_versions(cargo_lock: &str) -> Result<HashMap<String, String>> {
+    let lock: CargoLock = toml::from_str(cargo_lock).context("Failed to parse Cargo.lock")?;
+
+    let root = lock
+        .package
+        .iter()
+        .find(|p| p.name == SANDBOX_PACKAGE && p.source.is_none())
+        .context("Cargo.lock has no entry for the sandbox package")?;
+
+    // Entries are `name`, or `name version` when several versions of a crate are in the graph.
+    let mut versions = HashMap::new();
+    for entry in &root.dependencies {
+        let mut parts = entry.split_whitespace();
+        let Some(name) = parts.next() else {
+            continue;
+        };
+        let version = match parts.next() {
+            Some(version) => Some(version.to_string()),
+            None => lock
+                .package
+                .iter()
+                .find(|p| p.name == name)
+                .map(|p| p.version.clone()),
+        };
+        if let Some(version) = version {
+            versions.insert(normalize(name), version);
+        }
+    }
+    Ok(versions)
+}
+
+/// Pins every dependency to the exact version recorded in `cargo_lock`.
+/// Dependencies missing from the lockfile keep their original requirement.
+pub fn pin_dependencies(dependencies: &[Dependency], cargo_lock: &str) -> Result<Vec<Dependency>> {
+    let versions = resolved_versions(cargo_lock)?;
+    Ok(dependencies
+        .iter()
+        .map(|dep| {
+            let mut pinned = dep.clone();
+            if let Some(version) = versions.get(&normalize(&dep.name)) {
+                pinned.version = Some(format!("={}", version));
+            }
+            pinned
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The sandbox depends on `rand` 0.8 and `serde_json`, while `other` pulls
+    /// in `rand` 0.9, so `rand` appears twice.
+    const CARGO_LOCK: &str = r#"
+version = 4
+
+[[package]]
+name = "other"
+version = "1.0.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+dependencies = [
+ "rand 0.9.0",
+]
+
+[[package]]
+name = "rand"
+version = "0.9.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "rand"
+version = "0.8.5"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "sandbox"
+version = "0.1.0"
+dependencies = [
+ "other",
+ "rand 0.8.5",
+ "serde_json",
+]
+
+[[package]]
+name = "serde_json"
+version = "1.0.141"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+"#;
+
+    fn dep(name: &str, version: Option<&str>) -> Dependency {
+        Dependency {
+            name: name.to_string(),
+            version: version.map(str::to_string),
+            features: vec!["std".to_string()],
+        }
+    }
+
+    #[test]
+    fn resolves_direct_dependencies_only() {
+        let versions = resolved_versions(CARGO_LOCK).unwrap();
+        assert_eq!(versions.len(), 3);
+        assert_eq!(versions["rand"], "0.8.5");
+        assert_eq!(versions["serde-json"], "1.0.141");
+        assert_eq!(versions["other"], "1.0.0");
+    }
+
+    #[test]
+    fn pins_dependencies_to_the_locked_versions() {
+        let dependencies = [
+            dep("rand", Some("0.8")),
+            dep("serde-json", None),
+            dep("not-built", Some("2")),
+        ];
+        let pinned = pin_dependencies(&dependencies, CARGO_LOCK).unwrap();
+        let versions: Vec<_> = pinned.iter().map(|d| d.version.as_deref()).collect();
+        assert_eq!(versions, [Some("=0.8.5"), Some("=1.0.141"), Some("2")]);
+        assert_eq!(pinned[0].features, ["std"]);
+    }
+
+    #[test]
+    fn manifest_values_cannot_escape_their_strings() {
+        let dependencies = [
+            Dependency {
+                name: "rand".to_string(),
+                version: Some("0.8\"\n[patch.crates-io]\nrand = { path = \"/\" }\n#".to_string()),
+                features: vec!["std\", \"evil".to_string()],
+            },
+            dep("serde-json", None),
+        ];
+        let manifest: toml::Table =
+            toml::from_str(&render_manifest(&dependencies).unwrap()).unwrap();
+        assert_eq!(
+            manifest.keys().collect::<Vec<_>>(),
+            ["dependencies", "package"]
+        );
+        assert_eq!(manifest["package"]["name"].as_str(), Some(SANDBOX_PACKAGE));
+
+        let rand = &manifest["dependencies"]["rand"];
+        assert_eq!(rand["version"].as_str(), dependencies[0].version.as_deref());
+        assert_eq!(rand["features"].as_array().unwrap().len(), 1);
+        assert_eq!(rand["features"][0].as_str(), Some("std\", \"evil"));
+        assert_eq!(
+            manifest["dependencies"]["serde-json"]["version"].as_str(),
+            Some("*")
+        );
+    }
+
+    #[test]
+    fn lockfiles_without_the_sandbox_are_errors() {
+        let lock = "version = 4\n\n[[package]]\nname = \"rand\"\nversion = \"0.8.5\"\n";
+        assert!(resolved_versions(lock).is_err());
+        assert!(resolved_versions("not toml [").is_err());
+    }
+}
@@ -0,0 +1,241 @@
+//! Splits Markdown by its heading hierarchy. Fenced code blocks are never cut,
+//! and every chunk knows the headings it sits under.
+
+use crate::ingestion::split_into_chunks;
+
+/// Sections are packed into chunks of up to this many characters. A single
+/// fenced code block longer than this still becomes one chunk.
+const MAX_CHUNK_CHARS: usize = 1000;
+
+/// A piece of a Markdown document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarkdownChunk {
+    pub text: String,
+    /// Headings above the chunk, outermost first. Empty before the first heading.
+    pub headings: Vec<String>,
+}
+
+impl MarkdownChunk {
+    /// The headings as a breadcrumb, e.g. `Guide > Spawning > Join handles`.
+    pub fn breadcrumb(&self) -> Option<String> {
+        (!self.headings.is_empty()).then(|| self.headings.join(" > "))
+    }
+}
+
+/// An opening code fence: the fence character and how many of them.
+struct Fence {
+    marker: char,
+    len: usize,
+}
+
+fn parse_fence(line: &str) -> Option<Fence> {
+    let trimmed = line.trim_start();
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let marker = trimmed.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let len = trimmed.chars().take_while(|c| *c == marker).count();
+    (len >= 3).then_some(Fence { marker, len })
+}
+
+impl Fence {
+    fn is_closed_by(&self, line: &str) -> bool {
+        match parse_fence(line) {
+            Some(close) => {
+                close.marker == self.marker
+                    && close.len >= self.len
+                    && line.trim().chars().all(|c| c == self.marker)
+            }
+            None => false,
+        }
+    }
+}
+
+/// `## Title` as `(2, "Title")`.
+fn parse_heading(line: &str) -> Option<(usize, String)> {
+    let trimmed = line.trim_start();
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let level = trimmed.chars().take_while(|c| *c == '#').count();
+    if !(1..=6).contains(&level) {
+        return None;
+    }
+    let rest = &trimmed[level..];
+    if !rest.is_empty() && !rest.starts_with([' ', '\t']) {
+        return None;
+    }
+    let title = rest.trim().trim_end_matches('#').trim_end();
+    Some((level, title.to_string()))
+}
+
+/// A run of lines that is kept together if at all possible.
+struct Block {
+    text: String,
+    is_code: bool,
+}
+
+/// A heading's content: the headings above it and its blocks (paragraphs and code fences).
+struct Section {
+    headings: Vec<String>,
+    blocks: Vec<Block>,
+}
+
+fn split_sections(markdown: &str) -> Vec<Section> {
+    let mut sections = vec![Section {
+        headings: Vec::new(),
+        blocks: Vec::new(),
+    }];
+    // (level, title) of every heading above the current line.
+    let mut stack: Vec<(usize, String)> = Vec::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut code: Vec<&str> = Vec::new();
+    let mut fence: Option<Fence> = None;
+
+    fn flush(lines: &mut Vec<&str>, is_code: bool, section: &mut Section) {
+        if lines.iter().any(|l| !l.trim().is_empty()) {
+            section.blocks.push(Block {
+                text: lines.join("\n").trim_end().to_string(),
+                is_code,
+            });
+        }
+        lines.clear();
+    }
+
+    for line in markdown.lines() {
+        let section = sections.last_mut().unwrap();
+        if let Some(open) = &fence {
+            code.push(line);
+            if open.is_closed_by(line) {
+                fence = None;
+                flush(&mut code, true, section);
+            }
+            continue;
+        }
+        if let Some(open) = parse_fence(line) {
+            flush(&mut paragraph, false, section);
+            fence = Some(open);
+            code.push(line);
+            continue;
+        }
+        if let Some((level, title)) = parse_heading(line) {
+            flush(&mut paragraph, false, section);
+            stack.retain(|(l, _)| *l < level);
+            stack.push((level, title));
+            sections.push(Section {
+                headings: stack.iter().map(|(_, t)| t.clone()).collect(),
+                blocks: vec![Block {
+                    text: line.trim().to_string(),
+                    is_code: false,
+                }],
+            });
+            continue;
+        }
+        if line.trim().is_empty() {
+            flush(&mut paragraph, false, section);
+        } else {
+            paragraph.push(line);
+        }
+    }
+
+    // An unclosed fence runs to the end of the document, as in CommonMark.
+    let section = sections.last_mut().unwrap();
+    flush(&mut paragraph, false, section);
+    flush(&mut code, true, section);
+    sections
+}
+
+/// Splits `markdown` into chunks that each stay within one section, packing
+/// consecutive paragraphs and code blocks together up to `MAX_CHUNK_CHARS`.
+pub fn chunk_markdown(markdown: &str) -> Vec<MarkdownChunk> {
+    let mut chunks = Vec::new();
+    for section in split_sections(markdown) {
+        let mut current = String::new();
+        let mut emit = |text: &mut String| {
+            if !text.trim().is_empty() {
+                chunks.push(MarkdownChunk {
+                    text: std::mem::take(text),
+                    headings: section.headings.clone(),
+                });
+            }
+            text.clear();
+        };
+
+        for block in &section.blocks {
+            let block_len = block.text.chars().count();
+            if !current.is_empty() && current.chars().count() + 2 + block_len > MAX_CHUNK_CHARS {
+                emit(&mut current);
+            }
+            if block_len <= MAX_CHUNK_CHARS || block.is_code {
+                if !current.is_empty() {
+                    current.push_str("\n\n");
+                }
+                current.push_str(&block.text);
+                continue;
+            }
+            // An oversized paragraph is the only thing that gets split mid-block.
+            let pieces = split_into_chunks(&block.text).unwrap_or_else(|_| vec![block.text.clone()]);
+            for mut piece in pieces {
+                emit(&mut piece);
+            }
+        }
+        emit(&mut current);
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_carry_their_heading_breadcrumb() {
+        let markdown = "Intro text.\n\n# Guide\n\nSome prose.\n\n## Spawning\n\nUse spawn.\n\n### Join handles\n\nAwait them.\n\n## Channels\n\nUse mpsc.\n";
+        let chunks = chunk_markdown(markdown);
+        let crumbs: Vec<Option<String>> = chunks.iter().map(|c| c.breadcrumb()).collect();
+
+        assert_eq!(
+            crumbs,
+            [
+                None,
+                Some("Guide".to_string()),
+                Some("Guide > Spawning".to_string()),
+                Some("Guide > Spawning > Join handles".to_string()),
+                Some("Guide > Channels".to_string()),
+            ]
+        );
+        assert_eq!(chunks[2].text, "## Spawning\n\nUse spawn.");
+    }
+
+    #[test]
+    fn headings_inside_code_fences_are_ignored() {
+        let markdown = "# Shell\n\n```sh\n# not a heading\nls\n```\n";
+        let chunks = chunk_markdown(markdown);
+
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].headings, ["Shell"]);
+        assert!(chunks[0].text.ends_with("```sh\n# not a heading\nls\n```"));
+    }
+
+    #[test]
+    fn long_code_blocks_are_never_split() {
+        let body: String = (0..200).map(|i| format!("let x{i} = {i};\n")).collect();
+        let markdown = format!("# Example\n\nBefore.\n\n```rust\n{body}```\n\nAfter.\n");
+        let chunks = chunk_markdown(&markdown);
+
+        let code: Vec<&MarkdownChunk> = chunks.iter().filter(|c| c.text.contains("```rust")).collect();
+        assert_eq!(code.len(), 1);
+        assert!(code[0].text.starts_with("```rust\n"));
+        assert!(code[0].text.ends_with("```"));
+        assert!(code[0].text.contains("let x199 = 199;"));
+    }
+
+    #[test]
+    fn longer_fences_need_a_matching_close() {
+        let markdown = "````md\n```rust\nfn main() {}\n```\n````\n\n# After\n";
+        let chunks = chunk_markdown(markdown);
+
+        assert_eq!(chunks[0].text, "````md\n```rust\nfn main() {}\n```\n````");
+        assert_eq!(chunks[1].headings, ["After"]);
+    }
+}
@@ -0,0 +1,251 @@
+//! The payload schema for everything stored in the vector store. Ingestion,
+//! feedback and search all go through these types, so a key can't be written
+//! under one name and read under another.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::vector_store::PointPayload;
+
+/// A chunk of an ingested document in the knowledge base.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KnowledgeChunk {
+    /// The chunk's text. Older points stored it under `chunk`.
+    #[serde(alias = "chunk")]
+    pub text: String,
+    /// The document the chunk belongs to; see `documents`.
+    #[serde(default, alias = "source_id")]
+    pub document_id: String,
+    /// Where the document came from: a URL, a file name, or `pasted text`.
+    #[serde(default)]
+    pub source: String,
+    /// Position of the chunk within its document, starting at 0.
+    #[serde(default)]
+    pub chunk_index: usize,
+    /// Hex SHA-256 of `text`.
+    #[serde(default)]
+    pub content_hash: String,
+    /// Unix timestamp, in seconds.
+    #[serde(default)]
+    pub ingested_at: u64,
+    /// For source code: path of the item the chunk holds, e.g. `net::TcpStream::connect`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub item_path: Option<String>,
+    /// For source code: `fn`, `struct`, `impl`, `method`, ...
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub item_kind: Option<String>,
+    /// For Markdown: the headings above the chunk, e.g. `Guide > Spawning`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heading_path: Option<String>,
+    /// For crate sources: the crate the chunk documents, with `-` written as `_`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crate_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crate_version: Option<String>,
+}
+
+impl KnowledgeChunk {
+    /// Builds a chunk, filling in the content hash and the ingest time.
+    pub fn new(
+        text: impl Into<String>,
+        document_id: impl Into<String>,
+        source: impl Into<String>,
+        chunk_index: usize,
+    ) -> Self {
+        let text = text.into();
+        Self {
+            content_hash: content_hash(&text),
+            text,
+            document_id: document_id.into(),
+            source: source.into(),
+            chunk_index,
+            ingested_at: unix_now(),
+            item_path: None,
+            item_kind: None,
+            heading_path: None,
+            crate_name: None,
+            crate_version: None,
+        }
+    }
+
+    /// Marks the chunk as holding a single item of Rust source.
+    pub fn with_item(mut self, path: impl Into<String>, kind: impl Into<String>) -> Self {
+        self.item_path = Some(path.into());
+        self.item_kind = Some(kind.into());
+        self
+    }
+
+    /// Tags the chunk as documentation of a specific crate release.
+    pub fn with_crate(mut self, name: &str, version: impl Into<String>) -> Self {
+        self.crate_name = Some(normalize_crate_name(name));
+        self.crate_version = Some(version.into());
+        self
+    }
+
+    /// The chunk's point id: derived from its document and content, so ingesting
+    /// the same text into the same document again overwrites instead of duplicating.
+    pub fn point_id(&self) -> String {
+        derived_id(&format!("{}\n{}", self.document_id, self.content_hash))
+    }
+
+    /// Whether `other` stores the same text and metadata, ignoring where in the
+    /// document it sits and when it was ingested. Such a chunk keeps its vector.
+    pub fn same_content(&self, other: &KnowledgeChunk) -> bool {
+        let mut other = other.clone();
+        other.chunk_index = self.chunk_index;
+        other.ingested_at = self.ingested_at;
+        *self == other
+    }
+
+    /// Records the Markdown section the chunk came from.
+    pub fn with_heading_path(mut self, heading_path: Option<String>) -> Self {
+        self.heading_path = heading_path;
+        self
+    }
+
+    pub fn to_payload(&self) -> Result<PointPayload> {
+        to_payload(self)
+    }
+
+    pub fn from_payload(payload: &PointPayload) -> Result<Self> {
+        from_payload(payload)
+    }
+}
+
+/// An upvoted query/code pair in the approved solutions collection.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ApprovedSolution {
+    pub query: String,
+    pub code: String,
+    /// Pinned manifest the code was built with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cargo_toml: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cargo_lock: Option<String>,
+}
+
+impl ApprovedSolution {
+    pub fn to_payload(&self) -> Result<PointPayload> {
+        to_payload(self)
+    }
+
+    pub fn from_payload(payload: &PointPayload) -> Result<Self> {
+        from_payload(payload)
+    }
+}
+
+fn to_payload<T: Serialize>(value: &T) -> Result<PointPayload> {
+    match serde_json::to_value(value)? {
+        Value::Object(map) => Ok(map),
+        other => Err(anyhow!("Payload must serialize to a JSON object, got {}", other)),
+    }
+}
+
+fn from_payload<T: DeserializeOwned>(payload: &PointPayload) -> Result<T> {
+    serde_json::from_value(Value::Object(payload.clone())).context("Payload does not match schema")
+}
+
+/// The form crate names are stored and looked up in: `serde-json` and `serde_json` are the same crate.
+pub fn normalize_crate_name(name: &str) -> String {
+    name.trim().replace('-', "_").to_lowercase()
+}
+
+/// A UUID that is a function of `key`. Qdrant only accepts UUIDs and integers as point ids.
+pub fn derived_id(key: &str) -> String {
+    let digest = Sha256::digest(key.as_bytes());
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    uuid::Uuid::from_bytes(bytes).to_string()
+}
+
+/// Hex SHA-256 of `text`.
+pub fn content_hash(text: &str) -> String {
+    Sha256::digest(text.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn knowledge_chunk_round_trips() {
+        let chunk = KnowledgeChunk::new("fn main() {}", "doc-1", "https://example.com", 3);
+        let payload = chunk.to_payload().unwrap();
+
+        assert_eq!(payload["text"], "fn main() {}");
+        assert_eq!(payload["document_id"], "doc-1");
+        assert_eq!(payload["chunk_index"], 3);
+        assert_eq!(KnowledgeChunk::from_payload(&payload).unwrap(), chunk);
+    }
+
+    #[test]
+    fn knowledge_chunk_reads_legacy_keys() {
+        let mut legacy = PointPayload::new();
+        legacy.insert("chunk".to_string(), "old text".into());
+
+        let chunk = KnowledgeChunk::from_payload(&legacy).unwrap();
+        assert_eq!(chunk.text, "old text");
+        assert_eq!(chunk.document_id, "");
+        assert_eq!(chunk.item_path, None);
+    }
+
+    #[test]
+    fn item_fields_are_only_written_for_code() {
+        let prose = KnowledgeChunk::new("text", "doc-1", "notes.txt", 0);
+        assert!(!prose.to_payload().unwrap().contains_key("item_path"));
+
+        let code = KnowledgeChunk::new("fn f() {}", "doc-1", "lib.rs", 0).with_item("f", "fn");
+        let payload = code.to_payload().unwrap();
+        assert_eq!(payload["item_kind"], "fn");
+        assert_eq!(KnowledgeChunk::from_payload(&payload).unwrap(), code);
+    }
+
+    #[test]
+    fn point_ids_depend_on_document_and_content() {
+        let a = KnowledgeChunk::new("same", "doc-1", "a.txt", 0);
+        let moved = KnowledgeChunk::new("same", "doc-1", "a.txt", 7);
+        let other_doc = KnowledgeChunk::new("same", "doc-2", "a.txt", 0);
+
+        assert_eq!(a.point_id(), moved.point_id());
+        assert_ne!(a.point_id(), other_doc.point_id());
+        assert!(uuid::Uuid::parse_str(&a.point_id()).is_ok());
+        assert!(a.same_content(&moved));
+        assert!(!a.same_content(&KnowledgeChunk::new("same", "doc-1", "b.txt", 0)));
+    }
+
+    #[test]
+    fn approved_solution_omits_missing_manifest() {
+        let solution = ApprovedSolution {
+            query: "q".to_string(),
+            code: "c".to_string(),
+            cargo_toml: None,
+            cargo_lock: None,
+        };
+        let payload = solution.to_payload().unwrap();
+
+        assert!(!payload.contains_key("cargo_toml"));
+        assert_eq!(ApprovedSolution::from_payload(&payload).unwrap(), solution);
+    }
+
+    #[test]
+    fn content_hash_is_stable() {
+        assert_eq!(
+            content_hash("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -1,28 +1,106 @@
-use anyhow::Result;
+use std::collections::HashMap;
+
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
 use qdrant_client::{
-    Qdrant,
+    Payload, Qdrant,
     qdrant::{
-        CreateCollection, Distance, SearchPoints, VectorParams, VectorsConfig,
+        Condition, CreateCollection, DeletePoints, Distance, Filter, PointId, PointStruct,
+        PointsIdsList, PointsSelector, PointsUpdateOperation, ScrollPoints, SearchPoints,
+        UpdateBatchPoints, UpsertPoints, VectorParams, VectorsConfig,
+        point_id::PointIdOptions,
+        points_selector::PointsSelectorOneOf,
+        points_update_operation::{Operation, SetPayload},
         vectors_config::Config,
     },
 };
+use serde_json::Value;
 
-use crate::AppState;
+use crate::{
+    AppState,
+    embedder::embed_one,
+    payload::{ApprovedSolution, KnowledgeChunk, normalize_crate_name},
+    vector_store::{
+        PayloadFilter, PayloadUpdate, PointPayload, PointSelector, ScoredPoint, ScrollPage,
+        StoredPoint, VectorPoint, VectorStore,
+    },
+};
 
 pub const KNOWLEDGE_BASE_COLLECTION: &str = "knowledge_base";
 pub const APPROVED_SOLUTIONS_COLLECTION: &str = "approved_solutions";
 
-/// Creates the primary collection for storing knowledge base vectors if it doesn't exist.
-pub async fn ensure_collections_exist(client: &Qdrant) -> Result<()> {
+/// AllMiniLML6V2 uses 384-dimensional embeddings
+pub const EMBEDDING_DIMENSIONS: u64 = 384;
+
+/// Creates the collections for storing knowledge base vectors and approved solutions if they don't exist.
+pub async fn ensure_collections_exist(store: &dyn VectorStore) -> Result<()> {
     let collections_to_ensure = vec![KNOWLEDGE_BASE_COLLECTION, APPROVED_SOLUTIONS_COLLECTION];
     for collection_name in collections_to_ensure {
-        if client.collection_info(collection_name).await.is_err() {
-            client
+        store
+            .ensure_collection(collection_name, EMBEDDING_DIMENSIONS)
+            .await?;
+    }
+    Ok(())
+}
+
+/// The production vector store, backed by a Qdrant server.
+pub struct QdrantStore {
+    client: Qdrant,
+}
+
+impl QdrantStore {
+    pub fn new(client: Qdrant) -> Self {
+        Self { client }
+    }
+
+    pub fn from_url(url: &str) -> Result<Self> {
+        Ok(Self::new(Qdrant::from_url(url).build()?))
+    }
+}
+
+fn point_id_to_string(id: Option<PointId>) -> String {
+    match id.and_then(|id| id.point_id_options) {
+        Some(PointIdOptions::Uuid(uuid)) => uuid,
+        Some(PointIdOptions::Num(num)) => num.to_string(),
+        None => String::new(),
+    }
+}
+
+fn payload_from_qdrant(payload: HashMap<String, qdrant_client::qdrant::Value>) -> PointPayload {
+    payload
+        .into_iter()
+        .map(|(key, value)| (key, Value::from(value)))
+        .collect()
+}
+
+fn filter_to_qdrant(filter: PayloadFilter) -> Result<Filter> {
+    let conditions = filter
+        .must_match
+        .into_iter()
+        .map(|(key, value)| match value {
+            Value::String(s) => Ok(Condition::matches(key, s)),
+            Value::Bool(b) => Ok(Condition::matches(key, b)),
+            Value::Number(n) if n.is_i64() => Ok(Condition::matches(key, n.as_i64().unwrap())),
+            other => Err(anyhow!(
+                "Unsupported filter value for key '{}': {}",
+                key,
+                other
+            )),
+        })
+        .collect::<Result<Vec<Condition>>>()?;
+    Ok(Filter::must(conditions))
+}
+
+#[async_trait]
+impl VectorStore for QdrantStore {
+    async fn ensure_collection(&self, collection: &str, dimensions: u64) -> Result<()> {
+        if self.client.collection_info(collection).await.is_err() {
+            self.client
                 .create_collection(CreateCollection {
-                    collection_name: collection_name.to_string(),
+                    collection_name: collection.to_string(),
                     vectors_config: Some(VectorsConfig {
                         config: Some(Config::Params(VectorParams {
-                            size: 384, // AllMiniLML6V2 uses 384-dimensional embeddings
+                            size: dimensions,
                             distance: Distance::Cosine.into(),
                             ..Default::default()
                         })),
@@ -30,15 +108,158 @@ pub async fn ensure_collections_exist(client: &Qdrant) -> Result<()> {
                     ..Default::default()
                 })
                 .await?;
-            println!("INFO: Created Qdrant collection '{}'", collection_name);
+            println!("INFO: Created Qdrant collection '{}'", collection);
         }
+        Ok(())
+    }
+
+    async fn upsert(&self, collection: &str, points: Vec<VectorPoint>) -> Result<()> {
+        let points = points
+            .into_iter()
+            .map(|p| {
+                let payload: Payload = Value::Object(p.payload).try_into()?;
+                Ok(PointStruct::new(p.id, p.vector, payload))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        self.client
+            .upsert_points(UpsertPoints {
+                collection_name: collection.to_string(),
+                points,
+                wait: Some(true),
+                ..Default::default()
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn search(
+        &self,
+        collection: &str,
+        vector: Vec<f32>,
+        limit: u64,
+        filter: Option<PayloadFilter>,
+    ) -> Result<Vec<ScoredPoint>> {
+        let response = self
+            .client
+            .search_points(SearchPoints {
+                collection_name: collection.to_string(),
+                vector,
+                limit,
+                filter: filter.map(filter_to_qdrant).transpose()?,
+                with_payload: Some(true.into()),
+                ..Default::default()
+            })
+            .await?;
+
+        Ok(response
+            .result
+            .into_iter()
+            .map(|point| ScoredPoint {
+                id: point_id_to_string(point.id),
+                score: point.score,
+                payload: payload_from_qdrant(point.payload),
+            })
+            .collect())
+    }
+
+    async fn set_payloads(&self, collection: &str, updates: Vec<PayloadUpdate>) -> Result<()> {
+        let operations = updates
+            .into_iter()
+            .filter(|update| !update.ids.is_empty())
+            .map(|update| {
+                let payload: Payload = Value::Object(update.payload).try_into()?;
+                Ok(PointsUpdateOperation {
+                    operation: Some(Operation::SetPayload(SetPayload {
+                        payload: payload.into(),
+                        points_selector: Some(PointsSelector {
+                            points_selector_one_of: Some(PointsSelectorOneOf::Points(
+                                PointsIdsList {
+                                    ids: update.ids.into_iter().map(PointId::from).collect(),
+                                },
+                            )),
+                        }),
+                        ..Default::default()
+                    })),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if operations.is_empty() {
+            return Ok(());
+        }
+
+        self.client
+            .update_points_batch(UpdateBatchPoints {
+                collection_name: collection.to_string(),
+                operations,
+                wait: Some(true),
+                ..Default::default()
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, collection: &str, selector: PointSelector) -> Result<()> {
+        let selector = match selector {
+            PointSelector::Ids(ids) => PointsSelectorOneOf::Points(PointsIdsList {
+                ids: ids.into_iter().map(PointId::from).collect(),
+            }),
+            PointSelector::Filter(filter) => PointsSelectorOneOf::Filter(filter_to_qdrant(filter)?),
+        };
+
+        self.client
+            .delete_points(DeletePoints {
+                collection_name: collection.to_string(),
+                points: Some(PointsSelector {
+                    points_selector_one_of: Some(selector),
+                }),
+                wait: Some(true),
+                ..Default::default()
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn scroll(
+        &self,
+        collection: &str,
+        filter: Option<PayloadFilter>,
+        limit: u32,
+        offset: Option<String>,
+    ) -> Result<ScrollPage> {
+        let response = self
+            .client
+            .scroll(ScrollPoints {
+                collection_name: collection.to_string(),
+                filter: filter.map(filter_to_qdrant).transpose()?,
+                offset: offset.map(PointId::from),
+                limit: Some(limit),
+                with_payload: Some(true.into()),
+                with_vectors: Some(false.into()),
+                ..Default::default()
+            })
+            .await?;
+
+        let next_offset = response
+            .next_page_offset
+            .map(|id| point_id_to_string(Some(id)));
+        Ok(ScrollPage {
+            points: response
+                .result
+                .into_iter()
+                .map(|point| StoredPoint {
+                    id: point_id_to_string(point.id),
+                    payload: payload_from_qdrant(point.payload),
+                })
+                .collect(),
+            next_offset,
+        })
     }
-    Ok(())
 }
 
 // /// Searches the knowledge base for relevant context.
 // pub async fn search_knowledge_base(state: &AppState, query: &str) -> Result<String> {
-//     let query_embedding = state.embedding_model.embed(vec![query.to_string()], None)?[0].clone();
+//     let query_embedding = state.embedding_model.embed(vec![query.to_string()])?[0].clone();
 
 //     let search_response = state
 //         .qdrant_client
@@ -63,25 +284,19 @@ pub async fn ensure_collections_exist(client: &Qdrant) -> Result<()> {
 
 /// Searches both the knowledge base and approved solutions for relevant context.
 pub async fn search_for_context(state: &AppState, query: &str) -> Result<String> {
-    let query_embedding = state.embedding_model.embed(vec![query.to_string()], None)?[0].clone();
+    let query_embedding = embed_one(state.embedding_model.clone(), query.to_string()).await?;
 
     // Search the knowledge base for general documentation
-    let knowledge_search = state.qdrant_client.search_points(SearchPoints {
-        collection_name: KNOWLEDGE_BASE_COLLECTION.to_string(),
-        vector: query_embedding.clone(),
-        limit: 2,
-        with_payload: Some(true.into()),
-        ..Default::default()
-    });
+    let knowledge_search =
+        state
+            .vector_store
+            .search(KNOWLEDGE_BASE_COLLECTION, query_embedding.clone(), 2, None);
 
     // Search the approved solutions for golden examples
-    let approved_search = state.qdrant_client.search_points(SearchPoints {
-        collection_name: APPROVED_SOLUTIONS_COLLECTION.to_string(),
-        vector: query_embedding,
-        limit: 1,
-        with_payload: Some(true.into()),
-        ..Default::default()
-    });
+    let approved_search =
+        state
+            .vector_store
+            .search(APPROVED_SOLUTIONS_COLLECTION, query_embedding, 1, None);
 
     // Run both searches concurrently
     let (knowledge_res, approved_res) = tokio::join!(knowledge_search, approved_search);
@@ -89,31 +304,14 @@ pub async fn search_for_context(state: &AppState, query: &str) -> Result<String>
     let mut context_parts = Vec::new();
 
     if let Ok(res) = knowledge_res {
-        let knowledge_context = res
-            .result
-            .into_iter()
-            .filter_map(|point| point.payload.get("chunk")?.as_str().map(String::from))
-            .collect::<Vec<String>>()
-            .join("\n---\n");
+        let knowledge_context = format_knowledge_context(&res);
         if !knowledge_context.is_empty() {
             context_parts.push(format!("Relevant Documentation:\n{}", knowledge_context));
         }
     }
 
     if let Ok(res) = approved_res {
-        let approved_context = res
-            .result
-            .into_iter()
-            .filter_map(|point| {
-                let code = point.payload.get("code")?.as_str()?;
-                let original_query = point.payload.get("query")?.as_str()?;
-                Some(format!(
-                    "Previously approved solution for a similar query ('{}'):\n```rust\n{}\n```",
-                    original_query, code
-                ))
-            })
-            .collect::<Vec<String>>()
-            .join("\n---\n");
+        let approved_context = format_approved_context(&res);
         if !approved_context.is_empty() {
             context_parts.push(format!("Golden Example:\n{}", approved_context));
         }
@@ -121,3 +319,59 @@ pub async fn search_for_context(state: &AppState, query: &str) -> Result<String>
 
     Ok(context_parts.join("\n\n"))
 }
+
+/// Searches the API items indexed for `crate_name` (see `crate_source`). Returns
+/// an empty string when the crate has not been indexed.
+pub async fn search_crate_docs(state: &AppState, query: &str, crate_name: &str) -> Result<String> {
+    let query_embedding = embed_one(state.embedding_model.clone(), query.to_string()).await?;
+    let filter = PayloadFilter::new().with_match("crate_name", normalize_crate_name(crate_name));
+    let hits = state
+        .vector_store
+        .search(KNOWLEDGE_BASE_COLLECTION, query_embedding, 5, Some(filter))
+        .await?;
+    Ok(format_knowledge_context(&hits))
+}
+
+/// Joins the text of knowledge base hits, naming the source of each chunk
+/// and the item or section it holds.
+/// Points that don't match the `KnowledgeChunk` schema are skipped.
+pub fn format_knowledge_context(points: &[ScoredPoint]) -> String {
+    points
+        .iter()
+        .filter_map(|point| KnowledgeChunk::from_payload(&point.payload).ok())
+        .map(|chunk| {
+            let location = chunk
+                .item_path
+                .as_ref()
+                .map(|path| format!("`{}`", path))
+                .or_else(|| chunk.heading_path.clone());
+            let origin = match (location, chunk.source.is_empty()) {
+                (Some(location), true) => location,
+                (Some(location), false) => format!("{} in {}", location, chunk.source),
+                (None, false) => chunk.source.clone(),
+                (None, true) => return chunk.text,
+            };
+            format!("(from {})\n{}", origin, chunk.text)
+        })
+        .collect::<Vec<String>>()
+        .join("\n---\n")
+}
+
+/// Formats approved solution hits as golden examples, with the manifest they were built with.
+pub fn format_approved_context(points: &[ScoredPoint]) -> String {
+    points
+        .iter()
+        .filter_map(|point| ApprovedSolution::from_payload(&point.payload).ok())
+        .map(|solution| {
+            let manifest = solution
+                .cargo_toml
+                .map(|m| format!("\nBuilt with:\n```toml\n{}\n```", m))
+                .unwrap_or_default();
+            format!(
+                "Previously approved solution for a similar query ('{}'):\n```rust\n{}\n```{}",
+                solution.query, solution.code, manifest
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n---\n")
+}
@@ -0,0 +1,208 @@
+//! Just enough of robots.txt (RFC 9309) for the crawler: user-agent groups,
+//! `Allow`/`Disallow` with `*` and `$`, and the non-standard `Crawl-delay`.
+
+use std::time::Duration;
+
+/// The product token the crawler identifies itself with, in its User-Agent and
+/// when picking its group in robots.txt.
+pub const CRAWLER_AGENT: &str = "RagCrawler";
+
+/// The longest pause between requests the crawler will make; a longer
+/// `Crawl-delay` is shortened to this.
+pub const MAX_CRAWL_DELAY: Duration = Duration::from_secs(60);
+
+/// A `Crawl-delay` value in seconds, capped at `MAX_CRAWL_DELAY`.
+fn parse_crawl_delay(value: &str) -> Option<Duration> {
+    let seconds = value.parse::<f64>().ok().filter(|d| *d >= 0.0)?;
+    Some(Duration::try_from_secs_f64(seconds).map_or(MAX_CRAWL_DELAY, |d| d.min(MAX_CRAWL_DELAY)))
+}
+
+/// The rules robots.txt sets for one user agent.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RobotsRules {
+    allow: Vec<String>,
+    disallow: Vec<String>,
+    pub crawl_delay: Option<Duration>,
+    /// `Sitemap:` URLs. These apply to every user agent.
+    pub sitemaps: Vec<String>,
+}
+
+#[derive(Default)]
+struct Group {
+    agents: Vec<String>,
+    rules: RobotsRules,
+}
+
+impl RobotsRules {
+    /// Everything allowed, as when a site has no robots.txt.
+    pub fn allow_all() -> Self {
+        Self::default()
+    }
+
+    /// Parses robots.txt and keeps the group that applies to `agent`: the group
+    /// naming it, or the `*` group when none does.
+    pub fn parse(robots_txt: &str, agent: &str) -> Self {
+        let mut groups: Vec<Group> = Vec::new();
+        let mut sitemaps = Vec::new();
+        // Consecutive User-agent lines share the group that follows them.
+        let mut collecting_agents = false;
+
+        for line in robots_txt.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let (key, value) = (key.trim().to_ascii_lowercase(), value.trim());
+            match key.as_str() {
+                // Not part of any group, so it doesn't end one either.
+                "sitemap" => sitemaps.push(value.to_string()),
+                "user-agent" => {
+                    if !collecting_agents {
+                        groups.push(Group::default());
+                        collecting_agents = true;
+                    }
+                    if let Some(group) = groups.last_mut() {
+                        group.agents.push(value.to_ascii_lowercase());
+                    }
+                }
+                "allow" | "disallow" | "crawl-delay" => {
+                    collecting_agents = false;
+                    let Some(group) = groups.last_mut() else {
+                        continue;
+                    };
+                    match key.as_str() {
+                        // An empty Disallow allows everything; it adds no rule.
+                        "disallow" if !value.is_empty() => {
+                            group.rules.disallow.push(value.to_string())
+                        }
+                        "allow" if !value.is_empty() => group.rules.allow.push(value.to_string()),
+                        "crawl-delay" => group.rules.crawl_delay = parse_crawl_delay(value),
+                        _ => {}
+                    }
+                }
+                _ => collecting_agents = false,
+            }
+        }
+
+        let agent = agent.to_ascii_lowercase();
+        let named = groups
+            .iter()
+            .position(|g| g.agents.iter().any(|a| a != "*" && agent.contains(a.as_str())));
+        let wildcard = groups.iter().position(|g| g.agents.iter().any(|a| a == "*"));
+        let mut rules = match named.or(wildcard) {
+            Some(i) => groups.swap_remove(i).rules,
+            None => Self::allow_all(),
+        };
+        rules.sitemaps = sitemaps;
+        rules
+    }
+
+    /// Whether `path` (path plus query) may be fetched. The longest matching rule
+    /// wins, and `Allow` wins a tie.
+    pub fn is_allowed(&self, path: &str) -> bool {
+        let longest = |rules: &[String]| {
+            rules
+                .iter()
+                .filter(|rule| pattern_matches(rule, path))
+                .map(|rule| rule.len())
+                .max()
+        };
+        match (longest(&self.allow), longest(&self.disallow)) {
+            (_, None) => true,
+            (None, Some(_)) => false,
+            (Some(allow), Some(disallow)) => allow >= disallow,
+        }
+    }
+}
+
+/// Matches a robots.txt path pattern: a prefix, where `*` matches any run of
+/// characters and a trailing `$` anchors the end.
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(p) => (p, true),
+        None => (pattern, false),
+    };
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = path.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    for (i, part) in parts.iter().enumerate() {
+        let is_last = i == parts.len() - 1;
+        if is_last && anchored {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    !anchored || rest.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROBOTS: &str = "
+# Comment
+User-agent: *
+Disallow: /private/
+Allow: /private/public-page
+Disallow: /*.pdf$
+Crawl-delay: 2
+
+User-agent: BadBot
+User-agent: RagCrawler
+Disallow: /search
+
+Sitemap: https://example.com/sitemap.xml
+";
+
+    #[test]
+    fn picks_the_group_naming_the_crawler() {
+        let rules = RobotsRules::parse(ROBOTS, "RagCrawler/0.1");
+        assert!(!rules.is_allowed("/search?q=tokio"));
+        assert!(rules.is_allowed("/private/secret"));
+        assert_eq!(rules.crawl_delay, None);
+        assert_eq!(rules.sitemaps, ["https://example.com/sitemap.xml"]);
+    }
+
+    #[test]
+    fn falls_back_to_the_wildcard_group() {
+        let rules = RobotsRules::parse(ROBOTS, "OtherBot");
+        assert!(rules.is_allowed("/docs/index.html"));
+        assert!(!rules.is_allowed("/private/secret"));
+        assert!(rules.is_allowed("/private/public-page"));
+        assert!(!rules.is_allowed("/files/guide.pdf"));
+        assert!(rules.is_allowed("/files/guide.pdf.html"));
+        assert_eq!(rules.crawl_delay, Some(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn huge_crawl_delays_are_capped() {
+        for delay in ["86400", "1e300", "inf"] {
+            let robots = format!("User-agent: *\nCrawl-delay: {}\n", delay);
+            let rules = RobotsRules::parse(&robots, "RagCrawler");
+            assert_eq!(rules.crawl_delay, Some(MAX_CRAWL_DELAY), "{}", delay);
+        }
+        let rules = RobotsRules::parse("User-agent: *\nCrawl-delay: NaN\n", "RagCrawler");
+        assert_eq!(rules.crawl_delay, None);
+    }
+
+    #[test]
+    fn no_matching_group_allows_everything() {
+        let rules = RobotsRules::parse("User-agent: BadBot\nDisallow: /\n", "RagCrawler");
+        assert!(rules.is_allowed("/anything"));
+        assert!(RobotsRules::parse("", "RagCrawler").is_allowed("/"));
+    }
+
+    #[test]
+    fn wildcards_match_inside_paths() {
+        assert!(pattern_matches("/a/*/c", "/a/b/c/d"));
+        assert!(!pattern_matches("/a/*/c$", "/a/b/c/d"));
+        assert!(pattern_matches("/a/*/c$", "/a/b/c"));
+        assert!(pattern_matches("/", "/anything"));
+    }
+}
@@ -8,6 +8,8 @@
 // use tempfile::TempDir;
 // use tokio::fs;
 // use tokio::process::Command;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
 
 // /// A simple AST visitor that only inspects `use` statements
 // /// to find the root crate name.
@@ -105,11 +107,26 @@
 // In app_core/src/sandbox.rs
 
 use anyhow::{Context, Result};
-use std::process::Output;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::process::{Output, Stdio};
+use std::time::{Duration, Instant};
 use tempfile::TempDir;
 use tokio::fs;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::process::Command;
 
+use crate::build_cache::{self, BuildCache, BuildCacheSettings};
+use crate::diagnostics::{self, Diagnostic};
+use crate::docker_sandbox::DockerSandboxSettings;
+use crate::events::{QueryEvent, QueryEvents};
+use crate::lockfile::{self, PinnedManifest};
+
+/// How long a program's output pipes may stay open after it is gone, held by
+/// a process it started that outlived it.
+const PIPE_GRACE: Duration = Duration::from_secs(2);
+
 // We need a struct to pass the dependency info to the sandbox.
 // It's good practice to define this where it's used or in a shared module.
 pub use crate::llm::Dependency;
@@ -117,34 +134,334 @@ pub use crate::llm::Dependency;
 /// Represents the result of a sandbox compilation check.
 pub struct SandboxResult {
     pub success: bool,
+    /// Human-readable build output: rendered compiler messages followed by cargo's stderr.
+    /// Empty on success.
     pub output: String,
+    /// Compiler messages parsed from `--message-format=json`, warnings included.
+    pub diagnostics: Vec<Diagnostic>,
+    /// Set when the build succeeded and the program was asked to run.
+    pub run: Option<RunOutcome>,
+    /// Manifest pinned to the versions cargo resolved, when resolution got that far.
+    pub manifest: Option<PinnedManifest>,
+}
+
+/// How to run a successfully built program.
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    pub stdin: Option<String>,
+    pub timeout_secs: u64,
+    /// Cap on each of stdout and stderr; anything past it is dropped.
+    pub max_output_bytes: usize,
+}
+
+/// What happened when the built program was run.
+#[derive(Serialize, Debug, Clone)]
+pub struct RunOutcome {
+    /// `None` when the process was killed by a signal or timed out.
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub runtime_ms: u64,
+    /// The process hit the timeout or was killed by a signal.
+    pub killed: bool,
+    /// Stdout or stderr exceeded `max_output_bytes` and was cut.
+    pub truncated: bool,
+}
+
+impl RunOutcome {
+    pub fn succeeded(&self) -> bool {
+        self.exit_code == Some(0) && !self.killed
+    }
+}
+
+/// Which `SandboxExecutor` builds the generated code.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SandboxExecutorKind {
+    /// Runs cargo directly on this machine.
+    #[default]
+    Host,
+    /// Runs cargo in a locked-down Docker container.
+    Docker,
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct SandboxSettings {
+    #[serde(default)]
+    pub executor: SandboxExecutorKind,
+    #[serde(default)]
+    pub docker: DockerSandboxSettings,
+    #[serde(default)]
+    pub run: RunSettings,
+    #[serde(default)]
+    pub cache: BuildCacheSettings,
+}
+
+/// Limits applied when a query asks for the program to be executed.
+#[derive(Deserialize, Clone, Debug)]
+pub struct RunSettings {
+    #[serde(default = "default_run_timeout_secs")]
+    pub timeout_secs: u64,
+    #[serde(default = "default_max_output_bytes")]
+    pub max_output_bytes: usize,
+}
+
+fn default_run_timeout_secs() -> u64 {
+    10
+}
+
+fn default_max_output_bytes() -> usize {
+    64 * 1024
 }
 
-/// Creates a temporary Cargo project with explicit dependencies and features.
-pub async fn run_in_sandbox(code: &str, dependencies: &[Dependency]) -> Result<SandboxResult> {
-    let mut cargo_toml = String::from(
-        r#"[package]
-name = "sandbox"
-version = "0.1.0"
-edition = "2024"
-
-[dependencies]
-"#,
-    );
-
-    for dep in dependencies {
-        let features_str = dep.features.iter()
-            .map(|f| format!("\"{}\"", f))
-            .collect::<Vec<_>>()
-            .join(", ");
-        
-        // Correctly format the dependency line with features
-        cargo_toml.push_str(&format!(
-            "{} = {{ version = \"*\", features = [{}] }}\n",
-            dep.name, features_str
-        ));
+impl Default for RunSettings {
+    fn default() -> Self {
+        Self {
+            timeout_secs: default_run_timeout_secs(),
+            max_output_bytes: default_max_output_bytes(),
+        }
+    }
+}
+
+/// Where a sandbox build happens.
+#[derive(Debug, Clone)]
+pub struct SandboxPaths {
+    /// The generated project, with `Cargo.toml` and `src/main.rs`.
+    pub project_dir: PathBuf,
+    /// Shared target directory from the build cache.
+    pub target_dir: PathBuf,
+    /// Cargo home for executors that don't use the host's.
+    pub cargo_home: PathBuf,
+}
+
+impl SandboxPaths {
+    /// Where `cargo build` puts the program, relative to `target_dir`.
+    pub const BINARY: &'static str = "debug/sandbox";
+}
+
+/// Something that can build a prepared Cargo project.
+#[async_trait]
+pub trait SandboxExecutor: Send + Sync {
+    /// Runs `cargo build` on the project, forwarding every output line to `events`.
+    async fn build(&self, paths: &SandboxPaths, events: &QueryEvents) -> Result<SandboxResult>;
+
+    /// Runs the binary produced by a successful `build`.
+    async fn run(&self, paths: &SandboxPaths, options: &RunOptions) -> Result<RunOutcome>;
+}
+
+/// Builds on the host with the current user's privileges. Only use it for trusted setups.
+pub struct HostExecutor;
+
+#[async_trait]
+impl SandboxExecutor for HostExecutor {
+    async fn build(&self, paths: &SandboxPaths, events: &QueryEvents) -> Result<SandboxResult> {
+        let mut child = Command::new("cargo")
+            .args(["build", "--message-format=json"])
+            .current_dir(&paths.project_dir)
+            .env("CARGO_TARGET_DIR", &paths.target_dir)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .context("Failed to execute cargo build")?;
+
+        // Compiler messages arrive as JSON on stdout, cargo's own progress on stderr.
+        let stdout = child.stdout.take().context("Failed to capture cargo stdout")?;
+        let stderr = child.stderr.take().context("Failed to capture cargo stderr")?;
+
+        let mut diagnostics = Vec::new();
+        let read_stdout = async {
+            let mut lines = BufReader::new(stdout).lines();
+            while let Some(line) = lines.next_line().await? {
+                if let Some(text) = diagnostics::human_readable(&line) {
+                    events.emit(QueryEvent::BuildOutput { line: text });
+                }
+                diagnostics.extend(diagnostics::parse_cargo_line(&line));
+            }
+            Ok::<_, std::io::Error>(())
+        };
+
+        let mut stderr_output = String::new();
+        let read_stderr = async {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Some(line) = lines.next_line().await? {
+                events.emit(QueryEvent::BuildOutput { line: line.clone() });
+                stderr_output.push_str(&line);
+                stderr_output.push('\n');
+            }
+            Ok::<_, std::io::Error>(())
+        };
+
+        let (stdout_res, stderr_res) = tokio::join!(read_stdout, read_stderr);
+        stdout_res.context("Failed to read stdout from cargo build")?;
+        stderr_res.context("Failed to read stderr from cargo build")?;
+
+        let status = child.wait().await.context("Failed to wait for cargo build")?;
+
+        let result_output = if !status.success() {
+            human_output(&diagnostics, &stderr_output)
+        } else {
+            String::new()
+        };
+
+        Ok(SandboxResult {
+            success: status.success(),
+            output: result_output,
+            diagnostics,
+            run: None,
+            manifest: None,
+        })
     }
 
+    async fn run(&self, paths: &SandboxPaths, options: &RunOptions) -> Result<RunOutcome> {
+        let binary = paths.target_dir.join(SandboxPaths::BINARY);
+        let started = Instant::now();
+        let mut command = Command::new(&binary);
+        // Its own process group, so a timeout kills whatever it started too.
+        #[cfg(unix)]
+        command.process_group(0);
+        let mut child = command
+            .current_dir(&paths.project_dir)
+            .stdin(if options.stdin.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to start {}", binary.display()))?;
+
+        // Feed stdin in the background so a program that never reads it can't block us.
+        if let (Some(input), Some(mut stdin)) = (options.stdin.clone(), child.stdin.take()) {
+            tokio::spawn(async move {
+                let _ = stdin.write_all(input.as_bytes()).await;
+            });
+        }
+
+        let limit = options.max_output_bytes;
+        let stdout = child.stdout.take().context("Failed to capture program stdout")?;
+        let stderr = child.stderr.take().context("Failed to capture program stderr")?;
+        let (stop_stdout, stdout_stopped) = oneshot::channel();
+        let (stop_stderr, stderr_stopped) = oneshot::channel();
+        let stdout_task = tokio::spawn(read_capped(stdout, limit, stdout_stopped));
+        let stderr_task = tokio::spawn(read_capped(stderr, limit, stderr_stopped));
+
+        let timeout = Duration::from_secs(options.timeout_secs);
+        let (status, timed_out) = match tokio::time::timeout(timeout, child.wait()).await {
+            Ok(status) => (Some(status.context("Failed to wait for program")?), false),
+            Err(_) => {
+                #[cfg(unix)]
+                if let Some(pid) = child.id() {
+                    kill_process_group(pid).await;
+                }
+                child.kill().await.context("Failed to kill program after timeout")?;
+                (None, true)
+            }
+        };
+        let runtime_ms = started.elapsed().as_millis() as u64;
+
+        let (stdout, stdout_truncated) = finish_reading(stdout_task, stop_stdout).await?;
+        let (stderr, stderr_truncated) = finish_reading(stderr_task, stop_stderr).await?;
+        let exit_code = status.and_then(|s| s.code());
+
+        Ok(RunOutcome {
+            exit_code,
+            stdout,
+            stderr,
+            runtime_ms,
+            // No exit code without a timeout means a signal ended the process.
+            killed: timed_out || exit_code.is_none(),
+            truncated: stdout_truncated || stderr_truncated,
+        })
+    }
+}
+
+/// Joins the rendered compiler messages and cargo's stderr into what a user
+/// would have seen on a terminal.
+pub(crate) fn human_output(diagnostics: &[Diagnostic], stderr: &str) -> String {
+    let mut output = diagnostics
+        .iter()
+        .filter_map(|d| d.rendered.as_deref())
+        .collect::<Vec<_>>()
+        .join("\n");
+    if !output.is_empty() {
+        output.push('\n');
+    }
+    output.push_str(stderr);
+    output
+}
+
+/// Kills every process in the group led by `pid`. The program leads its own
+/// group, so this reaches the processes it forked as well.
+#[cfg(unix)]
+async fn kill_process_group(pid: u32) {
+    let killed = Command::new("kill")
+        .args(["-KILL", "--", &format!("-{}", pid)])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await;
+    if let Err(e) = killed {
+        println!("Warning: Failed to kill process group {}: {}", pid, e);
+    }
+}
+
+/// Reads `reader` to the end, keeping at most `limit` bytes. Draining the rest
+/// stops a chatty program from blocking on a full pipe. Stops early, with what
+/// it has, when `stop` fires.
+async fn read_capped<R: AsyncRead + Unpin>(
+    mut reader: R,
+    limit: usize,
+    mut stop: oneshot::Receiver<()>,
+) -> Result<(String, bool)> {
+    let mut kept = Vec::new();
+    let mut truncated = false;
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = tokio::select! {
+            n = reader.read(&mut buf) => n?,
+            _ = &mut stop => break,
+        };
+        if n == 0 {
+            break;
+        }
+        let room = limit.saturating_sub(kept.len());
+        truncated |= n > room;
+        kept.extend_from_slice(&buf[..n.min(room)]);
+    }
+    Ok((String::from_utf8_lossy(&kept).into_owned(), truncated))
+}
+
+/// Waits for a `read_capped` task once the program has ended. A pipe that is
+/// still open `PIPE_GRACE` later is held by a process that escaped; reading
+/// stops there rather than waiting on it forever.
+async fn finish_reading(
+    mut task: JoinHandle<Result<(String, bool)>>,
+    stop: oneshot::Sender<()>,
+) -> Result<(String, bool)> {
+    if let Ok(read) = tokio::time::timeout(PIPE_GRACE, &mut task).await {
+        return read?;
+    }
+    let _ = stop.send(());
+    task.await?
+}
+
+/// Creates a temporary Cargo project with explicit dependencies and features
+/// and builds it with the given executor, reusing the cached target directory
+/// for this dependency set. When `run` is set and the build succeeds, the
+/// program is executed as well.
+pub async fn run_in_sandbox(
+    executor: &dyn SandboxExecutor,
+    cache: &BuildCache,
+    code: &str,
+    dependencies: &[Dependency],
+    run: Option<&RunOptions>,
+    events: &QueryEvents,
+) -> Result<SandboxResult> {
+    let cargo_toml = lockfile::render_manifest(dependencies)?;
+
     let temp_dir = TempDir::new().context("Failed to create temp directory")?;
     let src_dir = temp_dir.path().join("src");
     fs::create_dir_all(&src_dir).await.context("Failed to create src directory")?;
@@ -156,21 +473,69 @@ edition = "2024"
         .await
         .context("Failed to write main.rs")?;
 
-    let build_output = Command::new("cargo")
-        .arg("build")
-        .current_dir(temp_dir.path())
-        .output()
-        .await
-        .context("Failed to execute cargo build")?;
-
-    let result_output = if !build_output.status.success() {
-        String::from_utf8(build_output.stderr).context("Failed to read stderr from cargo build")?
-    } else {
-        String::new()
+    let lease = cache.checkout(&build_cache::cache_key(dependencies)).await?;
+    let paths = SandboxPaths {
+        project_dir: temp_dir.path().to_path_buf(),
+        target_dir: lease.target_dir.clone(),
+        cargo_home: cache.cargo_home(),
     };
 
-    Ok(SandboxResult {
-        success: build_output.status.success(),
-        output: result_output,
-    })
-}
\ No newline at end of file
+    let mut result = executor.build(&paths, events).await?;
+    if let (true, Some(options)) = (result.success, run) {
+        result.run = Some(executor.run(&paths, options).await?);
+    }
+    drop(lease);
+
+    // Cargo writes the lockfile once resolution succeeds, even if compilation fails later.
+    if let Ok(cargo_lock) = fs::read_to_string(temp_dir.path().join("Cargo.lock")).await {
+        match lockfile::pin_dependencies(dependencies, &cargo_lock) {
+            Ok(pinned) => {
+                result.manifest = Some(PinnedManifest {
+                    cargo_toml: lockfile::render_manifest(&pinned)?,
+                    dependencies: pinned,
+                    cargo_lock,
+                });
+            }
+            Err(e) => println!("Warning: Failed to pin dependency versions: {e:#}"),
+        }
+    }
+
+    if let Err(e) = cache.evict().await {
+        println!("Warning: Failed to evict sandbox build cache: {e:#}");
+    }
+    Ok(result)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn timeouts_kill_what_the_program_started() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = SandboxPaths {
+            project_dir: dir.path().to_path_buf(),
+            target_dir: dir.path().join("target"),
+            cargo_home: dir.path().join("cargo-home"),
+        };
+        // The background `sleep` inherits stdout and would hold it open.
+        let binary = paths.target_dir.join(SandboxPaths::BINARY);
+        std::fs::create_dir_all(binary.parent().unwrap()).unwrap();
+        std::fs::write(&binary, "#!/bin/sh\necho started\nsleep 60 &\nsleep 60\n").unwrap();
+        std::fs::set_permissions(&binary, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let options = RunOptions {
+            stdin: None,
+            timeout_secs: 1,
+            max_output_bytes: 1024,
+        };
+        let started = Instant::now();
+        let outcome = HostExecutor.run(&paths, &options).await.unwrap();
+
+        assert!(outcome.killed);
+        assert_eq!(outcome.stdout, "started\n");
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
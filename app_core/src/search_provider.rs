@@ -0,0 +1,328 @@
+//! Web search backends. The pipeline only needs a ranked list of pages for a
+//! query, so any search engine, or a directory of canned results, will do.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result, bail};
+use async_trait::async_trait;
+use duckduckgo_rs::search_duckduckgo;
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+
+/// One search result.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    #[serde(default)]
+    pub title: String,
+    pub url: String,
+    #[serde(default)]
+    pub snippet: String,
+    /// The page's content, when the provider already has it and the page
+    /// needn't be fetched. Set by the fixture provider.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+}
+
+/// Anything that can answer a web search.
+#[async_trait]
+pub trait SearchProvider: Send + Sync {
+    /// Returns at most `limit` results, best first.
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>>;
+}
+
+/// Which `SearchProvider` implementation the application uses.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchProviderKind {
+    #[default]
+    #[serde(rename = "duckduckgo")]
+    DuckDuckGo,
+    /// A SearxNG instance's JSON API, at `searxng_url`.
+    Searxng,
+    /// Canned results from `fixtures_dir`, for offline runs and tests.
+    Fixtures,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct SearchSettings {
+    #[serde(default)]
+    pub provider: SearchProviderKind,
+    /// How many results of each search are read.
+    #[serde(default = "default_top_n")]
+    pub top_n: usize,
+    /// Pages fetched at the same time per search, and crates researched at the same time per query.
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    /// Longest wait for a search or a result page. Slower ones are skipped.
+    #[serde(default = "default_request_timeout_secs")]
+    pub request_timeout_secs: u64,
+    #[serde(default)]
+    pub searxng_url: Option<String>,
+    #[serde(default)]
+    pub fixtures_dir: Option<String>,
+}
+
+fn default_top_n() -> usize {
+    2
+}
+
+fn default_concurrency() -> usize {
+    4
+}
+
+fn default_request_timeout_secs() -> u64 {
+    10
+}
+
+impl Default for SearchSettings {
+    fn default() -> Self {
+        Self {
+            provider: SearchProviderKind::default(),
+            top_n: default_top_n(),
+            concurrency: default_concurrency(),
+            request_timeout_secs: default_request_timeout_secs(),
+            searxng_url: None,
+            fixtures_dir: None,
+        }
+    }
+}
+
+/// Builds the provider `settings` select.
+pub fn search_provider(
+    settings: &SearchSettings,
+    http_client: Arc<Client>,
+) -> Result<Arc<dyn SearchProvider>> {
+    Ok(match settings.provider {
+        SearchProviderKind::DuckDuckGo => Arc::new(DuckDuckGoProvider::new(http_client)),
+        SearchProviderKind::Searxng => {
+            let url = settings
+                .searxng_url
+                .as_deref()
+                .context("search.searxng_url must be set to use the searxng provider")?;
+            Arc::new(SearxngProvider::new(http_client, url)?)
+        }
+        SearchProviderKind::Fixtures => {
+            let dir = settings
+                .fixtures_dir
+                .as_deref()
+                .context("search.fixtures_dir must be set to use the fixtures provider")?;
+            Arc::new(FixtureSearchProvider::new(dir)?)
+        }
+    })
+}
+
+/// DuckDuckGo's HTML results page, scraped by `duckduckgo_rs`.
+pub struct DuckDuckGoProvider {
+    http_client: Arc<Client>,
+}
+
+impl DuckDuckGoProvider {
+    pub fn new(http_client: Arc<Client>) -> Self {
+        Self { http_client }
+    }
+}
+
+#[async_trait]
+impl SearchProvider for DuckDuckGoProvider {
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>> {
+        let results = search_duckduckgo(&self.http_client, query)
+            .await
+            .context("Failed to get search results from DuckDuckGo")?;
+        Ok(results
+            .into_iter()
+            .take(limit)
+            .map(|result| SearchHit {
+                title: String::new(),
+                url: result.url,
+                snippet: result.description,
+                content: None,
+            })
+            .collect())
+    }
+}
+
+/// A self-hosted SearxNG instance. Its `json` output format must be enabled
+/// (`search.formats` in its `settings.yml`).
+pub struct SearxngProvider {
+    http_client: Arc<Client>,
+    search_url: Url,
+}
+
+#[derive(Deserialize)]
+struct SearxngResponse {
+    #[serde(default)]
+    results: Vec<SearxngResult>,
+}
+
+#[derive(Deserialize)]
+struct SearxngResult {
+    url: String,
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    content: String,
+}
+
+impl SearxngProvider {
+    /// `base_url` is where the instance is served, e.g. `http://localhost:8888`.
+    pub fn new(http_client: Arc<Client>, base_url: &str) -> Result<Self> {
+        let mut base_url = Url::parse(base_url)
+            .with_context(|| format!("Invalid SearxNG URL {}", base_url))?;
+        // `join` replaces the last path segment unless the path ends in a slash,
+        // which would drop the prefix of an instance served under a path.
+        if !base_url.path().ends_with('/') {
+            base_url.set_path(&format!("{}/", base_url.path()));
+        }
+        let search_url = base_url.join("search")?;
+        Ok(Self {
+            http_client,
+            search_url,
+        })
+    }
+}
+
+#[async_trait]
+impl SearchProvider for SearxngProvider {
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>> {
+        let response = self
+            .http_client
+            .get(self.search_url.clone())
+            .query(&[("q", query), ("format", "json")])
+            .send()
+            .await
+            .context("Failed to reach SearxNG")?;
+        if !response.status().is_success() {
+            bail!("SearxNG answered {}", response.status());
+        }
+        let body: SearxngResponse = response
+            .json()
+            .await
+            .context("Failed to parse SearxNG results")?;
+        Ok(body
+            .results
+            .into_iter()
+            .take(limit)
+            .map(|result| SearchHit {
+                title: result.title,
+                url: result.url,
+                snippet: result.content,
+                content: None,
+            })
+            .collect())
+    }
+}
+
+/// Canned results read from a directory: `<slug>.json` holds the hits for the
+/// query with that slug (see `query_slug`), as a JSON array of `SearchHit`s.
+/// Queries without a file get `default.json`, or no results.
+pub struct FixtureSearchProvider {
+    dir: PathBuf,
+}
+
+impl FixtureSearchProvider {
+    pub fn new(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        if !dir.is_dir() {
+            bail!("Search fixture directory {} does not exist", dir.display());
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    fn read_hits(path: &Path) -> Result<Vec<SearchHit>> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read search fixture {}", path.display()))?;
+        serde_json::from_str(&json)
+            .with_context(|| format!("Failed to parse search fixture {}", path.display()))
+    }
+}
+
+/// The file name stem a query's fixture is stored under: its lowercase words
+/// joined by `-`, e.g. `tokio-mutex-example` for "Tokio Mutex example?".
+pub fn query_slug(query: &str) -> String {
+    query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+#[async_trait]
+impl SearchProvider for FixtureSearchProvider {
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>> {
+        let exact = self.dir.join(format!("{}.json", query_slug(query)));
+        let fallback = self.dir.join("default.json");
+        let hits = if exact.is_file() {
+            Self::read_hits(&exact)?
+        } else if fallback.is_file() {
+            Self::read_hits(&fallback)?
+        } else {
+            println!("No search fixture for query: {}", query);
+            Vec::new()
+        };
+        Ok(hits.into_iter().take(limit).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugs_ignore_case_and_punctuation() {
+        assert_eq!(query_slug("Tokio Mutex example?"), "tokio-mutex-example");
+        assert_eq!(
+            query_slug("crates.io rust crate serde_json latest"),
+            "crates-io-rust-crate-serde-json-latest"
+        );
+    }
+
+    #[tokio::test]
+    async fn fixtures_answer_by_query_then_default() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("tokio-mutex.json"),
+            r#"[{"title": "Mutex", "url": "https://docs.rs/tokio", "snippet": "An async mutex",
+                 "content": "Use `tokio::sync::Mutex`."},
+                {"url": "https://tokio.rs"}]"#,
+        )
+        .unwrap();
+        let provider = FixtureSearchProvider::new(dir.path()).unwrap();
+
+        let hits = provider.search("Tokio mutex", 5).await.unwrap();
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].content.as_deref(), Some("Use `tokio::sync::Mutex`."));
+        assert_eq!(provider.search("tokio mutex", 1).await.unwrap().len(), 1);
+        assert!(provider.search("unknown", 5).await.unwrap().is_empty());
+
+        std::fs::write(dir.path().join("default.json"), r#"[{"url": "https://example.com"}]"#)
+            .unwrap();
+        let hits = provider.search("unknown", 5).await.unwrap();
+        assert_eq!(hits[0].url, "https://example.com");
+    }
+
+    #[test]
+    fn searxng_urls_keep_their_path() {
+        let search_url = |base| {
+            SearxngProvider::new(Arc::new(Client::new()), base)
+                .unwrap()
+                .search_url
+                .to_string()
+        };
+        assert_eq!(
+            search_url("http://localhost:8888"),
+            "http://localhost:8888/search"
+        );
+        assert_eq!(
+            search_url("https://example.com/searx"),
+            "https://example.com/searx/search"
+        );
+        assert_eq!(
+            search_url("https://example.com/searx/"),
+            "https://example.com/searx/search"
+        );
+    }
+}
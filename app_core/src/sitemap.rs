@@ -0,0 +1,85 @@
+//! Reading `sitemap.xml` files (sitemaps.org protocol) to seed a crawl.
+//! Sitemaps are simple enough that the `<loc>` entries are all we need.
+
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+static LOC: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<loc>\s*(.*?)\s*</loc>").unwrap());
+
+/// What a sitemap file lists.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sitemap {
+    /// Page URLs, from a `<urlset>`.
+    Pages(Vec<String>),
+    /// More sitemaps, from a `<sitemapindex>`.
+    Index(Vec<String>),
+}
+
+/// Parses a sitemap or sitemap index.
+pub fn parse_sitemap(xml: &str) -> Sitemap {
+    let locs = LOC
+        .captures_iter(xml)
+        .map(|c| unescape_xml(strip_cdata(&c[1])))
+        .filter(|loc| !loc.is_empty())
+        .collect();
+    if xml.contains("<sitemapindex") {
+        Sitemap::Index(locs)
+    } else {
+        Sitemap::Pages(locs)
+    }
+}
+
+fn strip_cdata(text: &str) -> &str {
+    text.strip_prefix("<![CDATA[")
+        .and_then(|t| t.strip_suffix("]]>"))
+        .unwrap_or(text)
+        .trim()
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_page_urls() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <url><loc>https://example.com/docs/</loc><lastmod>2024-01-01</lastmod></url>
+  <url>
+    <loc>
+      https://example.com/docs/page?a=1&amp;b=2
+    </loc>
+  </url>
+  <url><loc><![CDATA[https://example.com/docs/cdata]]></loc></url>
+</urlset>"#;
+        assert_eq!(
+            parse_sitemap(xml),
+            Sitemap::Pages(vec![
+                "https://example.com/docs/".to_string(),
+                "https://example.com/docs/page?a=1&b=2".to_string(),
+                "https://example.com/docs/cdata".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn recognizes_sitemap_indexes() {
+        let xml = r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <sitemap><loc>https://example.com/sitemap-1.xml</loc></sitemap>
+</sitemapindex>"#;
+        assert_eq!(
+            parse_sitemap(xml),
+            Sitemap::Index(vec!["https://example.com/sitemap-1.xml".to_string()])
+        );
+    }
+}
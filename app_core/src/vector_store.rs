@@ -0,0 +1,386 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// The JSON payload stored next to each vector.
+pub type PointPayload = Map<String, Value>;
+
+/// A point to be written to a collection.
+#[derive(Debug, Clone)]
+pub struct VectorPoint {
+    /// Must be a UUID, since that is what Qdrant accepts.
+    pub id: String,
+    pub vector: Vec<f32>,
+    pub payload: PointPayload,
+}
+
+/// A search hit.
+#[derive(Debug, Clone)]
+pub struct ScoredPoint {
+    pub id: String,
+    pub score: f32,
+    pub payload: PointPayload,
+}
+
+/// A point as returned by `scroll`. Vectors are not returned.
+#[derive(Debug, Clone)]
+pub struct StoredPoint {
+    pub id: String,
+    pub payload: PointPayload,
+}
+
+/// One page of a `scroll`, with the offset to pass in to get the next page.
+#[derive(Debug, Clone, Default)]
+pub struct ScrollPage {
+    pub points: Vec<StoredPoint>,
+    pub next_offset: Option<String>,
+}
+
+/// Exact-match conditions on payload keys; a point matches when all of them hold.
+/// A condition on an array field matches when the array contains the value.
+#[derive(Debug, Clone, Default)]
+pub struct PayloadFilter {
+    pub must_match: Vec<(String, Value)>,
+}
+
+impl PayloadFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_match(mut self, key: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.must_match.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn matches(&self, payload: &PointPayload) -> bool {
+        self.must_match
+            .iter()
+            .all(|(key, expected)| match payload.get(key) {
+                Some(Value::Array(items)) => items.contains(expected),
+                Some(actual) => actual == expected,
+                None => false,
+            })
+    }
+}
+
+/// Payload keys to set on some points; see `VectorStore::set_payloads`.
+#[derive(Debug, Clone)]
+pub struct PayloadUpdate {
+    pub ids: Vec<String>,
+    pub payload: PointPayload,
+}
+
+/// Which points a delete applies to.
+#[derive(Debug, Clone)]
+pub enum PointSelector {
+    Ids(Vec<String>),
+    Filter(PayloadFilter),
+}
+
+/// Storage for embeddings and their payloads, organised in named collections.
+#[async_trait]
+pub trait VectorStore: Send + Sync {
+    /// Creates the collection with cosine distance if it doesn't exist yet.
+    async fn ensure_collection(&self, collection: &str, dimensions: u64) -> Result<()>;
+
+    /// Inserts the points, replacing any existing points with the same id.
+    async fn upsert(&self, collection: &str, points: Vec<VectorPoint>) -> Result<()>;
+
+    /// Returns up to `limit` points closest to `vector`, best match first.
+    async fn search(
+        &self,
+        collection: &str,
+        vector: Vec<f32>,
+        limit: u64,
+        filter: Option<PayloadFilter>,
+    ) -> Result<Vec<ScoredPoint>>;
+
+    /// Sets each update's payload keys on its points in one write, keeping their
+    /// vectors and other keys. Ids that don't exist are ignored.
+    async fn set_payloads(&self, collection: &str, updates: Vec<PayloadUpdate>) -> Result<()>;
+
+    async fn delete(&self, collection: &str, selector: PointSelector) -> Result<()>;
+
+    /// Pages through the points of a collection in a stable order.
+    async fn scroll(
+        &self,
+        collection: &str,
+        filter: Option<PayloadFilter>,
+        limit: u32,
+        offset: Option<String>,
+    ) -> Result<ScrollPage>;
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct MemoryCollection {
+    dimensions: u64,
+    points: BTreeMap<String, MemoryPoint>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct MemoryPoint {
+    vector: Vec<f32>,
+    payload: PointPayload,
+}
+
+/// An in-process, brute-force cosine store for tests, CI and running without Docker.
+/// When created with a path, every write is persisted to that JSON file.
+#[derive(Default)]
+pub struct MemoryVectorStore {
+    collections: RwLock<HashMap<String, MemoryCollection>>,
+    path: Option<PathBuf>,
+    /// Held from taking a snapshot until it is renamed into place, so
+    /// concurrent writes never share the temporary file and an older snapshot
+    /// can't replace a newer one.
+    persist_lock: tokio::sync::Mutex<()>,
+}
+
+impl MemoryVectorStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens a store persisted at `path`, loading its contents if the file exists.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let collections = if path.exists() {
+            let bytes = std::fs::read(&path)
+                .with_context(|| format!("Failed to read vector store file {}", path.display()))?;
+            serde_json::from_slice(&bytes)
+                .with_context(|| format!("Failed to parse vector store file {}", path.display()))?
+        } else {
+            HashMap::new()
+        };
+        Ok(Self {
+            collections: RwLock::new(collections),
+            path: Some(path),
+            persist_lock: Default::default(),
+        })
+    }
+
+    async fn persist(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let _persisting = self.persist_lock.lock().await;
+        let bytes = serde_json::to_vec(&*self.collections.read().unwrap())?;
+        // Write next to the target and rename, so a crash never leaves a half-written file.
+        let tmp_path = path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, bytes)
+            .await
+            .with_context(|| format!("Failed to write vector store file {}", tmp_path.display()))?;
+        tokio::fs::rename(&tmp_path, path)
+            .await
+            .with_context(|| format!("Failed to replace vector store file {}", path.display()))?;
+        Ok(())
+    }
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
+
+fn missing_collection(collection: &str) -> anyhow::Error {
+    anyhow!("Collection '{}' does not exist", collection)
+}
+
+#[async_trait]
+impl VectorStore for MemoryVectorStore {
+    async fn ensure_collection(&self, collection: &str, dimensions: u64) -> Result<()> {
+        let created = {
+            let mut collections = self.collections.write().unwrap();
+            if collections.contains_key(collection) {
+                false
+            } else {
+                collections.insert(
+                    collection.to_string(),
+                    MemoryCollection {
+                        dimensions,
+                        points: BTreeMap::new(),
+                    },
+                );
+                true
+            }
+        };
+        if created {
+            println!("INFO: Created in-memory collection '{}'", collection);
+            self.persist().await?;
+        }
+        Ok(())
+    }
+
+    async fn upsert(&self, collection: &str, points: Vec<VectorPoint>) -> Result<()> {
+        {
+            let mut collections = self.collections.write().unwrap();
+            let target = collections
+                .get_mut(collection)
+                .ok_or_else(|| missing_collection(collection))?;
+            for point in points {
+                if point.vector.len() as u64 != target.dimensions {
+                    return Err(anyhow!(
+                        "Vector for point {} has {} dimensions, collection '{}' expects {}",
+                        point.id,
+                        point.vector.len(),
+                        collection,
+                        target.dimensions
+                    ));
+                }
+                target.points.insert(
+                    point.id,
+                    MemoryPoint {
+                        vector: point.vector,
+                        payload: point.payload,
+                    },
+                );
+            }
+        }
+        self.persist().await
+    }
+
+    async fn search(
+        &self,
+        collection: &str,
+        vector: Vec<f32>,
+        limit: u64,
+        filter: Option<PayloadFilter>,
+    ) -> Result<Vec<ScoredPoint>> {
+        let collections = self.collections.read().unwrap();
+        let target = collections
+            .get(collection)
+            .ok_or_else(|| missing_collection(collection))?;
+
+        let mut hits: Vec<ScoredPoint> = target
+            .points
+            .iter()
+            .filter(|(_, p)| filter.as_ref().is_none_or(|f| f.matches(&p.payload)))
+            .map(|(id, p)| ScoredPoint {
+                id: id.clone(),
+                score: cosine_similarity(&vector, &p.vector),
+                payload: p.payload.clone(),
+            })
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(limit as usize);
+        Ok(hits)
+    }
+
+    async fn set_payloads(&self, collection: &str, updates: Vec<PayloadUpdate>) -> Result<()> {
+        {
+            let mut collections = self.collections.write().unwrap();
+            let target = collections
+                .get_mut(collection)
+                .ok_or_else(|| missing_collection(collection))?;
+            for update in updates {
+                for id in update.ids {
+                    if let Some(point) = target.points.get_mut(&id) {
+                        point
+                            .payload
+                            .extend(update.payload.iter().map(|(k, v)| (k.clone(), v.clone())));
+                    }
+                }
+            }
+        }
+        self.persist().await
+    }
+
+    async fn delete(&self, collection: &str, selector: PointSelector) -> Result<()> {
+        {
+            let mut collections = self.collections.write().unwrap();
+            let target = collections
+                .get_mut(collection)
+                .ok_or_else(|| missing_collection(collection))?;
+            match selector {
+                PointSelector::Ids(ids) => {
+                    for id in ids {
+                        target.points.remove(&id);
+                    }
+                }
+                PointSelector::Filter(filter) => {
+                    target.points.retain(|_, p| !filter.matches(&p.payload));
+                }
+            }
+        }
+        self.persist().await
+    }
+
+    async fn scroll(
+        &self,
+        collection: &str,
+        filter: Option<PayloadFilter>,
+        limit: u32,
+        offset: Option<String>,
+    ) -> Result<ScrollPage> {
+        let collections = self.collections.read().unwrap();
+        let target = collections
+            .get(collection)
+            .ok_or_else(|| missing_collection(collection))?;
+
+        // Points are kept sorted by id, so the offset is simply the first id of the next page.
+        let mut matching = target
+            .points
+            .range(offset.unwrap_or_default()..)
+            .filter(|(_, p)| filter.as_ref().is_none_or(|f| f.matches(&p.payload)));
+
+        let points: Vec<StoredPoint> = matching
+            .by_ref()
+            .take(limit as usize)
+            .map(|(id, p)| StoredPoint {
+                id: id.clone(),
+                payload: p.payload.clone(),
+            })
+            .collect();
+        let next_offset = matching.next().map(|(id, _)| id.clone());
+
+        Ok(ScrollPage {
+            points,
+            next_offset,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn concurrent_writes_all_reach_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("store.json");
+        let store = MemoryVectorStore::open(&path).unwrap();
+        store.ensure_collection("docs", 2).await.unwrap();
+
+        let writes = (0..16).map(|i| {
+            store.upsert(
+                "docs",
+                vec![VectorPoint {
+                    id: format!("point-{}", i),
+                    vector: vec![1.0, i as f32],
+                    payload: PointPayload::new(),
+                }],
+            )
+        });
+        for result in futures_util::future::join_all(writes).await {
+            result.unwrap();
+        }
+
+        let reopened = MemoryVectorStore::open(&path).unwrap();
+        assert_eq!(
+            reopened.collections.read().unwrap()["docs"].points.len(),
+            16
+        );
+        assert!(!path.with_extension("tmp").exists());
+    }
+}
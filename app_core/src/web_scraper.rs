@@ -1,66 +1,498 @@
 // in app_core/src/web_scraper.rs
 
-use anyhow::{Context, Result};
-use reqwest::Url;
+use anyhow::{Context, Result, bail};
+use futures_util::future::join_all;
+use regex::Regex;
+use reqwest::{Url, header};
 use scraper::{Html, Selector};
-use std::collections::{HashSet, VecDeque};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
 
-/// Scrapes a website starting from a given URL, collecting all text content.
-pub async fn scrape_website(start_url: &str) -> Result<String> {
+use crate::{
+    AppState,
+    content_extractor::extract_markdown,
+    http_cache::FetchedPage,
+    jobs::JobProgress,
+    robots::{CRAWLER_AGENT, MAX_CRAWL_DELAY, RobotsRules},
+    sitemap::{Sitemap, parse_sitemap},
+};
+
+/// Limits for crawling a website. The defaults come from `[crawler]` in the
+/// config file; `/api/ingest/url` can override them per request.
+#[derive(Deserialize, Clone, Debug)]
+pub struct CrawlSettings {
+    /// Most pages fetched in one crawl, counting failed fetches.
+    #[serde(default = "default_max_pages")]
+    pub max_pages: usize,
+    /// How many links away from the start page the crawl goes. 0 fetches only the start page.
+    #[serde(default = "default_max_depth")]
+    pub max_depth: usize,
+    /// Regexes a discovered URL must match one of. Empty allows every URL.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Regexes that rule a discovered URL out, checked after `include`.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Only URLs whose path starts with this are crawled, e.g.
+    /// `/tokio/latest/tokio/sync/` for one module of a docs.rs crate.
+    #[serde(default)]
+    pub path_prefix: Option<String>,
+    /// Also start from the pages listed in the site's sitemaps: those named in
+    /// robots.txt, or `/sitemap.xml`.
+    #[serde(default)]
+    pub use_sitemap: bool,
+    /// Skip pages whose `<link rel=canonical>` points to a page already crawled.
+    #[serde(default = "default_honor_canonical")]
+    pub honor_canonical: bool,
+    /// Requests per second sent to one host, across all running crawls.
+    /// A larger `Crawl-delay` in robots.txt wins. 0 disables the limit.
+    #[serde(default = "default_requests_per_second")]
+    pub requests_per_second: f64,
+    /// Pages fetched at the same time within one crawl.
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    #[serde(default = "default_request_timeout_secs")]
+    pub request_timeout_secs: u64,
+    #[serde(default = "default_respect_robots_txt")]
+    pub respect_robots_txt: bool,
+}
+
+fn default_max_pages() -> usize {
+    200
+}
+
+fn default_max_depth() -> usize {
+    3
+}
+
+fn default_honor_canonical() -> bool {
+    true
+}
+
+fn default_requests_per_second() -> f64 {
+    2.0
+}
+
+fn default_concurrency() -> usize {
+    4
+}
+
+fn default_request_timeout_secs() -> u64 {
+    15
+}
+
+fn default_respect_robots_txt() -> bool {
+    true
+}
+
+impl Default for CrawlSettings {
+    fn default() -> Self {
+        Self {
+            max_pages: default_max_pages(),
+            max_depth: default_max_depth(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            path_prefix: None,
+            use_sitemap: false,
+            honor_canonical: default_honor_canonical(),
+            requests_per_second: default_requests_per_second(),
+            concurrency: default_concurrency(),
+            request_timeout_secs: default_request_timeout_secs(),
+            respect_robots_txt: default_respect_robots_txt(),
+        }
+    }
+}
+
+/// Per-request changes to the configured `CrawlSettings`; unset fields keep
+/// the configured value.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct CrawlOverrides {
+    #[serde(default)]
+    pub max_pages: Option<usize>,
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+    #[serde(default)]
+    pub include: Option<Vec<String>>,
+    #[serde(default)]
+    pub exclude: Option<Vec<String>>,
+    #[serde(default)]
+    pub path_prefix: Option<String>,
+    #[serde(default)]
+    pub use_sitemap: Option<bool>,
+    #[serde(default)]
+    pub honor_canonical: Option<bool>,
+    #[serde(default)]
+    pub requests_per_second: Option<f64>,
+    #[serde(default)]
+    pub concurrency: Option<usize>,
+    #[serde(default)]
+    pub request_timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub respect_robots_txt: Option<bool>,
+}
+
+impl CrawlSettings {
+    /// The pause between requests to the crawled host. Rates too low to
+    /// represent wait `MAX_CRAWL_DELAY`; zero or less means no limit.
+    pub fn request_interval(&self) -> Duration {
+        if self.requests_per_second > 0.0 {
+            Duration::try_from_secs_f64(1.0 / self.requests_per_second)
+                .map_or(MAX_CRAWL_DELAY, |d| d.min(MAX_CRAWL_DELAY))
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    pub fn with_overrides(&self, overrides: &CrawlOverrides) -> Self {
+        let o = overrides.clone();
+        Self {
+            max_pages: o.max_pages.unwrap_or(self.max_pages),
+            max_depth: o.max_depth.unwrap_or(self.max_depth),
+            include: o.include.unwrap_or_else(|| self.include.clone()),
+            exclude: o.exclude.unwrap_or_else(|| self.exclude.clone()),
+            path_prefix: o.path_prefix.or_else(|| self.path_prefix.clone()),
+            use_sitemap: o.use_sitemap.unwrap_or(self.use_sitemap),
+            honor_canonical: o.honor_canonical.unwrap_or(self.honor_canonical),
+            requests_per_second: o.requests_per_second.unwrap_or(self.requests_per_second),
+            concurrency: o.concurrency.unwrap_or(self.concurrency),
+            request_timeout_secs: o.request_timeout_secs.unwrap_or(self.request_timeout_secs),
+            respect_robots_txt: o.respect_robots_txt.unwrap_or(self.respect_robots_txt),
+        }
+    }
+}
+
+/// Spaces out requests to the same host. Shared by every crawl of the process,
+/// so two jobs crawling one site don't double its load.
+#[derive(Default)]
+pub struct HostThrottle {
+    next_slot: Mutex<HashMap<String, Instant>>,
+}
+
+impl HostThrottle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Waits until a request to `host` may go out, keeping `interval` between requests.
+    pub async fn wait(&self, host: &str, interval: Duration) {
+        if interval.is_zero() {
+            return;
+        }
+        let slot = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let now = Instant::now();
+            let slot = next_slot.get(host).copied().unwrap_or(now).max(now);
+            next_slot.insert(host.to_string(), slot + interval);
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+}
+
+/// Which URLs a crawl may visit: its path prefix and its include and exclude
+/// patterns, compiled.
+struct UrlFilter {
+    path_prefix: Option<String>,
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl UrlFilter {
+    fn new(settings: &CrawlSettings) -> Result<Self> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|p| Regex::new(p).with_context(|| format!("Invalid URL pattern `{}`", p)))
+                .collect::<Result<Vec<_>>>()
+        };
+        Ok(Self {
+            path_prefix: settings.path_prefix.clone().filter(|p| !p.is_empty()),
+            include: compile(&settings.include)?,
+            exclude: compile(&settings.exclude)?,
+        })
+    }
+
+    fn allows(&self, url: &Url) -> bool {
+        if self
+            .path_prefix
+            .as_ref()
+            .is_some_and(|prefix| !url.path().starts_with(prefix.as_str()))
+        {
+            return false;
+        }
+        let url = url.as_str();
+        (self.include.is_empty() || self.include.iter().any(|re| re.is_match(url)))
+            && !self.exclude.iter().any(|re| re.is_match(url))
+    }
+}
+
+pub(crate) fn user_agent() -> String {
+    format!("{}/{}", CRAWLER_AGENT, env!("CARGO_PKG_VERSION"))
+}
+
+/// The path and query robots.txt rules are matched against.
+fn robots_path(url: &Url) -> String {
+    match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    }
+}
+
+/// Fetches the robots.txt of `start_url`'s host. A missing robots.txt allows
+/// everything; one that can't be fetched disallows everything, as RFC 9309 asks.
+async fn fetch_robots(
+    state: &AppState,
+    start_url: &Url,
+    settings: &CrawlSettings,
+    progress: &JobProgress,
+) -> RobotsRules {
+    let Ok(robots_url) = start_url.join("/robots.txt") else {
+        return RobotsRules::allow_all();
+    };
+    let request = state
+        .http_client
+        .get(robots_url.clone())
+        .header(header::USER_AGENT, user_agent())
+        .timeout(Duration::from_secs(settings.request_timeout_secs));
+    match state.http_cache.fetch(robots_url.as_str(), request).await {
+        Ok(page) if page.is_ok() => RobotsRules::parse(&page.body, CRAWLER_AGENT),
+        Ok(page) if (400..500).contains(&page.status) => RobotsRules::allow_all(),
+        Ok(page) => {
+            progress.error(format!("Failed to fetch {}: {}", robots_url, page.status));
+            RobotsRules::parse("User-agent: *\nDisallow: /", CRAWLER_AGENT)
+        }
+        Err(e) => {
+            progress.error(format!("Failed to fetch {}: {:#}", robots_url, e));
+            RobotsRules::parse("User-agent: *\nDisallow: /", CRAWLER_AGENT)
+        }
+    }
+}
+
+/// Sends the requests of a crawl: to its one host, throttled, with its timeout,
+/// through the HTTP cache.
+struct Fetcher<'a> {
+    state: &'a AppState,
+    host: &'a str,
+    interval: Duration,
+    timeout: Duration,
+    progress: &'a JobProgress,
+}
+
+impl Fetcher<'_> {
+    /// GETs `url`, from the cache or once the host's throttle allows. Returns `None`
+    /// for failed requests and for responses other than 200 OK, which are reported
+    /// to `progress`.
+    async fn get(&self, url: &Url) -> Option<FetchedPage> {
+        let cache = &self.state.http_cache;
+        let page = match cache.cached_page(url.as_str()).await {
+            Ok(Some(page)) => Ok(page),
+            Ok(None) => {
+                self.state.host_throttle.wait(self.host, self.interval).await;
+                println!("Scraping: {}", url);
+                let request = self
+                    .state
+                    .http_client
+                    .get(url.clone())
+                    .header(header::USER_AGENT, user_agent())
+                    .timeout(self.timeout);
+                cache.fetch(url.as_str(), request).await
+            }
+            Err(e) => Err(e),
+        };
+        match page {
+            Ok(page) if page.is_ok() => Some(page),
+            Ok(page) => {
+                self.progress
+                    .error(format!("Failed to fetch {}: {}", url, page.status));
+                None
+            }
+            Err(e) => {
+                self.progress
+                    .error(format!("Failed to fetch {}: {:#}", url, e));
+                None
+            }
+        }
+    }
+
+    /// Fetches one page. Returns `None` for pages that failed and for pages that aren't HTML.
+    async fn fetch_page(&self, url: &Url) -> Option<String> {
+        let page = self.get(url).await?;
+        let is_html = page
+            .content_type
+            .as_deref()
+            .is_none_or(|v| v.contains("html"));
+        if !is_html {
+            return None;
+        }
+        self.progress.page_fetched();
+        Some(page.body)
+    }
+
+    async fn fetch_text(&self, url: &Url) -> Option<String> {
+        self.get(url).await.map(|page| page.body)
+    }
+}
+
+/// Sitemap files read per crawl, counting those listed in sitemap indexes.
+const MAX_SITEMAPS: usize = 16;
+
+/// The page URLs listed in the site's sitemaps: those named in robots.txt, or
+/// `/sitemap.xml` when it names none. Sitemap indexes are followed, but only
+/// to sitemaps on the crawled host.
+async fn sitemap_urls(fetcher: &Fetcher<'_>, start_url: &Url, robots: &RobotsRules) -> Vec<Url> {
+    let mut pending: VecDeque<Url> = robots
+        .sitemaps
+        .iter()
+        .filter_map(|s| Url::parse(s).ok())
+        .collect();
+    if pending.is_empty() {
+        pending.extend(start_url.join("/sitemap.xml"));
+    }
+
+    let mut seen = HashSet::new();
+    let mut pages = Vec::new();
+    while let Some(sitemap_url) = pending.pop_front() {
+        if seen.len() >= MAX_SITEMAPS {
+            break;
+        }
+        if sitemap_url.domain() != Some(fetcher.host) || !seen.insert(sitemap_url.to_string()) {
+            continue;
+        }
+        let Some(xml) = fetcher.fetch_text(&sitemap_url).await else {
+            continue;
+        };
+        match parse_sitemap(&xml) {
+            Sitemap::Index(locs) => pending.extend(locs.iter().filter_map(|l| Url::parse(l).ok())),
+            Sitemap::Pages(locs) => pages.extend(locs.iter().filter_map(|l| Url::parse(l).ok())),
+        }
+    }
+    println!("Found {} pages in the sitemaps of {}", pages.len(), fetcher.host);
+    pages
+}
+
+/// The page a document names as its canonical version, if it names one.
+fn canonical_url(document: &Html, page_url: &Url) -> Option<Url> {
+    let selector = Selector::parse(r#"link[rel~="canonical"][href]"#).unwrap();
+    let href = document.select(&selector).next()?.value().attr("href")?;
+    let mut url = page_url.join(href).ok()?;
+    url.set_fragment(None);
+    Some(url)
+}
+
+/// Crawls a website breadth-first from `start_url`, staying on its domain, and
+/// collects the main content of every page as Markdown, within the limits of `settings`.
+/// Pages that fail to load are reported to `progress` and skipped.
+pub async fn scrape_website(
+    state: &AppState,
+    start_url: &str,
+    settings: &CrawlSettings,
+    progress: &JobProgress,
+) -> Result<String> {
     let start_url = Url::parse(start_url).context("Failed to parse start URL")?;
     let domain = start_url.domain().context("URL has no domain")?.to_string();
+    let filter = UrlFilter::new(settings)?;
+
+    let robots = if settings.respect_robots_txt {
+        fetch_robots(state, &start_url, settings, progress).await
+    } else {
+        RobotsRules::allow_all()
+    };
+    if !robots.is_allowed(&robots_path(&start_url)) {
+        bail!("robots.txt of {} disallows crawling {}", domain, start_url);
+    }
+
+    let mut interval = settings.request_interval();
+    if let Some(crawl_delay) = robots.crawl_delay {
+        interval = interval.max(crawl_delay);
+    }
+    let fetcher = Fetcher {
+        state,
+        host: &domain,
+        interval,
+        timeout: Duration::from_secs(settings.request_timeout_secs),
+        progress,
+    };
+    let concurrency = settings.concurrency.max(1);
+    let crawlable = |url: &Url| {
+        url.domain() == Some(domain.as_str())
+            && filter.allows(url)
+            && robots.is_allowed(&robots_path(url))
+    };
 
     let mut queue = VecDeque::new();
     let mut visited = HashSet::new();
     let mut all_text = String::new();
+    let mut pages_requested = 0;
 
-    queue.push_back(start_url.clone());
+    queue.push_back((start_url.clone(), 0));
     visited.insert(start_url.to_string());
 
     println!("Starting scrape of domain: {}", domain);
 
-    while let Some(current_url) = queue.pop_front() {
-        println!("Scraping: {}", current_url);
-
-        // Fetch the page content
-        let response = reqwest::get(current_url.clone()).await?;
-        if !response.status().is_success() {
-            println!("Warning: Failed to fetch {}: {}", current_url, response.status());
-            continue;
+    if settings.use_sitemap {
+        for url in sitemap_urls(&fetcher, &start_url, &robots).await {
+            if crawlable(&url) && visited.insert(url.to_string()) {
+                queue.push_back((url, 0));
+            }
         }
+    }
+
+    while !queue.is_empty() && pages_requested < settings.max_pages {
+        progress.check_cancelled()?;
 
-        let body = response.text().await?;
-        let document = Html::parse_document(&body);
+        // Fetch the next few pages at once, never more than max_pages in total
+        let wave_size = concurrency
+            .min(settings.max_pages - pages_requested)
+            .min(queue.len());
+        let wave: Vec<(Url, usize)> = queue.drain(..wave_size).collect();
+        pages_requested += wave.len();
+        let bodies = join_all(wave.iter().map(|(url, _)| fetcher.fetch_page(url))).await;
+
+        for ((url, depth), body) in wave.into_iter().zip(bodies) {
+            let Some(body) = body else {
+                continue;
+            };
+            let document = Html::parse_document(&body);
+
+            // Pages that name another page as canonical are duplicates of it;
+            // keep only the first of them to be crawled.
+            if settings.honor_canonical {
+                let canonical = canonical_url(&document, &url)
+                    .filter(|c| *c != url && c.domain() == Some(domain.as_str()));
+                if canonical.is_some_and(|c| !visited.insert(c.to_string())) {
+                    println!("Skipping {}: its canonical page was already crawled", url);
+                    continue;
+                }
+            }
 
-        // 1. Extract and append the text content from the current page
-        let text = extract_text_from_html(&document);
-        all_text.push_str(&text);
-        all_text.push_str("\n\n"); // Add separation between pages
+            // 1. Extract and append the main content of the current page as Markdown
+            let markdown = extract_markdown(&document);
+            all_text.push_str(&markdown);
+            all_text.push_str("\n\n"); // Add separation between pages
 
-        // 2. Find and queue new links on the same domain
-        let links = find_links_on_page(&document, &current_url, &domain);
-        for link in links {
-            if visited.insert(link.to_string()) {
-                queue.push_back(link);
+            // 2. Queue new links that are in scope and allowed by robots.txt
+            if depth >= settings.max_depth {
+                continue;
+            }
+            for link in find_links_on_page(&document, &url, &domain) {
+                if crawlable(&link) && visited.insert(link.to_string()) {
+                    queue.push_back((link, depth + 1));
+                }
             }
         }
     }
 
-    println!("Scrape complete. Total characters found: {}", all_text.len());
+    println!(
+        "Scrape complete. {} pages requested, total characters found: {}",
+        pages_requested,
+        all_text.len()
+    );
     Ok(all_text)
 }
 
-/// Extracts all visible text from an HTML document.
-fn extract_text_from_html(document: &Html) -> String {
-    // We select the `body` tag to avoid scraping text from `<head>`, `<script>`, etc.
-    let body_selector = Selector::parse("body").unwrap();
-    if let Some(body_node) = document.select(&body_selector).next() {
-        body_node.text().collect::<String>()
-    } else {
-        String::new()
-    }
-}
-
 /// Finds all valid, same-domain links on a page.
 fn find_links_on_page(document: &Html, base_url: &Url, domain: &str) -> HashSet<Url> {
     let link_selector = Selector::parse("a[href]").unwrap();
@@ -84,4 +516,93 @@ fn find_links_on_page(document: &Html, base_url: &Url, domain: &str) -> HashSet<
         }
     }
     valid_links
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_replace_only_the_fields_they_set() {
+        let configured = CrawlSettings::default();
+        let overrides: CrawlOverrides =
+            serde_json::from_str(r#"{"max_pages": 5, "exclude": ["/blog/"]}"#).unwrap();
+        let settings = configured.with_overrides(&overrides);
+        assert_eq!(settings.max_pages, 5);
+        assert_eq!(settings.exclude, ["/blog/"]);
+        assert_eq!(settings.max_depth, configured.max_depth);
+        assert!(settings.respect_robots_txt);
+    }
+
+    #[test]
+    fn request_intervals_stay_representable() {
+        let at = |requests_per_second| CrawlSettings {
+            requests_per_second,
+            ..Default::default()
+        };
+        assert_eq!(at(4.0).request_interval(), Duration::from_millis(250));
+        assert_eq!(at(0.0).request_interval(), Duration::ZERO);
+        assert_eq!(at(f64::NAN).request_interval(), Duration::ZERO);
+        assert_eq!(at(1e-300).request_interval(), MAX_CRAWL_DELAY);
+        assert_eq!(at(0.001).request_interval(), MAX_CRAWL_DELAY);
+    }
+
+    #[test]
+    fn include_and_exclude_patterns_filter_urls() {
+        let settings = CrawlSettings {
+            include: vec!["/docs/".to_string()],
+            exclude: vec![r"/docs/v\d+/".to_string()],
+            ..Default::default()
+        };
+        let filter = UrlFilter::new(&settings).unwrap();
+        let url = |s: &str| Url::parse(s).unwrap();
+        assert!(filter.allows(&url("https://tokio.rs/docs/overview")));
+        assert!(!filter.allows(&url("https://tokio.rs/blog/")));
+        assert!(!filter.allows(&url("https://tokio.rs/docs/v1/overview")));
+
+        let scoped = CrawlSettings {
+            path_prefix: Some("/tokio/latest/tokio/sync/".to_string()),
+            ..Default::default()
+        };
+        let filter = UrlFilter::new(&scoped).unwrap();
+        assert!(filter.allows(&url("https://docs.rs/tokio/latest/tokio/sync/struct.Mutex.html")));
+        assert!(!filter.allows(&url("https://docs.rs/tokio/latest/tokio/net/index.html")));
+
+        let invalid = CrawlSettings {
+            exclude: vec!["(".to_string()],
+            ..Default::default()
+        };
+        assert!(UrlFilter::new(&invalid).is_err());
+    }
+
+    #[test]
+    fn canonical_links_resolve_against_the_page() {
+        let page = Url::parse("https://docs.rs/tokio/1.0.0/tokio/sync/index.html").unwrap();
+        let html = Html::parse_document(
+            r#"<head><link rel="canonical" href="/tokio/latest/tokio/sync/index.html#top"></head>"#,
+        );
+        assert_eq!(
+            canonical_url(&html, &page).unwrap().as_str(),
+            "https://docs.rs/tokio/latest/tokio/sync/index.html"
+        );
+        assert_eq!(canonical_url(&Html::parse_document("<p>No head</p>"), &page), None);
+    }
+
+    #[test]
+    fn links_stay_on_the_domain() {
+        let html = Html::parse_document(
+            r#"<a href="/guide#intro">Guide</a><a href="https://other.org/">Other</a>
+               <a href="mailto:a@tokio.rs">Mail</a><a href="page?x=1">Page</a>"#,
+        );
+        let base = Url::parse("https://tokio.rs/docs/").unwrap();
+        let mut links: Vec<String> = find_links_on_page(&html, &base, "tokio.rs")
+            .into_iter()
+            .map(|u| u.to_string())
+            .collect();
+        links.sort();
+        assert_eq!(
+            links,
+            ["https://tokio.rs/docs/page", "https://tokio.rs/guide"]
+        );
+    }
+}
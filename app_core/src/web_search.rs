@@ -1,35 +1,66 @@
-use anyhow::{Context, Result};
-use duckduckgo_rs::{search_duckduckgo, SearchResult};
-use reqwest::Client;
-use scraper::{Html, Selector};
-
-/// Searches the web using DuckDuckGo, scrapes the top results, and returns the combined text content.
-pub async fn search_and_scrape(http_client: &Client, query: &str) -> Result<String> {
-    // 1. Search DuckDuckGo
-    let search_results: Vec<SearchResult> = search_duckduckgo(http_client, query)
+use std::time::Duration;
+
+use anyhow::{Result, anyhow, bail};
+use futures_util::{StreamExt, stream};
+use scraper::Html;
+
+use crate::{AppState, content_extractor::extract_markdown, search_provider::SearchHit};
+
+/// Text gathered from the web together with the URLs it came from.
+#[derive(Debug, Clone, Default)]
+pub struct WebContext {
+    pub text: String,
+    pub sources: Vec<String>,
+    /// Pages that failed or timed out. The text was gathered without them.
+    pub errors: Vec<String>,
+}
+
+/// Searches the web with the configured search provider, scrapes the top results
+/// concurrently, and returns their main content as Markdown. Only the search
+/// itself failing is an error; pages that fail are listed in `errors`.
+pub async fn search_and_scrape(state: &AppState, query: &str) -> Result<WebContext> {
+    let settings = &state.search_settings;
+    let timeout = Duration::from_secs(settings.request_timeout_secs);
+
+    // 1. Search
+    let hits = tokio::time::timeout(timeout, state.search_provider.search(query, settings.top_n))
         .await
-        .context("Failed to get search results from DuckDuckGo")?;
+        .map_err(|_| anyhow!("Web search timed out after {}s", settings.request_timeout_secs))??;
 
-    let mut scraped_content = Vec::new();
+    // 2. Scrape the top results a few at a time, keeping their order
+    let pages: Vec<(SearchHit, Result<String>)> = stream::iter(hits)
+        .map(|hit| async move {
+            let content = page_content(state, &hit, timeout).await;
+            (hit, content)
+        })
+        .buffered(settings.concurrency.max(1))
+        .collect()
+        .await;
 
-    // 2. Scrape the top 2 results
-    for result in search_results.iter().take(2) {
-        scraped_content.push(result.description.clone());
-
-        if let Ok(response) = http_client.get(&result.url).send().await {
-            if let Ok(html_content) = response.text().await {
-                let document = Html::parse_document(&html_content);
-                if let Ok(selector) = Selector::parse("p, h1, h2, h3, code") {
-                    let text = document
-                        .select(&selector)
-                        .map(|el| el.text().collect::<Vec<_>>().join(" "))
-                        .collect::<Vec<_>>()
-                        .join("\n");
-                    scraped_content.push(text);
-                }
-            }
+    let mut context = WebContext::default();
+    let mut scraped_content = Vec::new();
+    for (hit, content) in pages {
+        scraped_content.push(hit.snippet);
+        match content {
+            Ok(content) => scraped_content.push(content),
+            Err(e) => context.errors.push(format!("Skipped {}: {:#}", hit.url, e)),
         }
+        context.sources.push(hit.url);
     }
+    context.text = scraped_content.join("\n---\n");
+    Ok(context)
+}
 
-    Ok(scraped_content.join("\n---\n"))
-}
\ No newline at end of file
+/// The main content of a search result's page, fetched through the HTTP cache
+/// unless the provider already has it.
+async fn page_content(state: &AppState, hit: &SearchHit, timeout: Duration) -> Result<String> {
+    if let Some(content) = &hit.content {
+        return Ok(content.clone());
+    }
+    let request = state.http_client.get(&hit.url).timeout(timeout);
+    let page = state.http_cache.fetch(&hit.url, request).await?;
+    if !page.is_ok() {
+        bail!("HTTP {}", page.status);
+    }
+    Ok(extract_markdown(&Html::parse_document(&page.body)))
+}
@@ -0,0 +1,194 @@
+//! Web search and crate research degrade to warnings when the search provider,
+//! crates.io or docs.rs fail, instead of failing the query.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use app_core::{
+    AppSettings, AppState,
+    chat_backend::ScriptedBackend,
+    crate_research::research_crate,
+    embedder::HashEmbedder,
+    search_provider::{SearchHit, SearchProvider},
+    vector_store::MemoryVectorStore,
+    web_search::search_and_scrape,
+};
+use async_trait::async_trait;
+use serde_json::{Value, json};
+
+// Nothing listens on the discard port, so requests to it fail fast.
+const UNREACHABLE: &str = "http://127.0.0.1:9";
+
+fn fixtures() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/pipeline")
+}
+
+/// Answers every search with an error.
+struct FailingProvider;
+
+#[async_trait]
+impl SearchProvider for FailingProvider {
+    async fn search(&self, _query: &str, _limit: usize) -> Result<Vec<SearchHit>> {
+        bail!("search engine is down")
+    }
+}
+
+/// Never answers within the search timeout.
+struct SlowProvider;
+
+#[async_trait]
+impl SearchProvider for SlowProvider {
+    async fn search(&self, _query: &str, _limit: usize) -> Result<Vec<SearchHit>> {
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        Ok(Vec::new())
+    }
+}
+
+/// Returns the same hits for every query.
+struct StaticProvider(Vec<SearchHit>);
+
+#[async_trait]
+impl SearchProvider for StaticProvider {
+    async fn search(&self, _query: &str, limit: usize) -> Result<Vec<SearchHit>> {
+        Ok(self.0.iter().take(limit).cloned().collect())
+    }
+}
+
+async fn state_with(
+    scratch: &tempfile::TempDir,
+    crate_research: Value,
+    search_provider: Arc<dyn SearchProvider>,
+) -> AppState {
+    let settings: AppSettings = serde_json::from_value(json!({
+        "qdrant_url": "",
+        "llm_model": "",
+        "vector_store": "memory",
+        "search": {
+            "provider": "fixtures",
+            "fixtures_dir": fixtures().join("search"),
+            "request_timeout_secs": 1,
+        },
+        "http_cache": {
+            "mode": "off",
+            "dir": scratch.path().join("http_cache"),
+        },
+        "crate_research": crate_research,
+        "sandbox": {
+            "cache": { "dir": scratch.path().join("build_cache") },
+        },
+    }))
+    .unwrap();
+
+    let mut state = AppState::from_parts(
+        settings,
+        Arc::new(MemoryVectorStore::new()),
+        Arc::new(ScriptedBackend::new(Vec::new())),
+        Arc::new(HashEmbedder),
+    )
+    .await
+    .unwrap();
+    state.search_provider = search_provider;
+    state
+}
+
+fn local_index() -> Value {
+    json!({
+        "local_index_dir": fixtures().join("index"),
+        "docs_url": UNREACHABLE,
+    })
+}
+
+#[tokio::test]
+async fn failing_and_slow_searches_are_errors() {
+    let scratch = tempfile::tempdir().unwrap();
+
+    let state = state_with(&scratch, local_index(), Arc::new(FailingProvider)).await;
+    let error = search_and_scrape(&state, "tokio").await.unwrap_err();
+    assert!(format!("{:#}", error).contains("search engine is down"));
+
+    let state = state_with(&scratch, local_index(), Arc::new(SlowProvider)).await;
+    let error = search_and_scrape(&state, "tokio").await.unwrap_err();
+    assert!(error.to_string().contains("timed out"));
+}
+
+#[tokio::test]
+async fn pages_that_fail_are_skipped() {
+    let scratch = tempfile::tempdir().unwrap();
+    let hits = vec![
+        SearchHit {
+            title: "Gone".to_string(),
+            url: format!("{}/gone", UNREACHABLE),
+            snippet: "Unreachable page".to_string(),
+            content: None,
+        },
+        SearchHit {
+            title: "Guide".to_string(),
+            url: "https://example.com/guide".to_string(),
+            snippet: "A guide".to_string(),
+            content: Some("Use `spawn`.".to_string()),
+        },
+    ];
+    let state = state_with(&scratch, local_index(), Arc::new(StaticProvider(hits))).await;
+
+    let context = search_and_scrape(&state, "tokio").await.unwrap();
+    assert_eq!(
+        context.sources,
+        [
+            format!("{}/gone", UNREACHABLE),
+            "https://example.com/guide".to_string()
+        ]
+    );
+    assert!(context.text.contains("Use `spawn`."));
+    assert_eq!(context.errors.len(), 1);
+    assert!(context.errors[0].starts_with(&format!("Skipped {}/gone", UNREACHABLE)));
+}
+
+#[tokio::test]
+async fn unreachable_docs_fall_back_to_a_failing_search() {
+    let scratch = tempfile::tempdir().unwrap();
+    let state = state_with(&scratch, local_index(), Arc::new(FailingProvider)).await;
+
+    let research = research_crate(&state, "parse a token stream", "syn").await;
+    assert!(!research.missing);
+    assert!(research.text.contains("Crate 'syn' 2.0.104 (crates.io)"));
+    assert!(
+        research
+            .warnings
+            .iter()
+            .any(|w| w.contains("Research for crate 'syn' failed"))
+    );
+}
+
+#[tokio::test]
+async fn unreachable_registry_is_a_warning() {
+    let scratch = tempfile::tempdir().unwrap();
+    let registry_down = json!({
+        "registry_api_url": UNREACHABLE,
+        "docs_url": UNREACHABLE,
+    });
+    let state = state_with(&scratch, registry_down, Arc::new(SlowProvider)).await;
+
+    let research = research_crate(&state, "parse a token stream", "syn").await;
+    assert!(!research.missing, "an unreachable registry proves nothing");
+    assert!(research.text.is_empty());
+    assert!(
+        research
+            .warnings
+            .iter()
+            .any(|w| w.contains("Could not look up crate 'syn'"))
+    );
+    assert!(research.warnings.iter().any(|w| w.contains("timed out")));
+}
+
+#[tokio::test]
+async fn crates_missing_from_the_index_are_reported() {
+    let scratch = tempfile::tempdir().unwrap();
+    let state = state_with(&scratch, local_index(), Arc::new(FailingProvider)).await;
+
+    let research = research_crate(&state, "parse", "quick-parse-magic").await;
+    assert!(research.missing);
+    assert!(research.text.is_empty());
+    assert!(research.sources.is_empty());
+}
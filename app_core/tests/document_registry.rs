@@ -0,0 +1,184 @@
+//! The document registry over an in-memory vector store.
+
+use std::sync::Arc;
+
+use app_core::{
+    AppSettings, AppState,
+    chat_backend::ScriptedBackend,
+    documents::{delete_document, document_chunks, list_documents},
+    embedder::HashEmbedder,
+    ingestion::{
+        DocumentFormat, chunk_document, ingest_document, knowledge_points, plan_replacement,
+    },
+    jobs::JobProgress,
+    qdrant::KNOWLEDGE_BASE_COLLECTION,
+    vector_store::{MemoryVectorStore, PayloadUpdate, StoredPoint, VectorStore},
+};
+use serde_json::json;
+
+async fn store_with(documents: &[(&str, &str, &str)]) -> MemoryVectorStore {
+    let store = MemoryVectorStore::new();
+    store
+        .ensure_collection(KNOWLEDGE_BASE_COLLECTION, 2)
+        .await
+        .unwrap();
+    for (id, source, text) in documents {
+        let chunks = chunk_document(id, source, text, DocumentFormat::Markdown).unwrap();
+        let embeddings = chunks.iter().map(|_| vec![1.0, 0.0]).collect();
+        let points = knowledge_points(&chunks, embeddings).unwrap();
+        store
+            .upsert(KNOWLEDGE_BASE_COLLECTION, points)
+            .await
+            .unwrap();
+    }
+    store
+}
+
+#[tokio::test]
+async fn documents_are_listed_with_chunk_counts() {
+    let store = store_with(&[
+        ("doc-b", "b.md", "# One\n\nFirst.\n\n# Two\n\nSecond.\n"),
+        ("doc-a", "a.md", "Just one chunk.\n"),
+    ])
+    .await;
+
+    let documents = list_documents(&store).await.unwrap();
+    let listed: Vec<(&str, &str, usize)> = documents
+        .iter()
+        .map(|d| (d.document_id.as_str(), d.source.as_str(), d.chunks))
+        .collect();
+    assert_eq!(listed, [("doc-a", "a.md", 1), ("doc-b", "b.md", 2)]);
+
+    let chunks = document_chunks(&store, "doc-b").await.unwrap();
+    let texts: Vec<&str> = chunks.iter().map(|c| c.text.as_str()).collect();
+    assert_eq!(texts, ["# One\n\nFirst.", "# Two\n\nSecond."]);
+}
+
+#[tokio::test]
+async fn deleting_a_document_leaves_the_others() {
+    let store = store_with(&[
+        ("doc-a", "a.md", "# One\n\nFirst.\n\n# Two\n\nSecond.\n"),
+        ("doc-b", "b.md", "Other.\n"),
+    ])
+    .await;
+
+    assert_eq!(delete_document(&store, "doc-a").await.unwrap(), 2);
+    assert_eq!(delete_document(&store, "doc-a").await.unwrap(), 0);
+
+    let documents = list_documents(&store).await.unwrap();
+    assert_eq!(documents.len(), 1);
+    assert_eq!(documents[0].document_id, "doc-b");
+    assert!(document_chunks(&store, "doc-a").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn reingesting_only_touches_changed_chunks() {
+    let original = "# One\n\nFirst.\n\n# Two\n\nSecond.\n";
+    let store = store_with(&[("doc-a", "a.md", original)]).await;
+    let existing: Vec<StoredPoint> = store
+        .scroll(KNOWLEDGE_BASE_COLLECTION, None, 100, None)
+        .await
+        .unwrap()
+        .points;
+
+    let same = chunk_document("doc-a", "a.md", original, DocumentFormat::Markdown).unwrap();
+    let plan = plan_replacement(&existing, &same);
+    assert_eq!((plan.to_store.len(), plan.unchanged, plan.to_remove.len()), (0, 2, 0));
+
+    // "Two" is gone and a new section follows "One", so "One" keeps its place.
+    let edited = "# One\n\nFirst.\n\n# Three\n\nThird.\n\n# Three\n\nThird.\n";
+    let chunks = chunk_document("doc-a", "a.md", edited, DocumentFormat::Markdown).unwrap();
+    let plan = plan_replacement(&existing, &chunks);
+    assert_eq!(plan.unchanged, 1);
+    assert_eq!(plan.to_store.len(), 1, "duplicate sections are stored once");
+    assert_eq!(plan.to_store[0].text, "# Three\n\nThird.");
+    assert_eq!(plan.to_remove.len(), 1);
+}
+
+#[tokio::test]
+async fn moved_chunks_keep_their_vectors() {
+    let original = "# One\n\nFirst.\n\n# Two\n\nSecond.\n";
+    let store = store_with(&[("doc-a", "a.md", original)]).await;
+    let existing = store
+        .scroll(KNOWLEDGE_BASE_COLLECTION, None, 100, None)
+        .await
+        .unwrap()
+        .points;
+
+    let edited = "# Zero\n\nNew.\n\n# One\n\nFirst.\n\n# Two\n\nSecond.\n";
+    let chunks = chunk_document("doc-a", "a.md", edited, DocumentFormat::Markdown).unwrap();
+    let plan = plan_replacement(&existing, &chunks);
+    assert_eq!(plan.to_store.len(), 1);
+    assert_eq!(plan.to_store[0].text, "# Zero\n\nNew.");
+    assert_eq!(
+        plan.to_update.len(),
+        2,
+        "moved sections are not embedded again"
+    );
+    assert_eq!((plan.unchanged, plan.to_remove.len()), (0, 0));
+
+    let updates = plan
+        .to_update
+        .iter()
+        .map(|chunk| PayloadUpdate {
+            ids: vec![chunk.point_id()],
+            payload: chunk.to_payload().unwrap(),
+        })
+        .collect();
+    store
+        .set_payloads(KNOWLEDGE_BASE_COLLECTION, updates)
+        .await
+        .unwrap();
+    let stored = document_chunks(&store, "doc-a").await.unwrap();
+    let indexes: Vec<(usize, &str)> = stored
+        .iter()
+        .map(|c| (c.chunk_index, c.text.as_str()))
+        .collect();
+    assert_eq!(indexes, [(1, "# One\n\nFirst."), (2, "# Two\n\nSecond.")]);
+}
+
+async fn offline_state(scratch: &tempfile::TempDir) -> AppState {
+    let settings: AppSettings = serde_json::from_value(json!({
+        "qdrant_url": "",
+        "llm_model": "",
+        "vector_store": "memory",
+        "http_cache": { "mode": "off", "dir": scratch.path().join("http_cache") },
+        "sandbox": { "cache": { "dir": scratch.path().join("build_cache") } },
+    }))
+    .unwrap();
+    AppState::from_parts(
+        settings,
+        Arc::new(MemoryVectorStore::new()),
+        Arc::new(ScriptedBackend::new(Vec::new())),
+        Arc::new(HashEmbedder),
+    )
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn uploading_the_same_file_twice_stores_it_once() {
+    let scratch = tempfile::tempdir().unwrap();
+    let state = offline_state(&scratch).await;
+    let progress = JobProgress::none();
+    let upload = || {
+        ingest_document(
+            state.clone(),
+            "guide.md".to_string(),
+            "# One\n\nFirst.\n\n# Two\n\nSecond.\n".to_string(),
+            DocumentFormat::Markdown,
+            None,
+            &progress,
+        )
+    };
+
+    let first = upload().await.unwrap();
+    assert_eq!(first.added, 2);
+    let second = upload().await.unwrap();
+    assert_eq!(second.document_id, first.document_id);
+    assert_eq!((second.added, second.unchanged), (0, 2));
+
+    let documents = list_documents(state.vector_store.as_ref()).await.unwrap();
+    assert_eq!(documents.len(), 1);
+    assert_eq!(documents[0].chunks, 2);
+}
@@ -0,0 +1,172 @@
+//! Round-trips documents through the knowledge base payload schema and an
+//! in-memory vector store, without the embedding model.
+
+use app_core::{
+    ingestion::{DocumentFormat, chunk_document, knowledge_points, split_into_chunks},
+    payload::{ApprovedSolution, KnowledgeChunk},
+    qdrant::{
+        APPROVED_SOLUTIONS_COLLECTION, KNOWLEDGE_BASE_COLLECTION, format_approved_context,
+        format_knowledge_context,
+    },
+    vector_store::{MemoryVectorStore, PayloadFilter, ScoredPoint, VectorPoint, VectorStore},
+};
+
+/// Stands in for the embedding model: one axis per chunk, so the nth chunk is
+/// the best match for the nth query vector.
+fn one_hot(index: usize, dims: usize) -> Vec<f32> {
+    let mut v = vec![0.0; dims];
+    v[index % dims] = 1.0;
+    v
+}
+
+#[tokio::test]
+async fn ingested_chunks_reach_the_context() {
+    let document = format!(
+        "{}\n\nTo spawn a task, call tokio::spawn with a future.",
+        "Tokio is an asynchronous runtime for Rust. ".repeat(40)
+    );
+    let chunks: Vec<KnowledgeChunk> = split_into_chunks(&document)
+        .unwrap()
+        .into_iter()
+        .enumerate()
+        .map(|(i, text)| KnowledgeChunk::new(text, "doc-1", "https://tokio.rs/guide", i))
+        .collect();
+    assert!(chunks.len() > 1, "document should span several chunks");
+
+    let dims = chunks.len() as u64;
+    let embeddings = (0..chunks.len()).map(|i| one_hot(i, chunks.len())).collect();
+    let points = knowledge_points(&chunks, embeddings).unwrap();
+
+    let store = MemoryVectorStore::new();
+    store
+        .ensure_collection(KNOWLEDGE_BASE_COLLECTION, dims)
+        .await
+        .unwrap();
+    store
+        .upsert(KNOWLEDGE_BASE_COLLECTION, points)
+        .await
+        .unwrap();
+
+    let last = chunks.len() - 1;
+    let hits = store
+        .search(KNOWLEDGE_BASE_COLLECTION, one_hot(last, chunks.len()), 1, None)
+        .await
+        .unwrap();
+
+    let stored = KnowledgeChunk::from_payload(&hits[0].payload).unwrap();
+    assert_eq!(stored, chunks[last]);
+
+    let context = format_knowledge_context(&hits);
+    assert!(context.contains("tokio::spawn"));
+    assert!(context.contains("https://tokio.rs/guide"));
+}
+
+#[tokio::test]
+async fn chunks_are_filterable_by_document_id() {
+    let store = MemoryVectorStore::new();
+    store
+        .ensure_collection(KNOWLEDGE_BASE_COLLECTION, 2)
+        .await
+        .unwrap();
+
+    let chunks = vec![
+        KnowledgeChunk::new("first", "doc-a", "a.md", 0),
+        KnowledgeChunk::new("second", "doc-b", "b.md", 0),
+    ];
+    let points = knowledge_points(&chunks, vec![one_hot(0, 2), one_hot(1, 2)]).unwrap();
+    store
+        .upsert(KNOWLEDGE_BASE_COLLECTION, points)
+        .await
+        .unwrap();
+
+    let filter = PayloadFilter::new().with_match("document_id", "doc-b");
+    let hits = store
+        .search(KNOWLEDGE_BASE_COLLECTION, one_hot(0, 2), 5, Some(filter))
+        .await
+        .unwrap();
+
+    assert_eq!(hits.len(), 1);
+    assert_eq!(format_knowledge_context(&hits), "(from b.md)\nsecond");
+}
+
+#[tokio::test]
+async fn approved_solutions_round_trip() {
+    let store = MemoryVectorStore::new();
+    store
+        .ensure_collection(APPROVED_SOLUTIONS_COLLECTION, 2)
+        .await
+        .unwrap();
+
+    let solution = ApprovedSolution {
+        query: "print hello".to_string(),
+        code: "fn main() { println!(\"hello\"); }".to_string(),
+        cargo_toml: Some("[package]\nname = \"sandbox\"".to_string()),
+        cargo_lock: None,
+    };
+    let point = VectorPoint {
+        id: "solution-1".to_string(),
+        vector: one_hot(0, 2),
+        payload: solution.to_payload().unwrap(),
+    };
+    store
+        .upsert(APPROVED_SOLUTIONS_COLLECTION, vec![point])
+        .await
+        .unwrap();
+
+    let hits = store
+        .search(APPROVED_SOLUTIONS_COLLECTION, one_hot(0, 2), 1, None)
+        .await
+        .unwrap();
+
+    assert_eq!(ApprovedSolution::from_payload(&hits[0].payload).unwrap(), solution);
+    let context = format_approved_context(&hits);
+    assert!(context.contains("print hello"));
+    assert!(context.contains("name = \"sandbox\""));
+}
+
+#[tokio::test]
+async fn rust_items_are_retrieved_whole() {
+    let source = "/// Adds two numbers.\npub fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n\npub struct Point { x: i32 }\n";
+    let chunks = chunk_document("doc-1", "src/math.rs", source, DocumentFormat::Auto).unwrap();
+    assert_eq!(chunks.len(), 2);
+
+    let store = MemoryVectorStore::new();
+    store
+        .ensure_collection(KNOWLEDGE_BASE_COLLECTION, 2)
+        .await
+        .unwrap();
+    let points = knowledge_points(&chunks, vec![one_hot(0, 2), one_hot(1, 2)]).unwrap();
+    store
+        .upsert(KNOWLEDGE_BASE_COLLECTION, points)
+        .await
+        .unwrap();
+
+    let hits = store
+        .search(KNOWLEDGE_BASE_COLLECTION, one_hot(0, 2), 1, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        format_knowledge_context(&hits),
+        "(from `math::add` in src/math.rs)\n/// Adds two numbers.\npub fn add(a: i32, b: i32) -> i32 {\n    a + b\n}"
+    );
+}
+
+#[test]
+fn markdown_sections_keep_their_breadcrumb() {
+    let readme = "# Tokio\n\nAn async runtime.\n\n## Example\n\n```rust\n#[tokio::main]\nasync fn main() {}\n```\n";
+    let chunks = chunk_document("doc-1", "README.md", readme, DocumentFormat::Auto).unwrap();
+    let hits: Vec<ScoredPoint> = knowledge_points(&chunks, vec![one_hot(0, 2), one_hot(1, 2)])
+        .unwrap()
+        .into_iter()
+        .map(|p| ScoredPoint {
+            id: p.id,
+            score: 1.0,
+            payload: p.payload,
+        })
+        .collect();
+
+    assert_eq!(
+        format_knowledge_context(&hits[1..]),
+        "(from Tokio > Example in README.md)\n## Example\n\n```rust\n#[tokio::main]\nasync fn main() {}\n```"
+    );
+}
@@ -0,0 +1,96 @@
+//! Runs `process_query` end to end offline: scripted LLM answers, fixture
+//! search results, a local crates.io index snapshot, the in-memory vector store
+//! and a hashing embedder. Only the final `cargo build` runs for real, on a
+//! program without dependencies.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use app_core::{
+    AppSettings, AppState, QueryOptions, QueryStatus, chat_backend::ScriptedBackend,
+    embedder::HashEmbedder, process_query, vector_store::MemoryVectorStore,
+};
+use serde_json::json;
+
+fn fixtures() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/pipeline")
+}
+
+async fn offline_state(scratch: &tempfile::TempDir, backend: Arc<ScriptedBackend>) -> AppState {
+    let settings: AppSettings = serde_json::from_value(json!({
+        "qdrant_url": "",
+        "llm_model": "",
+        "vector_store": "memory",
+        "max_repair_attempts": 1,
+        "search": {
+            "provider": "fixtures",
+            "fixtures_dir": fixtures().join("search"),
+        },
+        "http_cache": {
+            "mode": "off",
+            "dir": scratch.path().join("http_cache"),
+        },
+        "crate_research": {
+            "local_index_dir": fixtures().join("index"),
+            // Nothing listens on the discard port, so docs.rs pages fail fast.
+            "docs_url": "http://127.0.0.1:9",
+        },
+        "sandbox": {
+            "cache": { "dir": scratch.path().join("build_cache") },
+        },
+    }))
+    .unwrap();
+
+    AppState::from_parts(
+        settings,
+        Arc::new(MemoryVectorStore::new()),
+        backend,
+        Arc::new(HashEmbedder),
+    )
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn query_runs_offline_from_fixtures() {
+    let scratch = tempfile::tempdir().unwrap();
+    let backend = Arc::new(ScriptedBackend::from_dir(fixtures().join("llm")).unwrap());
+    let state = offline_state(&scratch, backend.clone()).await;
+
+    let result = process_query(
+        "Count the words in a string",
+        &QueryOptions::default(),
+        &state,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        result.status,
+        QueryStatus::Success,
+        "{}",
+        result.build_output
+    );
+    assert!(result.code.contains("split_whitespace"));
+    // The made-up crate is dropped before generation; the real one is kept.
+    assert_eq!(result.identified_crates, ["syn"]);
+    assert!(
+        result
+            .warnings
+            .iter()
+            .any(|w| w.contains("quick-parse-magic"))
+    );
+    assert!(
+        result
+            .sources
+            .iter()
+            .any(|s| s.starts_with("https://doc.rust-lang.org/"))
+    );
+
+    let prompts = backend.recorded_prompts();
+    assert_eq!(prompts.len(), 2, "a successful build needs no repair");
+    let generation = &prompts[1].user;
+    assert!(generation.contains("Crate 'syn' 2.0.104 (crates.io)"));
+    assert!(generation.contains("do not depend on them: quick-parse-magic"));
+    assert!(generation.contains("Use `split_whitespace`"));
+}
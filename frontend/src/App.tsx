@@ -4,17 +4,98 @@ import './App.css';
 
 const API_BASE_URL = 'http://127.0.0.1:3000';
 
+interface QueryResult {
+    status: 'success' | 'compile_failed' | 'run_failed' | 'no_code';
+    code: string;
+    dependencies: { name: string; features: string[] }[];
+    diagnostics: Diagnostic[];
+    build_output: string;
+    identified_crates: string[];
+    sources: string[];
+    warnings: string[];
+    timings: { total_ms: number };
+    run: RunOutcome | null;
+    manifest: PinnedManifest | null;
+}
+
+interface PinnedManifest {
+    dependencies: { name: string; version: string | null; features: string[] }[];
+    cargo_toml: string;
+    cargo_lock: string;
+}
+
+interface Diagnostic {
+    level: string;
+    code: string | null;
+    message: string;
+    primary_span: { file_name: string; line_start: number; column_start: number } | null;
+}
+
+interface RunOutcome {
+    exit_code: number | null;
+    stdout: string;
+    stderr: string;
+    runtime_ms: number;
+    killed: boolean;
+    truncated: boolean;
+}
+
+const formatRun = (run: RunOutcome): string => {
+    const status = run.killed ? 'killed' : `exit code ${run.exit_code}`;
+    const parts = [`Run: ${status} in ${run.runtime_ms} ms${run.truncated ? ' (output truncated)' : ''}`];
+    if (run.stdout) parts.push(`stdout:\n${run.stdout}`);
+    if (run.stderr) parts.push(`stderr:\n${run.stderr}`);
+    return parts.join('\n\n');
+};
+
+interface IngestJob {
+    id: string;
+    status: 'running' | 'completed' | 'failed' | 'cancelled';
+    progress: {
+        pages_fetched: number;
+        chunks_embedded: number;
+        batches_upserted: number;
+        errors: string[];
+    };
+    report: { document_id: string; chunks: number; added: number; moved: number; unchanged: number; removed: number } | null;
+    error: string | null;
+}
+
+// Polls an ingestion job until it finishes, reporting progress through `setStatus`.
+const followJob = async (jobId: string, setStatus: (status: string) => void): Promise<IngestJob> => {
+    for (;;) {
+        const { data: job } = await axios.get<IngestJob>(`${API_BASE_URL}/api/jobs/${jobId}`);
+        const { pages_fetched, chunks_embedded, errors } = job.progress;
+        if (job.status === 'running') {
+            setStatus(`Working... ${pages_fetched} pages fetched, ${chunks_embedded} chunks embedded`);
+            await new Promise(resolve => setTimeout(resolve, 1000));
+            continue;
+        }
+        if (job.status === 'completed' && job.report) {
+            const { added, moved, unchanged, removed } = job.report;
+            const warnings = errors.length ? `, ${errors.length} errors` : '';
+            setStatus(`Done: ${added} added, ${moved} moved, ${unchanged} unchanged, ${removed} removed${warnings}.`);
+        } else {
+            setStatus(`Ingestion ${job.status}${job.error ? `: ${job.error}` : '.'}`);
+        }
+        return job;
+    }
+};
+
 interface Message {
     id: number;
     sender: 'user' | 'ai';
     text: string;
     originalQuery?: string;
+    runOutput?: string;
+    manifest?: PinnedManifest;
 }
 
 function App() {
     const [query, setQuery] = useState('');
     const [history, setHistory] = useState<Message[]>([]);
     const [isLoading, setIsLoading] = useState(false);
+    const [execute, setExecute] = useState(false);
     const [textContent, setTextContent] = useState('');
     const [textStatus, setTextStatus] = useState('');
     const [selectedFile, setSelectedFile] = useState<File | null>(null);
@@ -28,9 +109,9 @@ function App() {
         }
         setTextStatus('Ingesting text...');
         try {
-            await axios.post(`${API_BASE_URL}/api/ingest/text`, { content: textContent });
-            setTextStatus('Successfully ingested text!');
+            const { data } = await axios.post(`${API_BASE_URL}/api/ingest/text`, { content: textContent });
             setTextContent('');
+            await followJob(data.job_id, setTextStatus);
         } catch (error) {
             console.error('Text ingestion error:', error);
             setTextStatus('Failed to ingest text.');
@@ -46,11 +127,11 @@ function App() {
         const formData = new FormData();
         formData.append('document', selectedFile);
         try {
-            await axios.post(`${API_BASE_URL}/api/ingest/file`, formData);
-            setFileStatus('Successfully ingested file!');
+            const { data } = await axios.post(`${API_BASE_URL}/api/ingest/file`, formData);
             setSelectedFile(null);
             const fileInput = document.getElementById('file-input') as HTMLInputElement;
             if (fileInput) fileInput.value = '';
+            await followJob(data.job_id, setFileStatus);
         } catch (error) {
             console.error('File ingestion error:', error);
             setFileStatus('Failed to ingest file.');
@@ -71,15 +152,19 @@ function App() {
         setHistory(prev => [...prev, userMessage]);
 
         try {
-            const response = await axios.post(`${API_BASE_URL}/api/query`, { query });
-            const aiText = response.data.response;
-            const codePart = aiText.substring(aiText.indexOf('---') + 4);
+            const response = await axios.post<QueryResult>(`${API_BASE_URL}/api/query`, { query, execute });
+            const result = response.data;
+            const text = result.status === 'success' || result.status === 'run_failed'
+                ? result.code
+                : `Failed to produce compiling code.\n\n${result.build_output}`;
 
             const aiMessage: Message = {
                 id: Date.now() + 1,
                 sender: 'ai',
-                text: codePart.trim(),
+                text: text.trim(),
                 originalQuery: query,
+                runOutput: result.run ? formatRun(result.run) : undefined,
+                manifest: result.manifest ?? undefined,
             };
             setHistory(prev => [...prev, aiMessage]);
         } catch (error) {
@@ -96,10 +181,15 @@ function App() {
         }
     };
 
-    const handleFeedback = async (originalQuery: string | undefined, code: string, upvoted: boolean) => {
-        if (!originalQuery) return;
+    const handleFeedback = async (msg: Message, upvoted: boolean) => {
+        if (!msg.originalQuery) return;
         try {
-            await axios.post(`${API_BASE_URL}/api/feedback`, { query: originalQuery, code, upvoted });
+            await axios.post(`${API_BASE_URL}/api/feedback`, {
+                query: msg.originalQuery,
+                code: msg.text,
+                upvoted,
+                manifest: msg.manifest,
+            });
             alert('Thank you for your feedback!');
         } catch (error) {
             console.error('Feedback error:', error);
@@ -114,9 +204,9 @@ function App() {
         }
         setUrlStatus('Scraping and ingesting website...');
         try {
-            await axios.post(`${API_BASE_URL}/api/ingest/url`, { url });
-            setUrlStatus('Successfully ingested website!');
+            const { data } = await axios.post(`${API_BASE_URL}/api/ingest/url`, { url });
             setUrl('');
+            await followJob(data.job_id, setUrlStatus);
         } catch (error) {
             console.error('URL ingestion error:', error);
             setUrlStatus('Failed to ingest from URL.');
@@ -170,9 +260,22 @@ function App() {
                                         <pre>
                                             <code>{msg.text}</code>
                                         </pre>
+                                        {msg.manifest && (
+                                            <details className="manifest">
+                                                <summary>Cargo.toml</summary>
+                                                <pre>
+                                                    <code>{msg.manifest.cargo_toml}</code>
+                                                </pre>
+                                            </details>
+                                        )}
+                                        {msg.runOutput && (
+                                            <pre className="run-output">
+                                                <code>{msg.runOutput}</code>
+                                            </pre>
+                                        )}
                                         <div className="feedback-buttons">
-                                            <button title="Upvote" onClick={() => handleFeedback(msg.originalQuery, msg.text, true)}>👍</button>
-                                            <button title="Downvote" onClick={() => handleFeedback(msg.originalQuery, msg.text, false)}>👎</button>
+                                            <button title="Upvote" onClick={() => handleFeedback(msg, true)}>👍</button>
+                                            <button title="Downvote" onClick={() => handleFeedback(msg, false)}>👎</button>
                                         </div>
                                     </div>
                                 ) : (
@@ -197,6 +300,15 @@ function App() {
                         disabled={isLoading}
                         rows={3}
                     />
+                    <label className="execute-toggle">
+                        <input
+                            type="checkbox"
+                            checked={execute}
+                            onChange={(e) => setExecute(e.target.checked)}
+                            disabled={isLoading}
+                        />
+                        Run program
+                    </label>
                     <button onClick={handleQuerySubmit} disabled={isLoading}>
                         Send
                     </button>
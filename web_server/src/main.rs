@@ -1,19 +1,25 @@
 #![allow(unused)]
 use std::env;
+use std::sync::Arc;
 
 use anyhow::{Context, Result, anyhow};
 use app_core::{
-    feedback::process_upvoted_solution, ingestion::ingest_document, process_query, web_scraper::scrape_website, AppSettings, AppState
+    chat_backend::GenaiBackend, crate_source::ingest_crate, feedback::process_upvoted_solution, lockfile::PinnedManifest, documents::{delete_document, document_chunks, list_documents, migrate_legacy_chunks, reingest_document, DocumentSummary}, ingestion::{ingest_document, DocumentFormat, PASTED_TEXT_SOURCE}, jobs::JobInfo, payload::KnowledgeChunk, process_query, process_query_stream, web_scraper::{scrape_website, CrawlOverrides}, AppSettings, AppState, QueryOptions, QueryResult, VectorStoreKind
 };
 use axum::{
     Json, Router,
-    extract::{DefaultBodyLimit, State},
+    extract::{DefaultBodyLimit, Path, Query, State},
     http::StatusCode,
-    response::{IntoResponse, Response},
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
     routing::{get, post},
 };
+use futures_util::{Stream, StreamExt};
 use axum_extra::extract::Multipart;
 use dotenv::dotenv;
+use genai::Client;
 use serde::{Deserialize, Serialize};
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::trace::TraceLayer;
@@ -28,11 +34,20 @@ pub mod docker_manager;
 #[derive(Deserialize)]
 struct QueryRequest {
     query: String,
+    /// Run the program after it builds.
+    #[serde(default)]
+    execute: bool,
+    #[serde(default)]
+    stdin: Option<String>,
 }
 
-#[derive(Serialize)]
-struct QueryResponse {
-    response: String,
+impl QueryRequest {
+    fn options(&self) -> QueryOptions {
+        QueryOptions {
+            execute: self.execute,
+            stdin: self.stdin.clone(),
+        }
+    }
 }
 
 // app error that wraps `anyhow::Error`.
@@ -68,11 +83,38 @@ struct IngestRequest {
 #[derive(Deserialize)]
 struct IngestTextRequest {
     content: String,
+    /// `auto`, `text`, `rust` or `markdown`.
+    #[serde(default)]
+    format: DocumentFormat,
+    /// The document this text replaces. Pasted text is otherwise identified by its content.
+    #[serde(default)]
+    document_id: Option<String>,
 }
 
 #[derive(Deserialize)]
 struct IngestUrlRequest {
     url: String,
+    /// Changes to the configured crawl limits for this crawl.
+    #[serde(default)]
+    crawl: CrawlOverrides,
+}
+
+#[derive(Deserialize, Default)]
+struct ReingestRequest {
+    /// New text for the document. URLs and crates are fetched again when omitted.
+    #[serde(default)]
+    content: Option<String>,
+    #[serde(default)]
+    format: Option<DocumentFormat>,
+}
+
+#[derive(Deserialize)]
+struct IngestCrateRequest {
+    /// Directory containing the crate's `Cargo.toml`.
+    path: String,
+    /// Rustdoc JSON to use instead of `target/doc/<crate>.json`.
+    #[serde(default)]
+    rustdoc_json: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -80,6 +122,9 @@ struct FeedbackRequest {
     query: String,
     code: String,
     upvoted: bool,
+    /// The pinned manifest returned with the query result.
+    #[serde(default)]
+    manifest: Option<PinnedManifest>,
 }
 
 
@@ -89,11 +134,14 @@ struct FeedbackRequest {
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenv().ok();
-    let docker_host = env::var("DOCKER_HOST").expect("`.env` must contain DOCKER_HOST");
-    // qdrant start
-    docker_manager::ensure_qdrant_running(docker_host)
-        .await
-        .context("Failed to ensure Qdrant container is running")?;
+    let settings = AppSettings::new().map_err(|e| anyhow!("Failed to load settings.Error: {e}"))?;
+    // qdrant start, only needed when it backs the vector store
+    if settings.vector_store == VectorStoreKind::Qdrant {
+        let docker_host = env::var("DOCKER_HOST").expect("`.env` must contain DOCKER_HOST");
+        docker_manager::ensure_qdrant_running(docker_host)
+            .await
+            .context("Failed to ensure Qdrant container is running")?;
+    }
     let gemini_key = env::var("GEMINI_API_KEY").expect("`.env` must contain GEMINI_API_KEY");
     // OPENAI KEY
     let openai_key = env::var("OPENAI_API_KEY").expect("`.env` must contain OPENAI_API_KEY");
@@ -102,12 +150,13 @@ async fn main() -> Result<()> {
     // openai model
     let openai_model = env::var("OPENAI_MODEL").unwrap_or_else(|_| "gpt.4o-mini".to_owned());
 
-    let settings = AppSettings::new().map_err(|e| anyhow!("Failed to load settings.Error: {e}"))?;
-    let mut app_state = AppState::new(settings)
+    let mut app_state = AppState::new(settings.clone())
         .await
         .context("Failed to initialize app state.")?;
     /**********************choose model ***********/
-    app_state.model = gemini_model;
+    if settings.llm_fixtures_dir.is_none() {
+        app_state.chat_backend = Arc::new(GenaiBackend::new(Client::default(), gemini_model));
+    }
 
     // Configure a permissive CORS policy for development
     let cors = CorsLayer::new()
@@ -120,12 +169,26 @@ async fn main() -> Result<()> {
         .route("/", get(root_handler))
         // `POST /api/query` goes to our new handler
         .route("/api/query", post(api_query_handler))
+        // `GET /api/query/stream?query=...` streams progress as Server-Sent Events
+        .route("/api/query/stream", get(api_query_stream_handler))
         // curl --request POST http://127.0.0.1:3000/api/shutdown to stop qdrant
         .route("/api/shutdown", post(api_shutdown_handler))
         .route("/api/ingest/file", post(api_ingest_file_handler))
         .route("/api/ingest/text", post(api_ingest_text_handler))
         .route("/api/feedback", post(api_feedback_handler))
         .route("/api/ingest/url", post(api_ingest_url_handler))
+        .route("/api/ingest/crate", post(api_ingest_crate_handler))
+        .route("/api/documents", get(api_list_documents_handler))
+        // curl --request POST http://127.0.0.1:3000/api/documents/migrate once after upgrading
+        .route("/api/documents/migrate", post(api_migrate_documents_handler))
+        .route(
+            "/api/documents/{id}",
+            get(api_document_chunks_handler).delete(api_delete_document_handler),
+        )
+        .route("/api/documents/{id}/reingest", post(api_reingest_document_handler))
+        .route("/api/jobs", get(api_list_jobs_handler))
+        .route("/api/jobs/{id}", get(api_job_handler))
+        .route("/api/jobs/{id}/cancel", post(api_cancel_job_handler))
         .with_state(app_state)
         .layer(DefaultBodyLimit::max(50 * 1024 * 1024)) // axum allows 50 MB in bytes uploads;
         .layer(cors)
@@ -145,17 +208,26 @@ async fn root_handler() -> &'static str {
 }
 
 /// The main handler for our API. It accepts a JSON payload
-/// and returns a JSON response.
+/// and returns the typed query result as JSON.
 async fn api_query_handler(
     State(state): State<AppState>,
     Json(payload): Json<QueryRequest>,
-) -> Result<Json<QueryResponse>, AppError> {
+) -> Result<Json<QueryResult>, AppError> {
     // Call processing function from core library
-    let response_string = process_query(&payload.query, &state).await?;
-    let response = QueryResponse {
-        response: format!("Received your query: '{}'", response_string),
-    };
-    Ok(Json(response))
+    let result = process_query(&payload.query, &payload.options(), &state).await?;
+    Ok(Json(result))
+}
+
+/// Streams the progress of a query as Server-Sent Events. Uses GET so that
+/// browsers can consume it with `EventSource`.
+async fn api_query_stream_handler(
+    State(state): State<AppState>,
+    Query(payload): Query<QueryRequest>,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    let options = payload.options();
+    let stream = process_query_stream(payload.query, options, state)
+        .map(|event| Event::default().event(event.name()).json_data(&event));
+    Sse::new(stream).keep_alive(KeepAlive::default())
 }
 
 /// Handler for stopping and removing the Qdrant container
@@ -186,7 +258,7 @@ async fn api_feedback_handler(
 ) -> Result<StatusCode, AppError> {
     if payload.upvoted {
         // Pass a reference to the state
-        process_upvoted_solution(&state, payload.query, payload.code).await?;
+        process_upvoted_solution(&state, payload.query, payload.code, payload.manifest).await?;
     }
     // For now, we do nothing on a downvote
     Ok(StatusCode::OK)
@@ -196,8 +268,10 @@ async fn api_feedback_handler(
 async fn api_ingest_file_handler(
     State(state): State<AppState>,
     mut multipart: Multipart,
-) -> Result<StatusCode, AppError> {
+) -> Result<(StatusCode, Json<JobStarted>), AppError> {
     let mut document_content = String::new();
+    let mut file_name = String::from("uploaded file");
+    let mut document_id = None;
 
     // Explicitly handle multipart errors to provide a better response than a generic 500.
     while let Some(field) = multipart.next_field().await.map_err(|err| {
@@ -206,9 +280,21 @@ async fn api_ingest_file_handler(
             err
         ))
     })? {
+        // An optional "document_id" field names the document the upload replaces.
+        if field.name() == Some("document_id") {
+            let id = field.text().await.map_err(|err| {
+                AppError(anyhow::anyhow!("Failed to read the document_id field: {}", err))
+            })?;
+            document_id = Some(id.trim().to_string()).filter(|id| !id.is_empty());
+            continue;
+        }
+
         // Look for the specific field named "document".
         if field.name() == Some("document") {
             let content_type = field.content_type().unwrap_or("text/plain").to_string();
+            if let Some(name) = field.file_name() {
+                file_name = name.to_string();
+            }
 
             // Read the raw bytes of the field first.
             let bytes = field.bytes().await.map_err(|err| {
@@ -231,8 +317,6 @@ async fn api_ingest_file_handler(
                     ))
                 })?;
             }
-            // Once we've found and processed the 'document' field, we can stop looking.
-            break;
         }
     }
 
@@ -243,8 +327,13 @@ async fn api_ingest_file_handler(
     }
 
     // Pass the extracted text content to our core ingestion logic.
-    ingest_document(state.clone(), document_content).await?;
-    Ok(StatusCode::OK)
+    let job_id = state.ingest_jobs.spawn(format!("file {}", file_name), {
+        let state = state.clone();
+        move |progress| async move {
+            ingest_document(state, file_name, document_content, DocumentFormat::Auto, document_id, &progress).await
+        }
+    });
+    Ok(job_started(job_id))
 }
 
 // New handler with extensive logging for debugging
@@ -307,22 +396,156 @@ async fn api_ingest_file_handler(
 async fn api_ingest_text_handler(
     State(state): State<AppState>,
     Json(payload): Json<IngestTextRequest>,
-) -> Result<StatusCode, AppError> {
-    ingest_document(state.clone(), payload.content).await?;
-    Ok(StatusCode::OK)
+) -> Result<(StatusCode, Json<JobStarted>), AppError> {
+    let job_id = state.ingest_jobs.spawn(PASTED_TEXT_SOURCE, {
+        let state = state.clone();
+        move |progress| async move {
+            ingest_document(state, PASTED_TEXT_SOURCE.to_string(), payload.content, payload.format, payload.document_id, &progress).await
+        }
+    });
+    Ok(job_started(job_id))
 }
 
 async fn api_ingest_url_handler(
     State(state): State<AppState>,
     Json(payload): Json<IngestUrlRequest>,
-) -> Result<StatusCode, AppError> {
+) -> Result<(StatusCode, Json<JobStarted>), AppError> {
     println!("Received request to ingest URL: {}", payload.url);
-    
-    // Scrape all text content from the website
-    let document_content = scrape_website(&payload.url).await?;
 
-    // Use the existing ingestion logic to process the scraped content
-    ingest_document(state.clone(), document_content).await?;
-    
-    Ok(StatusCode::OK)
+    let job_id = state.ingest_jobs.spawn(format!("url {}", payload.url), {
+        let state = state.clone();
+        move |progress| async move {
+            // Crawl the website, collecting each page's main content as Markdown
+            let settings = state.crawl_settings.with_overrides(&payload.crawl);
+            let document_content =
+                scrape_website(&state, &payload.url, &settings, &progress).await?;
+
+            // Chunk it by heading, keeping code blocks whole
+            ingest_document(state, payload.url, document_content, DocumentFormat::Markdown, None, &progress).await
+        }
+    });
+    Ok(job_started(job_id))
+}
+
+/// Handler for indexing the public API of a crate checked out on the server.
+async fn api_ingest_crate_handler(
+    State(state): State<AppState>,
+    Json(payload): Json<IngestCrateRequest>,
+) -> Result<(StatusCode, Json<JobStarted>), AppError> {
+    println!("Received request to ingest crate at: {}", payload.path);
+    let job_id = state.ingest_jobs.spawn(format!("crate {}", payload.path), {
+        let state = state.clone();
+        move |progress| async move {
+            let summary = ingest_crate(
+                &state,
+                std::path::Path::new(&payload.path),
+                payload.rustdoc_json.map(std::path::PathBuf::from),
+                None,
+                &progress,
+            )
+            .await?;
+            Ok(summary.ingest)
+        }
+    });
+    Ok(job_started(job_id))
+}
+
+/// Lists everything in the knowledge base, one entry per ingested document.
+async fn api_list_documents_handler(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<DocumentSummary>>, AppError> {
+    let documents = list_documents(state.vector_store.as_ref()).await?;
+    Ok(Json(documents))
+}
+
+/// Gives chunks stored before documents had ids a document id.
+async fn api_migrate_documents_handler(
+    State(state): State<AppState>,
+) -> Result<Json<MigrationResult>, AppError> {
+    let migrated = migrate_legacy_chunks(state.vector_store.as_ref()).await?;
+    Ok(Json(MigrationResult { migrated }))
+}
+
+#[derive(Serialize)]
+struct MigrationResult {
+    migrated: usize,
+}
+
+/// Returns the chunks of one document, in order.
+async fn api_document_chunks_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Response, AppError> {
+    let chunks: Vec<KnowledgeChunk> = document_chunks(state.vector_store.as_ref(), &id).await?;
+    if chunks.is_empty() {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+    Ok(Json(chunks).into_response())
+}
+
+/// Removes a document and all of its chunks from the knowledge base.
+async fn api_delete_document_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    let removed = delete_document(state.vector_store.as_ref(), &id).await?;
+    println!("Deleted document {} ({} chunks)", id, removed);
+    Ok(if removed == 0 {
+        StatusCode::NOT_FOUND
+    } else {
+        StatusCode::NO_CONTENT
+    })
+}
+
+/// Ingests a document again under the same id, from new content or its original source.
+async fn api_reingest_document_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    payload: Option<Json<ReingestRequest>>,
+) -> Result<Response, AppError> {
+    let payload = payload.map(|Json(p)| p).unwrap_or_default();
+    if document_chunks(state.vector_store.as_ref(), &id).await?.is_empty() {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+    let job_id = state.ingest_jobs.spawn(format!("re-ingest {}", id), {
+        let state = state.clone();
+        move |progress| async move {
+            reingest_document(&state, &id, payload.content, payload.format, &progress)
+                .await?
+                .ok_or_else(|| anyhow!("Document {} no longer exists", id))
+        }
+    });
+    Ok(job_started(job_id).into_response())
+}
+
+#[derive(Serialize)]
+struct JobStarted {
+    job_id: String,
+}
+
+/// The response of endpoints that start an ingestion job; poll `/api/jobs/{id}` for progress.
+fn job_started(job_id: String) -> (StatusCode, Json<JobStarted>) {
+    (StatusCode::ACCEPTED, Json(JobStarted { job_id }))
+}
+
+/// Lists ingestion jobs, newest first.
+async fn api_list_jobs_handler(State(state): State<AppState>) -> Json<Vec<JobInfo>> {
+    Json(state.ingest_jobs.list())
+}
+
+/// Progress and outcome of one ingestion job.
+async fn api_job_handler(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    match state.ingest_jobs.get(&id) {
+        Some(job) => Json(job).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Asks a running job to stop after its current page or batch.
+async fn api_cancel_job_handler(State(state): State<AppState>, Path(id): Path<String>) -> StatusCode {
+    match state.ingest_jobs.cancel(&id) {
+        Some(true) => StatusCode::ACCEPTED,
+        Some(false) => StatusCode::CONFLICT,
+        None => StatusCode::NOT_FOUND,
+    }
 }